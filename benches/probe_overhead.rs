@@ -0,0 +1,199 @@
+//! Measures what the exporter adds around each operation it times, which has
+//! to stay well under the latency of a fast device to not show up in the
+//! measurements:
+//!
+//! * recording the latency, the CPU usage of the probe, and the sample for
+//!   the dashboard;
+//! * the whole probe loop, picking the offsets and running the rounds, minus
+//!   the time spent reading (as measured by the probes).
+//!
+//! Each is measured `RUNS` times, and the bench fails if the median takes more
+//! than `MAX_OVERHEAD` (or `MAX_ROUND` for the probe loop), or if the
+//! recording allocates. Both limits are about twice what a laptop takes, so
+//! that only regressions fail:
+//!
+//!     cargo bench --bench probe_overhead
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::process::exit;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use fs_latency::config::Config;
+use fs_latency::control::Control;
+use fs_latency::metrics::Metrics;
+use fs_latency::process::ThreadUsage;
+use fs_latency::status::Status;
+use fs_latency::target::Target;
+
+const MAX_OVERHEAD: Duration = Duration::from_micros(10);
+
+const MAX_ROUND: Duration = Duration::from_micros(20);
+
+const ITERATIONS: u32 = 100_000;
+
+const ROUNDS: u32 = 10_000;
+
+const RUNS: usize = 7;
+
+/// Size of the file read by the probe loop.
+const FILE_SIZE: u64 = 64 << 20;
+
+/// Counts the allocations, to check that there are none.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn median(mut runs: Vec<Duration>) -> Duration {
+    runs.sort();
+    runs[runs.len() / 2]
+}
+
+/// Time what is recorded after each operation, returning the median time per
+/// operation and the number of allocations.
+fn recording() -> (Duration, usize) {
+    let target = "/data/file";
+    let metrics = Metrics::new("fs_latency_", false, false, &HashMap::new());
+    metrics.add_target(target, None);
+    metrics.set_thresholds(target, &[Duration::from_millis(1)]);
+    metrics.set_slo(target, Duration::from_millis(1), 0.99);
+    let status = Status::new(vec![target.to_owned()], Vec::new(), 300);
+
+    // What a read probe does besides reading
+    let round = |i: u32| {
+        let usage = ThreadUsage::now();
+        let duration = 0.0001 + (i % 100) as f64 * 0.000001;
+        let mut status = status.lock().unwrap();
+        status.record_sample(target, i as u64 * 4096, duration);
+        metrics.observe("read", target, None, duration);
+        metrics.probe_usage("read", target, &usage, &ThreadUsage::now());
+        metrics.set_success(target, true);
+        status.record_round(target, false);
+    };
+
+    // Fill the windows and create the series first
+    for i in 0..1000 {
+        round(i);
+    }
+
+    let mut runs = Vec::with_capacity(RUNS);
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        for i in 0..ITERATIONS {
+            round(i);
+        }
+        runs.push(start.elapsed() / ITERATIONS);
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    (median(runs), allocations)
+}
+
+/// The total time the read probes measured.
+fn read_time(metrics: &Metrics) -> Duration {
+    let families = metrics.registry().gather();
+    let family = families
+        .iter()
+        .find(|f| f.get_name() == "fs_latency_seconds")
+        .unwrap();
+    let seconds = family
+        .get_metric()
+        .iter()
+        .filter(|m| {
+            m.get_label()
+                .iter()
+                .any(|l| l.get_name() == "operation" && l.get_value() == "read")
+        })
+        .map(|m| m.get_histogram().get_sample_sum())
+        .sum();
+    Duration::from_secs_f64(seconds)
+}
+
+/// Time rounds of read probes on a file, without the reads themselves,
+/// returning the median time per round.
+fn probe_loop() -> Duration {
+    let path = std::env::temp_dir().join(format!("probe_overhead-{}.dat", std::process::id()));
+    File::create(&path).unwrap().set_len(FILE_SIZE).unwrap();
+    let config = Config::parse([
+        OsString::from("--ops"),
+        OsString::from("read"),
+        path.clone().into(),
+    ]);
+    let target = config.targets[0].clone();
+    let metrics = Arc::new(Metrics::new("fs_latency_", false, false, &HashMap::new()));
+    metrics.add_target(&target.name, None);
+    let status = Status::new(vec![target.name.clone()], Vec::new(), 300);
+    let mut target = match Target::open(
+        target,
+        config.regions,
+        metrics.clone(),
+        status,
+        None,
+        Arc::new(Control::default()),
+    ) {
+        Ok(t) => t,
+        Err(e) => {
+            std::fs::remove_file(&path).unwrap();
+            eprintln!("Can't open {:?}: {}", path, e);
+            exit(1);
+        }
+    };
+
+    // Fill the windows and create the series first
+    target.run_rounds(1000);
+
+    let mut runs = Vec::with_capacity(RUNS);
+    for _ in 0..RUNS {
+        let reading = read_time(&metrics);
+        let start = Instant::now();
+        target.run_rounds(ROUNDS);
+        let elapsed = start.elapsed();
+        let reading = read_time(&metrics) - reading;
+        runs.push(elapsed.saturating_sub(reading) / ROUNDS);
+    }
+    std::fs::remove_file(&path).unwrap();
+    median(runs)
+}
+
+fn main() {
+    let (overhead, allocations) = recording();
+    println!(
+        "recording: {:?} per operation, {} allocations in {} operations",
+        overhead,
+        allocations,
+        ITERATIONS * RUNS as u32
+    );
+    let round = probe_loop();
+    println!("probe loop: {:?} per round, besides reading", round);
+
+    if overhead > MAX_OVERHEAD || round > MAX_ROUND || allocations > 0 {
+        eprintln!(
+            "The overhead should be under {:?} ({:?} for a round), without allocating",
+            MAX_OVERHEAD, MAX_ROUND
+        );
+        exit(1);
+    }
+}
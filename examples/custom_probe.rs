@@ -0,0 +1,46 @@
+//! An exporter with an additional operation, `header`, which reads the first
+//! bytes of the target (e.g. to check the magic number of a container format):
+//!
+//!     cargo run --example custom_probe -- --ops read,header /data/file
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use fs_latency::config::TargetConfig;
+use fs_latency::metrics::Metrics;
+use fs_latency::{exporter, prober, ProbeResult, Prober};
+
+struct HeaderProbe {
+    path: PathBuf,
+}
+
+impl Prober for HeaderProbe {
+    fn probe(&mut self, operation: &str, metrics: &Metrics, target: &str) -> ProbeResult {
+        let start = Instant::now();
+        let mut header = [0; 16];
+        let result = File::open(&self.path).and_then(|mut f| f.read_exact(&mut header));
+        match result {
+            Ok(()) => {
+                metrics.observe(operation, target, None, start.elapsed().as_secs_f64());
+                Ok(())
+            }
+            Err(e) => {
+                metrics.error(operation, target);
+                Err(format!("Can't read header of {:?}: {}", self.path, e))
+            }
+        }
+    }
+}
+
+fn header_probe(path: &Path, _config: &TargetConfig) -> Result<Box<dyn Prober>, String> {
+    Ok(Box::new(HeaderProbe {
+        path: path.to_owned(),
+    }))
+}
+
+fn main() {
+    prober::register("header", header_probe);
+    exporter::run()
+}
@@ -0,0 +1,478 @@
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::Duration;
+
+use crate::config::{parse_duration, Config, TargetConfig};
+use crate::metrics::{Metrics, Recorded};
+use crate::status::quantile;
+
+const USAGE: &str = "\
+Usage: fs-latency-exporter accept [accept options] [options] [FILENAME...]
+Runs the probes on the targets for a while (like --bench), then checks the
+measurements against pass/fail criteria, prints a report in JSON, and exits
+with status 0 if all the criteria passed, 1 otherwise. This can be used to
+accept new volumes in CI.
+Accept options:
+    --duration DURATION
+        How long to run the probes (default: 1m)
+    --require OPERATION:STATISTIC<=VALUE
+        Fail if the STATISTIC of the latency of OPERATION, for any target,
+        is more than the duration VALUE. STATISTIC is mean, max, or a
+        percentile like p50, p99 or p99.9. `OPERATION:errors<=COUNT` and
+        `OPERATION:iops>=RATE` can also be used, and the comparison can be
+        either <= or >= (can be repeated)
+    --criteria FILE
+        Read criteria from FILE, one per line, with comments starting with
+        `#`
+    --junit FILE
+        Also write the report to FILE in JUnit's XML format, which most CI
+        systems display
+Other options are the ones of the exporter (see --help). If --scratch-dir is
+set and --ops isn't, the probes are read, write (which measures write, fsync
+and verify), create, chmod and link. Without any criteria, all the
+operations have to succeed without errors.";
+
+/// The probes run on targets with a scratch dir and no `--ops`.
+const BATTERY: &[&str] = &["read", "write", "create", "chmod", "link"];
+
+/// The statistics reported for each operation.
+const PERCENTILES: &[(&str, f64)] = &[("p50", 0.5), ("p90", 0.9), ("p99", 0.99), ("p99.9", 0.999)];
+
+#[derive(Clone, Copy)]
+enum Statistic {
+    Mean,
+    Max,
+    Quantile(f64),
+    Errors,
+    Iops,
+}
+
+/// A pass/fail criterion, like `read:p99<=10ms`.
+struct Criterion {
+    text: String,
+    operation: String,
+    statistic: Statistic,
+    at_most: bool,
+    bound: f64,
+}
+
+impl Criterion {
+    fn parse(value: &str) -> Result<Criterion, String> {
+        let invalid = || format!("Invalid criterion {:?}", value);
+        let (operation, rest) = value.split_once(':').ok_or_else(invalid)?;
+        let (statistic, at_most, bound) = if let Some((s, b)) = rest.split_once("<=") {
+            (s, true, b)
+        } else if let Some((s, b)) = rest.split_once(">=") {
+            (s, false, b)
+        } else {
+            return Err(invalid());
+        };
+        let statistic = match statistic.trim() {
+            "mean" => Statistic::Mean,
+            "max" => Statistic::Max,
+            "errors" => Statistic::Errors,
+            "iops" => Statistic::Iops,
+            s => {
+                let percentile = s
+                    .strip_prefix('p')
+                    .and_then(|p| p.parse::<f64>().ok())
+                    .filter(|&p| p > 0.0 && p <= 100.0)
+                    .ok_or_else(invalid)?;
+                Statistic::Quantile(percentile / 100.0)
+            }
+        };
+        let bound = match statistic {
+            Statistic::Errors | Statistic::Iops => {
+                bound.trim().parse::<f64>().map_err(|_| invalid())?
+            }
+            _ => parse_duration(bound.trim())
+                .map_err(|_| invalid())?
+                .as_secs_f64(),
+        };
+        Ok(Criterion {
+            text: value.trim().to_owned(),
+            operation: operation.trim().to_owned(),
+            statistic,
+            at_most,
+            bound,
+        })
+    }
+
+    /// Format a value of the statistic, for messages.
+    fn format(&self, value: f64) -> String {
+        match self.statistic {
+            Statistic::Errors => format!("{}", value),
+            Statistic::Iops => format!("{:.1}", value),
+            _ => format!("{:.3}ms", value * 1000.0),
+        }
+    }
+}
+
+/// The measurements of one operation of a target.
+struct Measured {
+    sorted: Vec<f64>,
+    errors: u64,
+    iops: f64,
+}
+
+impl Measured {
+    fn get(&self, statistic: Statistic) -> Option<f64> {
+        match statistic {
+            Statistic::Errors => Some(self.errors as f64),
+            Statistic::Iops => Some(self.iops),
+            Statistic::Mean if self.sorted.is_empty() => None,
+            Statistic::Mean => Some(self.sorted.iter().sum::<f64>() / self.sorted.len() as f64),
+            Statistic::Max => self.sorted.last().copied(),
+            Statistic::Quantile(q) => quantile(&self.sorted, q),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let mut stats = json!({
+            "count": self.sorted.len(),
+            "errors": self.errors,
+            "iops": self.iops,
+            "mean": self.get(Statistic::Mean),
+            "max": self.get(Statistic::Max),
+        });
+        for &(name, q) in PERCENTILES {
+            stats[name] = quantile(&self.sorted, q).into();
+        }
+        stats
+    }
+}
+
+/// The outcome of a criterion for a target.
+struct Check<'a> {
+    criterion: &'a Criterion,
+    value: Option<f64>,
+    failure: Option<String>,
+}
+
+/// The parsed `accept` options.
+pub struct Accept {
+    duration: Duration,
+    criteria: Vec<Criterion>,
+    junit: Option<PathBuf>,
+}
+
+impl Accept {
+    /// Parse the arguments of the `accept` subcommand, and the exporter's
+    /// options after them, exiting on error.
+    pub fn from_args(args: Vec<OsString>) -> (Config, Accept) {
+        let mut accept = Accept {
+            duration: Duration::from_secs(60),
+            criteria: Vec::new(),
+            junit: None,
+        };
+        let fail = |message: String| -> ! {
+            eprintln!("{}", message);
+            exit(2);
+        };
+        let mut rest: Vec<OsString> = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let name = match arg.to_str() {
+                Some("--help") => {
+                    println!("{}", USAGE);
+                    exit(0);
+                }
+                Some(name @ ("--duration" | "--require" | "--criteria" | "--junit")) => {
+                    name.to_owned()
+                }
+                _ => {
+                    rest.push(arg);
+                    continue;
+                }
+            };
+            let value = match args.next() {
+                Some(v) => v,
+                None => fail(format!("Missing value for {}", name)),
+            };
+            if name == "--criteria" || name == "--junit" {
+                let path = PathBuf::from(value);
+                if name == "--junit" {
+                    accept.junit = Some(path);
+                } else if let Err(e) = accept.load_criteria(&path) {
+                    fail(e);
+                }
+                continue;
+            }
+            let value = match value.into_string() {
+                Ok(v) => v,
+                Err(_) => fail(format!("Invalid value for {}", name)),
+            };
+            match name.as_str() {
+                "--duration" => match parse_duration(&value) {
+                    Ok(d) if !d.is_zero() => accept.duration = d,
+                    _ => fail(format!("Invalid value for {}", name)),
+                },
+                _ => match Criterion::parse(&value) {
+                    Ok(c) => accept.criteria.push(c),
+                    Err(e) => fail(e),
+                },
+            }
+        }
+
+        let mut config = Config::parse(rest);
+        if config.bench.is_some() {
+            fail("Use --duration rather than --bench with accept".to_owned());
+        }
+        // Default to the whole battery where there is somewhere to write
+        for target in &mut config.targets {
+            if target.default_ops && target.scratch_dir.is_some() {
+                target.ops = BATTERY.iter().map(|&o| (o.to_owned(), None)).collect();
+            }
+        }
+        config.bench = Some(accept.duration);
+        (config, accept)
+    }
+
+    fn load_criteria(&mut self, filename: &Path) -> Result<(), String> {
+        let content = std::fs::read_to_string(filename)
+            .map_err(|e| format!("Can't read {:?}: {}", filename, e))?;
+        for (number, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let criterion = Criterion::parse(line)
+                .map_err(|e| format!("{} on line {} of {:?}", e, number + 1, filename))?;
+            self.criteria.push(criterion);
+        }
+        Ok(())
+    }
+
+    /// Check the measurements against the criteria, print the report, and
+    /// exit with the result.
+    pub fn finish(
+        &self,
+        targets: &[TargetConfig],
+        recorded: &Recorded,
+        metrics: &Metrics,
+        runtime: Duration,
+    ) -> ! {
+        // Without criteria, every operation has to succeed
+        let defaults: Vec<Criterion>;
+        let criteria = if self.criteria.is_empty() {
+            let operations: BTreeSet<&str> = targets
+                .iter()
+                .flat_map(|t| t.ops.iter().map(|(o, _)| o.as_str()))
+                .chain(recorded.keys().map(|(_, o)| o.as_str()))
+                .collect();
+            defaults = operations
+                .into_iter()
+                .map(|o| Criterion::parse(&format!("{}:errors<=0", o)).unwrap())
+                .collect();
+            &defaults
+        } else {
+            &self.criteria
+        };
+
+        // Criteria apply to the targets which measured their operation, and
+        // fail if none did
+        let measured_anywhere = |operation: &str| {
+            recorded.keys().any(|(_, o)| o == operation)
+                || targets
+                    .iter()
+                    .any(|t| metrics.error_count(operation, &t.name) > 0)
+        };
+
+        let mut passed = true;
+        let mut results = Vec::new();
+        for target in targets {
+            let mut operations: BTreeSet<&str> = recorded
+                .keys()
+                .filter(|(t, _)| t == &target.name)
+                .map(|(_, o)| o.as_str())
+                .collect();
+            operations.extend(target.ops.iter().map(|(o, _)| o.as_str()));
+            operations.extend(criteria.iter().map(|c| c.operation.as_str()));
+            let measured = |operation: &str| {
+                let mut sorted = recorded
+                    .get(&(target.name.clone(), operation.to_owned()))
+                    .cloned()
+                    .unwrap_or_default();
+                sorted.sort_by(f64::total_cmp);
+                Measured {
+                    iops: sorted.len() as f64 / runtime.as_secs_f64(),
+                    errors: metrics.error_count(operation, &target.name),
+                    sorted,
+                }
+            };
+
+            let mut stats = serde_json::Map::new();
+            let mut checks = Vec::new();
+            for operation in operations {
+                let measured = measured(operation);
+                if measured.sorted.is_empty() && measured.errors == 0 {
+                    if !measured_anywhere(operation) {
+                        for criterion in criteria.iter().filter(|c| c.operation == operation) {
+                            checks.push(Check {
+                                criterion,
+                                value: None,
+                                failure: Some(format!("{} was never measured", operation)),
+                            });
+                        }
+                    }
+                    continue;
+                }
+                for criterion in criteria.iter().filter(|c| c.operation == operation) {
+                    let value = measured.get(criterion.statistic);
+                    let failure = match value {
+                        None => Some(format!("{} was never measured", operation)),
+                        Some(v) if criterion.at_most && v > criterion.bound => Some(format!(
+                            "{} is {}, more than {}",
+                            criterion.text,
+                            criterion.format(v),
+                            criterion.format(criterion.bound)
+                        )),
+                        Some(v) if !criterion.at_most && v < criterion.bound => Some(format!(
+                            "{} is {}, less than {}",
+                            criterion.text,
+                            criterion.format(v),
+                            criterion.format(criterion.bound)
+                        )),
+                        Some(_) => None,
+                    };
+                    checks.push(Check {
+                        criterion,
+                        value,
+                        failure,
+                    });
+                }
+                stats.insert(operation.to_owned(), measured.to_json());
+            }
+            passed &= checks.iter().all(|c| c.failure.is_none());
+            results.push((target, stats, checks));
+        }
+
+        let report = json!({
+            "passed": passed,
+            "duration": runtime.as_secs_f64(),
+            "targets": results
+                .iter()
+                .map(|(target, stats, checks)| json!({
+                    "target": target.name,
+                    "path": target.path.to_string_lossy(),
+                    "passed": checks.iter().all(|c| c.failure.is_none()),
+                    "operations": stats,
+                    "checks": checks
+                        .iter()
+                        .map(|c| json!({
+                            "criterion": c.criterion.text,
+                            "value": c.value,
+                            "passed": c.failure.is_none(),
+                            "failure": c.failure,
+                        }))
+                        .collect::<Vec<_>>(),
+                }))
+                .collect::<Vec<_>>(),
+        });
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+
+        if let Some(path) = &self.junit {
+            if let Err(e) = std::fs::write(path, junit(&results, runtime)) {
+                eprintln!("Can't write {:?}: {}", path, e);
+                exit(2);
+            }
+        }
+        exit(if passed { 0 } else { 1 });
+    }
+}
+
+/// The report in the JUnit XML format: a test suite per target, with a test
+/// case per criterion.
+fn junit(
+    results: &[(&TargetConfig, serde_json::Map<String, Value>, Vec<Check>)],
+    runtime: Duration,
+) -> String {
+    let count = |checks: &[Check]| checks.iter().filter(|c| c.failure.is_some()).count();
+    let tests: usize = results.iter().map(|(_, _, c)| c.len()).sum();
+    let failures: usize = results.iter().map(|(_, _, c)| count(c)).sum();
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <testsuites name=\"fs-latency-exporter accept\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n",
+        tests,
+        failures,
+        runtime.as_secs_f64()
+    );
+    for (target, _, checks) in results {
+        xml.push_str(&format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n",
+            escape(&target.name),
+            checks.len(),
+            count(checks),
+            runtime.as_secs_f64()
+        ));
+        for check in checks {
+            let name = format!(
+                "  <testcase classname=\"{}\" name=\"{}\"",
+                escape(&target.name),
+                escape(&check.criterion.text)
+            );
+            match &check.failure {
+                Some(failure) => xml.push_str(&format!(
+                    "  {}>\n      <failure message=\"{}\"/>\n    </testcase>\n",
+                    name,
+                    escape(failure)
+                )),
+                None => xml.push_str(&format!("  {}/>\n", name)),
+            }
+        }
+        xml.push_str("  </testsuite>\n");
+    }
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Criterion, Statistic};
+
+    #[test]
+    fn criteria() {
+        let c = Criterion::parse(" read : p99.9 <= 10ms ").unwrap();
+        assert_eq!(c.operation, "read");
+        assert!(matches!(c.statistic, Statistic::Quantile(q) if (q - 0.999).abs() < 1e-12));
+        assert!(c.at_most);
+        assert_eq!(c.bound, 0.01);
+        assert_eq!(c.text, "read : p99.9 <= 10ms");
+
+        let c = Criterion::parse("write:iops>=50").unwrap();
+        assert!(matches!(c.statistic, Statistic::Iops));
+        assert!(!c.at_most);
+        assert_eq!(c.bound, 50.0);
+
+        let c = Criterion::parse("fsync:errors<=0").unwrap();
+        assert!(matches!(c.statistic, Statistic::Errors));
+        let c = Criterion::parse("read:mean<=0.002").unwrap();
+        assert!(matches!(c.statistic, Statistic::Mean));
+        assert_eq!(c.bound, 0.002);
+    }
+
+    #[test]
+    fn invalid_criteria() {
+        for value in [
+            "p99<=10ms",
+            "read:p99<10ms",
+            "read:p0<=10ms",
+            "read:p101<=10ms",
+            "read:median<=10ms",
+            "read:p99<=soon",
+            "read:errors<=some",
+        ] {
+            assert!(Criterion::parse(value).is_err(), "{}", value);
+        }
+    }
+}
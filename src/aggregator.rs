@@ -0,0 +1,230 @@
+use prometheus::GaugeVec;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+use crate::metrics::Metrics;
+
+/// Forget about agents which haven't pushed in that long.
+const ORIGIN_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Whether a line of the text format is a HELP or TYPE comment, the only ones
+/// we keep.
+fn is_header(line: &str) -> bool {
+    line.starts_with("# HELP ") || line.starts_with("# TYPE ")
+}
+
+/// A metric family in the text format: its HELP and TYPE lines, and samples.
+struct Family {
+    name: String,
+    header: Vec<String>,
+    samples: Vec<String>,
+}
+
+/// Collects the metrics pushed by agents, to expose them with our own.
+pub struct Aggregator {
+    /// Last metrics pushed by each origin, in the text format
+    origins: Mutex<HashMap<String, (Instant, String)>>,
+    last_push: GaugeVec,
+}
+
+/// Escape a label value for the text format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Add the `origin` label to a sample line.
+fn add_origin(line: &str, origin: &str) -> String {
+    let label = format!("origin=\"{}\"", escape(origin));
+    match line.find(['{', ' ']) {
+        Some(i) if line.as_bytes()[i] == b'{' => {
+            format!("{}{}{},{}", &line[..i], '{', label, &line[i + 1..])
+        }
+        Some(i) => format!("{}{{{}}}{}", &line[..i], label, &line[i..]),
+        None => line.to_owned(),
+    }
+}
+
+/// Add the lines of a metrics text to the families, grouping the samples of a
+/// family from all the origins together as the format requires.
+fn merge(families: &mut Vec<Family>, text: &str, origin: Option<&str>) {
+    let mut current: Option<usize> = None;
+    for line in text.lines() {
+        if line.is_empty() || (line.starts_with('#') && !is_header(line)) {
+            continue;
+        }
+        let name = match line.strip_prefix("# ") {
+            Some(comment) => {
+                let mut words = comment.splitn(3, ' ');
+                match (words.next(), words.next()) {
+                    (Some("HELP" | "TYPE"), Some(name)) => name,
+                    _ => continue,
+                }
+            }
+            None => match current {
+                Some(_) => "",
+                None => line.split(['{', ' ']).next().unwrap(),
+            },
+        };
+        if !name.is_empty() {
+            let index = match families.iter().position(|f| f.name == name) {
+                Some(index) => index,
+                None => {
+                    families.push(Family {
+                        name: name.to_owned(),
+                        header: Vec::new(),
+                        samples: Vec::new(),
+                    });
+                    families.len() - 1
+                }
+            };
+            current = Some(index);
+        }
+        let family = &mut families[current.unwrap()];
+        if line.starts_with('#') {
+            // Only keep the HELP and TYPE lines from the first origin
+            let kind = line.get(..6).unwrap_or(line);
+            if !family.header.iter().any(|h| h.starts_with(kind)) {
+                family.header.push(line.to_owned());
+            }
+        } else {
+            family.samples.push(match origin {
+                Some(origin) => add_origin(line, origin),
+                None => line.to_owned(),
+            });
+        }
+    }
+}
+
+impl Aggregator {
+    pub fn new(metrics: &Metrics) -> Aggregator {
+        let last_push = GaugeVec::new(
+            metrics.opts(
+                "aggregator_last_push_timestamp_seconds",
+                "When the agent last pushed its metrics",
+            ),
+            &["origin"],
+        )
+        .unwrap();
+        metrics.register(&last_push);
+        Aggregator {
+            origins: Mutex::new(HashMap::new()),
+            last_push,
+        }
+    }
+
+    /// Store the metrics pushed by an agent.
+    pub fn push(&self, origin: &str, text: String) -> Result<(), String> {
+        if origin.is_empty() {
+            return Err("Missing origin".to_owned());
+        }
+        if let Some(line) = text.lines().find(|l| {
+            if l.starts_with('#') {
+                !is_header(l)
+            } else {
+                !l.is_empty() && !l.contains(' ')
+            }
+        }) {
+            return Err(format!("Invalid line: {}", line));
+        }
+        let mut origins = self.origins.lock().unwrap();
+        if !origins.contains_key(origin) {
+            info!("Receiving metrics from {}", origin);
+        }
+        origins.insert(origin.to_owned(), (Instant::now(), text));
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        self.last_push
+            .with_label_values(&[origin])
+            .set(now.as_secs_f64());
+        Ok(())
+    }
+
+    /// Render our own metrics and the ones pushed by the agents.
+    pub fn render(&self, local: &str) -> String {
+        let mut origins = self.origins.lock().unwrap();
+        origins.retain(|origin, (time, _)| {
+            let fresh = time.elapsed() < ORIGIN_TIMEOUT;
+            if !fresh {
+                info!("No metrics from {} in a while, dropping it", origin);
+                let _ = self.last_push.remove_label_values(&[origin]);
+            }
+            fresh
+        });
+
+        let mut families = Vec::new();
+        merge(&mut families, local, None);
+        let mut names: Vec<&String> = origins.keys().collect();
+        names.sort();
+        for origin in names {
+            merge(&mut families, &origins[origin].1, Some(origin));
+        }
+
+        let mut output = String::new();
+        for family in families {
+            for line in family.header.iter().chain(family.samples.iter()) {
+                output.push_str(line);
+                output.push('\n');
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{add_origin, merge, Aggregator};
+    use crate::metrics::Metrics;
+
+    #[test]
+    fn origin_label() {
+        assert_eq!(add_origin("up 1", "a"), "up{origin=\"a\"} 1");
+        assert_eq!(
+            add_origin("up{target=\"x\"} 1", "a\"b"),
+            "up{origin=\"a\\\"b\",target=\"x\"} 1"
+        );
+    }
+
+    #[test]
+    fn merge_families() {
+        let mut families = Vec::new();
+        merge(
+            &mut families,
+            "# HELP up Up\n# TYPE up gauge\nup 1\n# other comment\n#x\n",
+            None,
+        );
+        merge(
+            &mut families,
+            "# HELP up Up\n# TYPE up gauge\nup 0\n# TYPE down gauge\ndown 1\n",
+            Some("b"),
+        );
+        assert_eq!(families.len(), 2);
+        assert_eq!(families[0].name, "up");
+        assert_eq!(families[0].header, ["# HELP up Up", "# TYPE up gauge"]);
+        assert_eq!(families[0].samples, ["up 1", "up{origin=\"b\"} 0"]);
+        assert_eq!(families[1].header, ["# TYPE down gauge"]);
+        assert_eq!(families[1].samples, ["down{origin=\"b\"} 1"]);
+    }
+
+    #[test]
+    fn push_invalid_comment() {
+        let metrics = Metrics::new("fs_latency_", false, false, &HashMap::new());
+        let aggregator = Aggregator::new(&metrics);
+        assert!(aggregator.push("a", "#x\n".to_owned()).is_err());
+        assert!(aggregator.push("a", "#\n".to_owned()).is_err());
+        aggregator
+            .push("b", "# TYPE up gauge\nup 1\n".to_owned())
+            .unwrap();
+        for _ in 0..2 {
+            assert_eq!(
+                aggregator.render(""),
+                "# TYPE up gauge\nup{origin=\"b\"} 1\n"
+            );
+        }
+    }
+}
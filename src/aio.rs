@@ -0,0 +1,111 @@
+use std::io;
+use std::os::unix::io::RawFd;
+use std::time::Instant;
+
+const IOCB_CMD_PREAD: u16 = 0;
+
+/// `struct iocb` from linux/aio_abi.h.
+#[repr(C)]
+#[derive(Default)]
+struct Iocb {
+    aio_data: u64,
+    #[cfg(target_endian = "little")]
+    aio_key: u32,
+    aio_rw_flags: i32,
+    #[cfg(target_endian = "big")]
+    aio_key: u32,
+    aio_lio_opcode: u16,
+    aio_reqprio: i16,
+    aio_fildes: u32,
+    aio_buf: u64,
+    aio_nbytes: u64,
+    aio_offset: i64,
+    aio_reserved2: u64,
+    aio_flags: u32,
+    aio_resfd: u32,
+}
+
+/// `struct io_event` from linux/aio_abi.h.
+#[repr(C)]
+#[derive(Default)]
+struct IoEvent {
+    data: u64,
+    obj: u64,
+    res: i64,
+    res2: i64,
+}
+
+/// Reads through Linux native AIO (like fio's libaio engine), which
+/// separates the time taken to submit a request (queuing in the kernel and
+/// block layer) from the time it takes to complete (the device).
+pub struct Aio {
+    context: libc::c_ulong,
+}
+
+impl Aio {
+    pub fn new() -> io::Result<Aio> {
+        let mut context: libc::c_ulong = 0;
+        if unsafe { libc::syscall(libc::SYS_io_setup, 1, &mut context) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Aio { context })
+    }
+
+    /// Read `buffer.len()` bytes at `offset`, and return the time taken to
+    /// submit the request and the time it then took to complete.
+    pub fn read(&self, fd: RawFd, buffer: &mut [u8], offset: u64) -> io::Result<(f64, f64)> {
+        let mut iocb = Iocb {
+            aio_lio_opcode: IOCB_CMD_PREAD,
+            aio_fildes: fd as u32,
+            aio_buf: buffer.as_mut_ptr() as u64,
+            aio_nbytes: buffer.len() as u64,
+            aio_offset: offset as i64,
+            ..Default::default()
+        };
+        let mut iocbs = [&mut iocb as *mut Iocb];
+
+        let start = Instant::now();
+        let ret =
+            unsafe { libc::syscall(libc::SYS_io_submit, self.context, 1, iocbs.as_mut_ptr()) };
+        if ret != 1 {
+            return Err(io::Error::last_os_error());
+        }
+        let queue = start.elapsed().as_secs_f64();
+
+        let start = Instant::now();
+        let mut event = IoEvent::default();
+        loop {
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_io_getevents,
+                    self.context,
+                    1,
+                    1,
+                    &mut event,
+                    std::ptr::null_mut::<libc::timespec>(),
+                )
+            };
+            if ret == 1 {
+                break;
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+        let service = start.elapsed().as_secs_f64();
+
+        if event.res < 0 {
+            return Err(io::Error::from_raw_os_error(-event.res as i32));
+        } else if (event.res as usize) < buffer.len() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok((queue, service))
+    }
+}
+
+impl Drop for Aio {
+    fn drop(&mut self) {
+        unsafe { libc::syscall(libc::SYS_io_destroy, self.context) };
+    }
+}
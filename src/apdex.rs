@@ -0,0 +1,100 @@
+use prometheus::{CounterVec, GaugeVec, Opts};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use crate::series::Series;
+
+/// Number of recent operations the Apdex score is computed over.
+const WINDOW: usize = 100;
+
+/// Counts of operations within and over latency thresholds, and an Apdex
+/// score.
+///
+/// The Apdex score uses the first threshold T of a target: operations taking
+/// at most T are satisfying, at most 4T are tolerable (counting for half), and
+/// slower or failed operations count for nothing.
+pub struct Thresholds {
+    /// Thresholds of each target which has some, and their labels
+    targets: HashMap<String, Vec<(f64, String)>>,
+    /// Score of the recent operations, by target and operation
+    recent: Series<VecDeque<f64>>,
+    within: CounterVec,
+    over: CounterVec,
+    apdex: GaugeVec,
+}
+
+impl Thresholds {
+    pub fn new(within_opts: Opts, over_opts: Opts, apdex_opts: Opts) -> Thresholds {
+        Thresholds {
+            targets: HashMap::new(),
+            recent: Series::default(),
+            within: CounterVec::new(within_opts, &["operation", "target", "threshold"]).unwrap(),
+            over: CounterVec::new(over_opts, &["operation", "target", "threshold"]).unwrap(),
+            apdex: GaugeVec::new(apdex_opts, &["operation", "target"]).unwrap(),
+        }
+    }
+
+    pub fn counters(&self) -> [&CounterVec; 2] {
+        [&self.within, &self.over]
+    }
+
+    pub fn gauge(&self) -> &GaugeVec {
+        &self.apdex
+    }
+
+    pub fn add(&mut self, target: &str, thresholds: &[Duration]) {
+        let thresholds = thresholds
+            .iter()
+            .map(|&t| {
+                let label = humantime::format_duration(t).to_string().replace(' ', "");
+                (t.as_secs_f64(), label)
+            })
+            .collect();
+        self.targets.insert(target.to_owned(), thresholds);
+    }
+
+    /// Count a successful operation.
+    pub fn observe(&mut self, operation: &str, target: &str, duration: f64) {
+        let thresholds = match self.targets.get(target) {
+            Some(t) => t,
+            None => return,
+        };
+        for (threshold, label) in thresholds {
+            // Export every threshold for both counters, even if they are 0
+            let within = self.within.with_label_values(&[operation, target, label]);
+            let over = self.over.with_label_values(&[operation, target, label]);
+            if duration <= *threshold {
+                within.inc();
+            } else {
+                over.inc();
+            }
+        }
+        let t = thresholds[0].0;
+        let score = if duration <= t {
+            1.0
+        } else if duration <= 4.0 * t {
+            0.5
+        } else {
+            0.0
+        };
+        self.score(operation, target, score);
+    }
+
+    /// Count a failed operation.
+    pub fn error(&mut self, operation: &str, target: &str) {
+        if self.targets.contains_key(target) {
+            self.score(operation, target, 0.0);
+        }
+    }
+
+    fn score(&mut self, operation: &str, target: &str, score: f64) {
+        let recent = self.recent.get_or_default(target, operation);
+        if recent.len() == WINDOW {
+            recent.pop_front();
+        }
+        recent.push_back(score);
+        self.apdex
+            .with_label_values(&[operation, target])
+            .set(recent.iter().sum::<f64>() / recent.len() as f64);
+    }
+}
@@ -0,0 +1,117 @@
+use serde_json::{json, Value};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::TargetConfig;
+use crate::integrity::BLOCK_SIZE;
+use crate::metrics::Recorded;
+use crate::status::quantile;
+
+/// The percentiles reported by fio by default.
+const PERCENTILES: &[f64] = &[
+    1.0, 5.0, 10.0, 20.0, 30.0, 40.0, 50.0, 60.0, 70.0, 80.0, 90.0, 95.0, 99.0, 99.5, 99.9, 99.95,
+    99.99,
+];
+
+/// Latency statistics in nanoseconds, in fio's format.
+fn latency_stats(sorted: &[f64], percentiles: bool) -> Value {
+    let ns = |v: f64| (v * 1e9).round() as u64;
+    if sorted.is_empty() {
+        return json!({"min": 0, "max": 0, "mean": 0.0, "stddev": 0.0, "N": 0});
+    }
+    let mean = sorted.iter().sum::<f64>() / sorted.len() as f64;
+    let variance = sorted.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / sorted.len() as f64;
+    let mut stats = json!({
+        "min": ns(sorted[0]),
+        "max": ns(sorted[sorted.len() - 1]),
+        "mean": mean * 1e9,
+        "stddev": variance.sqrt() * 1e9,
+        "N": sorted.len(),
+    });
+    if percentiles {
+        let percentiles: serde_json::Map<String, Value> = PERCENTILES
+            .iter()
+            .map(|&p| {
+                let value = quantile(sorted, p / 100.0).unwrap();
+                (format!("{:.6}", p), ns(value).into())
+            })
+            .collect();
+        stats["percentile"] = percentiles.into();
+    }
+    stats
+}
+
+/// Statistics for one direction (read, write), in fio's format.
+fn direction(latencies: Option<&Vec<f64>>, block_size: usize, runtime: Duration) -> Value {
+    let mut sorted = latencies.cloned().unwrap_or_default();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let runtime_secs = runtime.as_secs_f64();
+    let io_bytes = (sorted.len() * block_size) as u64;
+    let bw_bytes = (io_bytes as f64 / runtime_secs) as u64;
+    json!({
+        "io_bytes": io_bytes,
+        "io_kbytes": io_bytes / 1024,
+        "bw_bytes": bw_bytes,
+        "bw": bw_bytes / 1024,
+        "iops": sorted.len() as f64 / runtime_secs,
+        "runtime": runtime.as_millis() as u64,
+        "total_ios": sorted.len(),
+        "short_ios": 0,
+        "drop_ios": 0,
+        "slat_ns": latency_stats(&[], false),
+        "clat_ns": latency_stats(&sorted, true),
+        "lat_ns": latency_stats(&sorted, false),
+    })
+}
+
+/// Build a report of the latencies measured during `--bench`, in the format of
+/// `fio --output-format=json`, so tools that read fio's output can use it.
+pub fn report(targets: &[TargetConfig], recorded: &Recorded, runtime: Duration) -> Value {
+    let get = |target: &TargetConfig, operation: &str| {
+        recorded.get(&(target.name.clone(), operation.to_owned()))
+    };
+    let jobs: Vec<Value> = targets
+        .iter()
+        .map(|target| {
+            let mut sync = get(target, "fsync").cloned().unwrap_or_default();
+            sync.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let mut job = json!({
+                "jobname": target.name,
+                "groupid": 0,
+                "error": 0,
+                "elapsed": runtime.as_secs(),
+                "job_runtime": runtime.as_millis() as u64,
+                "read": direction(get(target, "read"), target.read_size, runtime),
+                "write": direction(get(target, "write"), BLOCK_SIZE, runtime),
+                "trim": direction(None, 0, runtime),
+                "sync": {
+                    "total_ios": sync.len(),
+                    "lat_ns": latency_stats(&sync, true),
+                },
+            });
+            // Not something fio does, so only there if the storm probe ran
+            if target.has_op("storm") {
+                let mut creates = get(target, "storm_create").cloned().unwrap_or_default();
+                creates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                let storms = get(target, "storm").map(|s| s.iter().sum()).unwrap_or(0.0);
+                job["create_storm"] = json!({
+                    "storms": get(target, "storm").map(|s| s.len()).unwrap_or(0),
+                    "total_creates": creates.len(),
+                    "creates_per_second": if storms > 0.0 {
+                        creates.len() as f64 / storms
+                    } else {
+                        0.0
+                    },
+                    "lat_ns": latency_stats(&creates, true),
+                });
+            }
+            job
+        })
+        .collect();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    json!({
+        "fio version": concat!("fs-latency-exporter-", env!("CARGO_PKG_VERSION")),
+        "timestamp": now.as_secs(),
+        "timestamp_ms": now.as_millis() as u64,
+        "jobs": jobs,
+    })
+}
@@ -0,0 +1,12 @@
+/// FNV-1a, a simple non-cryptographic hash. It is stable across versions,
+/// so it can be stored on disk.
+pub fn fnv1a(mut hash: u64, data: &[u8]) -> u64 {
+    for &byte in data {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Initial value for [`fnv1a`].
+pub const FNV_OFFSET: u64 = 0xcbf29ce484222325;
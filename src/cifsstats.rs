@@ -0,0 +1,187 @@
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{CounterVec, GaugeVec};
+use std::fs::read_to_string;
+use std::path::Path;
+use tracing::{info, warn};
+
+use crate::metrics::{set_counter, Metrics};
+use crate::mounts::find_mount;
+
+/// Collects the statistics of the CIFS/SMB share of each target, from
+/// /proc/fs/cifs/Stats, at scrape time: the reconnects and oplock breaks
+/// tell problems on the file server apart from problems on the client.
+#[derive(Clone)]
+pub struct CifsStatsCollector {
+    /// Targets and their share, as written in the statistics
+    /// (`\\server\share`)
+    targets: Vec<(String, String)>,
+    reconnects: CounterVec,
+    oplock_breaks: CounterVec,
+    operations: CounterVec,
+    failures: CounterVec,
+    disconnected: GaugeVec,
+}
+
+impl CifsStatsCollector {
+    /// Create the collector, for the targets that are on CIFS. Returns None
+    /// if there are none.
+    pub fn new<'a, I: IntoIterator<Item = (String, &'a Path)>>(
+        targets: I,
+        metrics: &Metrics,
+    ) -> Option<CifsStatsCollector> {
+        let mut cifs_targets = Vec::new();
+        for (target, path) in targets {
+            if let Some(mount) = find_mount(path) {
+                if mount.fstype == "cifs" || mount.fstype == "smb3" {
+                    info!("{:?} is on CIFS share {}", path, mount.source);
+                    cifs_targets.push((target, mount.source.replace('/', "\\")));
+                }
+            }
+        }
+        if cifs_targets.is_empty() {
+            return None;
+        }
+
+        let counter = |name: &str, help: &str, labels: &[&str]| {
+            CounterVec::new(metrics.opts(name, help), labels).unwrap()
+        };
+        Some(CifsStatsCollector {
+            targets: cifs_targets,
+            reconnects: counter(
+                "cifs_reconnects_total",
+                "Number of reconnects of CIFS sessions or shares, for all the mounts",
+                &["target", "kind"],
+            ),
+            oplock_breaks: counter(
+                "cifs_oplock_breaks_total",
+                "Number of oplock breaks sent for the CIFS share",
+                &["target"],
+            ),
+            operations: counter(
+                "cifs_operations_total",
+                "Number of SMB requests sent for the CIFS share",
+                &["target", "operation"],
+            ),
+            failures: counter(
+                "cifs_operation_failures_total",
+                "Number of SMB requests which failed for the CIFS share",
+                &["target", "operation"],
+            ),
+            disconnected: GaugeVec::new(
+                metrics.opts(
+                    "cifs_share_disconnected",
+                    "Whether the CIFS share is currently disconnected from the server",
+                ),
+                &["target"],
+            )
+            .unwrap(),
+        })
+    }
+
+    fn record(&self, target: &str, line: &str) {
+        let (name, values) = match line.trim().split_once(':') {
+            Some(v) => v,
+            None => return,
+        };
+        let words: Vec<&str> = values.split_whitespace().collect();
+        let number = |i: usize| words.get(i).and_then(|w| w.parse::<f64>().ok());
+        match (name, words.get(1), words.get(3)) {
+            // SMB2+: "OplockBreaks: 3 sent 0 failed"
+            ("OplockBreaks", Some(&"sent"), _) | ("Oplocks breaks", _, _) => {
+                if let Some(sent) = number(0) {
+                    set_counter(&self.oplock_breaks.with_label_values(&[target]), sent);
+                }
+            }
+            // "Reads: 12 total 0 failed"
+            (operation, Some(&"total"), Some(&"failed")) => {
+                if let (Some(total), Some(failed)) = (number(0), number(2)) {
+                    set_counter(
+                        &self.operations.with_label_values(&[target, operation]),
+                        total,
+                    );
+                    set_counter(
+                        &self.failures.with_label_values(&[target, operation]),
+                        failed,
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Collector for CifsStatsCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        let mut descs = Vec::new();
+        descs.extend(self.reconnects.desc());
+        descs.extend(self.oplock_breaks.desc());
+        descs.extend(self.operations.desc());
+        descs.extend(self.failures.desc());
+        descs.extend(self.disconnected.desc());
+        descs
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let stats = match read_to_string("/proc/fs/cifs/Stats") {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Can't read /proc/fs/cifs/Stats: {}", e);
+                return Vec::new();
+            }
+        };
+
+        // Shares start with "1) \\server\share", possibly followed by
+        // "DISCONNECTED"
+        let mut current: Vec<&str> = Vec::new();
+        for line in stats.lines() {
+            let share = line
+                .split_once(") ")
+                .filter(|(n, _)| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+            if let Some((_, share)) = share {
+                current.clear();
+                let mut words = share.split_whitespace();
+                let name = words.next().unwrap_or("");
+                let disconnected = words.any(|w| w == "DISCONNECTED");
+                for (target, target_share) in &self.targets {
+                    if target_share.eq_ignore_ascii_case(name) {
+                        current.push(target);
+                        self.disconnected
+                            .with_label_values(&[target])
+                            .set(if disconnected { 1.0 } else { 0.0 });
+                    }
+                }
+            } else if let Some(counts) = line.trim().strip_suffix(" share reconnects") {
+                // "0 session 0 share reconnects", for all the sessions
+                let words: Vec<f64> = counts
+                    .split_whitespace()
+                    .filter_map(|w| w.parse().ok())
+                    .collect();
+                if let [session, share] = words[..] {
+                    for (target, _) in &self.targets {
+                        set_counter(
+                            &self.reconnects.with_label_values(&[target, "session"]),
+                            session,
+                        );
+                        set_counter(
+                            &self.reconnects.with_label_values(&[target, "share"]),
+                            share,
+                        );
+                    }
+                }
+            } else {
+                for target in &current {
+                    self.record(target, line);
+                }
+            }
+        }
+
+        let mut families = Vec::new();
+        families.extend(self.reconnects.collect());
+        families.extend(self.oplock_breaks.collect());
+        families.extend(self.operations.collect());
+        families.extend(self.failures.collect());
+        families.extend(self.disconnected.collect());
+        families
+    }
+}
@@ -0,0 +1,70 @@
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::direct::Resolve;
+use crate::metrics::Metrics;
+
+/// Opens the target, reads its first block, and closes it, like an
+/// application opening a file would, measuring the whole sequence rather
+/// than a read on a file that is kept open (and each step as a phase, to
+/// tell the lookup from the transfer).
+pub struct ColdOpenProbe {
+    resolve: Resolve,
+    buffer: Vec<u8>,
+}
+
+impl ColdOpenProbe {
+    pub fn new(size: usize, resolve: &Resolve) -> ColdOpenProbe {
+        ColdOpenProbe {
+            resolve: resolve.clone(),
+            buffer: vec![0; size],
+        }
+    }
+
+    pub fn run(&mut self, path: &Path, metrics: &Metrics, target: &str) -> Result<(), String> {
+        let (file, open, read) = match self.open_and_read(path) {
+            Ok(result) => result,
+            Err(e) => {
+                metrics.error("open", target);
+                return Err(e);
+            }
+        };
+
+        // Drop the block from the cache, so the next open reads it again
+        // (not measured)
+        unsafe {
+            libc::posix_fadvise(
+                file.as_raw_fd(),
+                0,
+                self.buffer.len() as libc::off_t,
+                libc::POSIX_FADV_DONTNEED,
+            )
+        };
+
+        let start = Instant::now();
+        drop(file);
+        let close = start.elapsed().as_secs_f64();
+        metrics.observe("open", target, None, open + read + close);
+        metrics.observe_phase("open", target, "open", open);
+        metrics.observe_phase("open", target, "read", read);
+        metrics.observe_phase("open", target, "close", close);
+        Ok(())
+    }
+
+    /// Open the file and read from it, returning it and how long each took.
+    fn open_and_read(&mut self, path: &Path) -> Result<(File, f64, f64), String> {
+        let start = Instant::now();
+        let mut file = self
+            .resolve
+            .open(path, libc::O_RDONLY, 0)
+            .map_err(|e| format!("Can't open {:?}: {}", path, e))?;
+        let open = start.elapsed().as_secs_f64();
+        let start = Instant::now();
+        file.read(&mut self.buffer)
+            .map_err(|e| format!("Error reading {:?}: {}", path, e))?;
+        Ok((file, open, start.elapsed().as_secs_f64()))
+    }
+}
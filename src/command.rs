@@ -0,0 +1,110 @@
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use crate::config::TargetConfig;
+use crate::metrics::Metrics;
+use crate::prober::{ProbeResult, Prober};
+
+/// Runs a shell command and measures how long it takes, for checks that can
+/// only be done with another tool (e.g. a vendor's health check CLI). The
+/// path of the target is passed in the FS_LATENCY_TARGET environment
+/// variable.
+pub struct CommandProbe {
+    command: String,
+    timeout: Duration,
+    path: PathBuf,
+}
+
+impl CommandProbe {
+    pub fn new(config: &TargetConfig, path: &Path) -> CommandProbe {
+        CommandProbe {
+            command: config.command.clone().unwrap(),
+            timeout: config.command_timeout,
+            path: path.to_owned(),
+        }
+    }
+
+    /// Run the command, and return how long it took and its exit status, or
+    /// None if it had to be killed.
+    fn run_command(&self) -> Result<(f64, Option<ExitStatus>), String> {
+        let start = Instant::now();
+        let mut child = Command::new("/bin/sh")
+            .arg("-c")
+            .arg(&self.command)
+            .env("FS_LATENCY_TARGET", &self.path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .process_group(0)
+            .spawn()
+            .map_err(|e| format!("Can't run command {:?}: {}", self.command, e))?;
+        let pid = child.id() as libc::pid_t;
+
+        // Kill the command (and whatever it started) if it takes too long
+        let (done, wait) = channel::<()>();
+        let timeout = self.timeout;
+        let watchdog = std::thread::spawn(move || {
+            if wait.recv_timeout(timeout) == Err(RecvTimeoutError::Timeout) {
+                unsafe { libc::kill(-pid, libc::SIGKILL) };
+                true
+            } else {
+                false
+            }
+        });
+
+        // Wait for it to exit, but don't reap it until the watchdog is done,
+        // so the pid can't be reused by another process it would kill
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        while unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        } != 0
+            && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted
+        {}
+        let duration = start.elapsed().as_secs_f64();
+        let _ = done.send(());
+        let killed = watchdog.join().unwrap();
+
+        let status = child
+            .wait()
+            .map_err(|e| format!("Can't wait for command {:?}: {}", self.command, e))?;
+        Ok((duration, if killed { None } else { Some(status) }))
+    }
+}
+
+impl Prober for CommandProbe {
+    fn probe(&mut self, _operation: &str, metrics: &Metrics, target: &str) -> ProbeResult {
+        let result = self.run_command();
+        match result {
+            Ok((duration, Some(status))) if status.success() => {
+                metrics.command_status(target, 0);
+                metrics.observe("command", target, None, duration);
+                Ok(())
+            }
+            Ok((_, Some(status))) => {
+                metrics.command_status(target, status.code().unwrap_or(-1));
+                metrics.error("command", target);
+                Err(format!("Command {:?} failed: {}", self.command, status))
+            }
+            Ok((_, None)) => {
+                metrics.command_status(target, -1);
+                metrics.error("command", target);
+                Err(format!(
+                    "Command {:?} took more than {}, killed",
+                    self.command,
+                    humantime::format_duration(self.timeout)
+                ))
+            }
+            Err(e) => {
+                metrics.error("command", target);
+                Err(e)
+            }
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::block_in_place;
+use tracing::{info, warn};
+
+use crate::client::{encode, parse_url, request, Url};
+use crate::metrics::Metrics;
+
+/// How often to update the health check (its TTL is 3 times that).
+const UPDATE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Registers the metrics endpoint as a service in Consul, with a TTL check
+/// that passes while the probes of all the targets succeed.
+pub struct Consul {
+    url: Url,
+    token: Option<String>,
+    service: String,
+    id: String,
+    address: SocketAddr,
+    tags: Vec<String>,
+}
+
+impl Consul {
+    pub fn new(
+        url: &str,
+        service: String,
+        origin: &str,
+        address: SocketAddr,
+        labels: &HashMap<String, String>,
+    ) -> Consul {
+        let url = parse_url(url).unwrap();
+        let mut tags: Vec<String> = labels.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        tags.sort();
+        Consul {
+            url,
+            token: std::env::var("CONSUL_HTTP_TOKEN").ok(),
+            id: format!("{}-{}-{}", service, origin, address.port()),
+            service,
+            address,
+            tags,
+        }
+    }
+
+    fn put(&self, path: &str, body: serde_json::Value) -> Result<(), String> {
+        let mut headers = vec![("Content-Type", "application/json")];
+        if let Some(token) = &self.token {
+            headers.push(("X-Consul-Token", token));
+        }
+        let (code, response) = request(
+            &self.url,
+            "PUT",
+            path,
+            &headers,
+            body.to_string().as_bytes(),
+        )?;
+        if code == 200 {
+            Ok(())
+        } else {
+            Err(format!(
+                "Consul replied with status {}: {}",
+                code,
+                response.trim()
+            ))
+        }
+    }
+
+    fn register(&self) -> Result<(), String> {
+        let mut service = serde_json::json!({
+            "ID": self.id,
+            "Name": self.service,
+            "Port": self.address.port(),
+            "Tags": self.tags,
+            "Check": {
+                "CheckID": format!("service:{}", self.id),
+                "Name": "Probes succeeding",
+                "TTL": format!("{}s", UPDATE_INTERVAL.as_secs() * 3),
+                "DeregisterCriticalServiceAfter": "10m",
+            },
+        });
+        // Without an address, Consul uses the one of its agent
+        if !self.address.ip().is_unspecified() {
+            service["Address"] = self.address.ip().to_string().into();
+        }
+        self.put("/v1/agent/service/register", service)
+    }
+
+    fn update(&self, passing: bool, output: String) -> Result<(), String> {
+        self.put(
+            &format!(
+                "/v1/agent/check/update/{}",
+                encode(&format!("service:{}", self.id))
+            ),
+            serde_json::json!({
+                "Status": if passing { "passing" } else { "critical" },
+                "Output": output,
+            }),
+        )
+    }
+
+    /// Keep the service registered and its check updated, forever, on a
+    /// multi-threaded runtime.
+    pub async fn run(self, targets: Vec<String>, metrics: Arc<Metrics>) {
+        let mut registered = false;
+        loop {
+            block_in_place(|| self.check(&mut registered, &targets, &metrics));
+            tokio::time::sleep(UPDATE_INTERVAL).await;
+        }
+    }
+
+    fn check(&self, registered: &mut bool, targets: &[String], metrics: &Metrics) {
+        if !*registered {
+            match self.register() {
+                Ok(()) => {
+                    info!("Registered in Consul as {}", self.id);
+                    *registered = true;
+                }
+                Err(e) => warn!("Can't register in Consul: {}", e),
+            }
+        }
+        if *registered {
+            let failing = metrics.failing(targets);
+            let output = if failing.is_empty() {
+                "All probes succeeding".to_owned()
+            } else {
+                format!("Probes failing: {}", failing.join(", "))
+            };
+            if let Err(e) = self.update(failing.is_empty(), output) {
+                // The agent might have lost our registration, do it again
+                warn!("Can't update Consul check: {}", e);
+                *registered = false;
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Instant;
+
+#[derive(Default)]
+struct State {
+    paused: bool,
+    triggered: bool,
+}
+
+/// Lets the control API pause a target, or make it probe right away.
+#[derive(Default)]
+pub struct Control {
+    state: Mutex<State>,
+    wake: Condvar,
+}
+
+/// The controls of all the targets, by name.
+pub type Controls = Arc<Vec<(String, Arc<Control>)>>;
+
+impl Control {
+    pub fn is_paused(&self) -> bool {
+        self.state.lock().unwrap().paused
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.state.lock().unwrap().paused = paused;
+    }
+
+    /// Run the probes of the target now, even if it is paused.
+    pub fn trigger(&self) {
+        self.state.lock().unwrap().triggered = true;
+        self.wake.notify_all();
+    }
+
+    /// Wait until `deadline`, or until a probe is triggered. Returns true if
+    /// a probe was triggered.
+    pub fn wait_until(&self, deadline: Instant) -> bool {
+        let mut state = self.state.lock().unwrap();
+        while !state.triggered {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            state = self.wake.wait_timeout(state, deadline - now).unwrap().0;
+        }
+        state.triggered = false;
+        true
+    }
+}
+
+/// Apply an action of the control API ("probe", "pause", "resume") to a
+/// target, or all of them. Returns the targets affected, or `None` if the
+/// target doesn't exist.
+pub fn apply(controls: &Controls, action: &str, target: Option<&str>) -> Option<Vec<String>> {
+    let selected: Vec<_> = controls
+        .iter()
+        .filter(|(name, _)| target.is_none_or(|t| t == name))
+        .collect();
+    if selected.is_empty() {
+        return None;
+    }
+    for (_, control) in &selected {
+        match action {
+            "probe" => control.trigger(),
+            "pause" => control.set_paused(true),
+            "resume" => control.set_paused(false),
+            _ => return None,
+        }
+    }
+    Some(selected.into_iter().map(|(name, _)| name.clone()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use super::{apply, Control, Controls};
+
+    #[test]
+    fn actions() {
+        let controls: Controls = Arc::new(vec![
+            ("a".to_owned(), Arc::new(Control::default())),
+            ("b".to_owned(), Arc::new(Control::default())),
+        ]);
+        assert_eq!(
+            apply(&controls, "pause", None),
+            Some(vec!["a".to_owned(), "b".to_owned()])
+        );
+        assert!(controls[0].1.is_paused() && controls[1].1.is_paused());
+        assert_eq!(
+            apply(&controls, "resume", Some("b")),
+            Some(vec!["b".to_owned()])
+        );
+        assert!(controls[0].1.is_paused() && !controls[1].1.is_paused());
+        assert_eq!(apply(&controls, "resume", Some("c")), None);
+        assert_eq!(apply(&controls, "explode", Some("a")), None);
+
+        assert_eq!(
+            apply(&controls, "probe", Some("a")),
+            Some(vec!["a".to_owned()])
+        );
+        let far = Instant::now() + Duration::from_secs(60);
+        assert!(controls[0].1.wait_until(far));
+        assert!(!controls[0].1.wait_until(Instant::now()));
+    }
+}
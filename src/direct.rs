@@ -0,0 +1,260 @@
+use std::ffi::CString;
+use std::fs::File;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+/// Where to allocate the I/O buffers, so that memory access doesn't add to
+/// the latency of fast devices.
+#[derive(Clone, Default)]
+pub struct BufferOptions {
+    /// Allocate on this NUMA node
+    pub numa_node: Option<u32>,
+    /// Back with (2 MiB) hugepages, to avoid TLB misses
+    pub hugepages: bool,
+}
+
+/// A buffer suitable for direct I/O, aligned on a 4096-byte boundary.
+pub struct AlignedBuffer {
+    storage: Storage,
+    start: usize,
+    len: usize,
+}
+
+enum Storage {
+    Heap(Vec<u8>),
+    Mapped { ptr: *mut u8, size: usize },
+}
+
+// The mapping is only accessed through the buffer
+unsafe impl Send for Storage {}
+
+impl Drop for Storage {
+    fn drop(&mut self) {
+        if let Storage::Mapped { ptr, size } = *self {
+            unsafe { libc::munmap(ptr as *mut libc::c_void, size) };
+        }
+    }
+}
+
+impl AlignedBuffer {
+    pub fn new(len: usize) -> AlignedBuffer {
+        let storage = vec![0; len + 4096];
+        let ptr = storage.as_ptr() as usize;
+        let start = (4096 - ptr % 4096) % 4096;
+        AlignedBuffer {
+            storage: Storage::Heap(storage),
+            start,
+            len,
+        }
+    }
+
+    /// Allocate a buffer with specific placement, mapping memory directly.
+    pub fn allocate(len: usize, options: &BufferOptions) -> io::Result<AlignedBuffer> {
+        if options.numa_node.is_none() && !options.hugepages {
+            return Ok(AlignedBuffer::new(len));
+        }
+        let page = if options.hugepages { 2 << 20 } else { 4096 };
+        let size = len.div_ceil(page).max(1) * page;
+        let mut flags = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS;
+        if options.hugepages {
+            flags |= libc::MAP_HUGETLB;
+        }
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                flags,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let storage = Storage::Mapped {
+            ptr: ptr as *mut u8,
+            size,
+        };
+        if let Some(node) = options.numa_node {
+            // Room for 1024 nodes
+            let mut mask = [0 as libc::c_ulong; 16];
+            let bits = libc::c_ulong::BITS as usize;
+            match mask.get_mut(node as usize / bits) {
+                Some(word) => *word |= 1 << (node as usize % bits),
+                None => return Err(io::Error::from_raw_os_error(libc::EINVAL)),
+            }
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_mbind,
+                    ptr,
+                    size,
+                    libc::MPOL_BIND,
+                    mask.as_ptr(),
+                    mask.len() * bits + 1,
+                    MPOL_MF_STRICT,
+                )
+            };
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        // Fault the pages in now, on the right node
+        unsafe { std::ptr::write_bytes(ptr as *mut u8, 0, size) };
+        Ok(AlignedBuffer {
+            storage,
+            start: 0,
+            len,
+        })
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        match &self.storage {
+            Storage::Heap(v) => &v[self.start..self.start + self.len],
+            Storage::Mapped { ptr, .. } => unsafe { std::slice::from_raw_parts(*ptr, self.len) },
+        }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        match &mut self.storage {
+            Storage::Heap(v) => &mut v[self.start..self.start + self.len],
+            Storage::Mapped { ptr, .. } => unsafe {
+                std::slice::from_raw_parts_mut(*ptr, self.len)
+            },
+        }
+    }
+}
+
+const MPOL_MF_STRICT: libc::c_uint = 1;
+
+/// Restrictions on how the paths of targets and scratch files are resolved,
+/// so that swapping in a symbolic link can't redirect the probes to another
+/// file.
+#[derive(Clone, Default)]
+pub struct Resolve {
+    /// Only open files beneath this directory
+    pub root: Option<PathBuf>,
+    /// Don't follow symbolic links
+    pub no_follow: bool,
+}
+
+impl Resolve {
+    /// Open a file with the given `open()` flags, following the restrictions
+    /// (using `openat2()`).
+    pub fn open(&self, path: &Path, flags: libc::c_int, mode: u32) -> io::Result<File> {
+        let flags = flags | libc::O_CLOEXEC;
+        if self.root.is_none() && !self.no_follow {
+            let path = CString::new(path.as_os_str().as_bytes())?;
+            return check_fd(unsafe { libc::open(path.as_ptr(), flags, mode) });
+        }
+
+        let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+        how.flags = flags as u64;
+        if flags & libc::O_CREAT != 0 || flags & libc::O_TMPFILE == libc::O_TMPFILE {
+            how.mode = mode as u64;
+        }
+        if self.no_follow {
+            how.resolve |= libc::RESOLVE_NO_SYMLINKS;
+        }
+        let (dir, path) = match &self.root {
+            Some(root) => {
+                // Relative paths are relative to the current directory, as
+                // they would be without --root
+                let cwd = if root.is_relative() || path.is_relative() {
+                    std::env::current_dir()?
+                } else {
+                    PathBuf::new()
+                };
+                let root = cwd.join(root);
+                let path = cwd.join(path);
+                let relative = match path.strip_prefix(&root) {
+                    Ok(r) => r,
+                    Err(_) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::PermissionDenied,
+                            format!("not beneath {:?}", root),
+                        ))
+                    }
+                };
+                let relative = if relative.as_os_str().is_empty() {
+                    PathBuf::from(".")
+                } else {
+                    relative.to_owned()
+                };
+                how.resolve |= libc::RESOLVE_BENEATH;
+                (Some(File::open(root)?), relative)
+            }
+            None => (None, path.to_owned()),
+        };
+        let dir_fd = dir.as_ref().map_or(libc::AT_FDCWD, |d| d.as_raw_fd());
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_openat2,
+                dir_fd,
+                path.as_ptr(),
+                &how,
+                std::mem::size_of::<libc::open_how>(),
+            )
+        } as libc::c_int;
+        match check_fd(fd) {
+            // Older kernel, only the last component can be checked
+            Err(e) if e.raw_os_error() == Some(libc::ENOSYS) && self.root.is_none() => {
+                check_fd(unsafe { libc::open(path.as_ptr(), flags | libc::O_NOFOLLOW, mode) })
+            }
+            result => result,
+        }
+    }
+}
+
+fn check_fd(fd: libc::c_int) -> io::Result<File> {
+    if fd == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+}
+
+/// Open a file for direct I/O, bypassing the page cache.
+pub fn open_direct(path: &Path, write: bool, resolve: &Resolve) -> io::Result<File> {
+    let flags = if write {
+        libc::O_RDWR | libc::O_CREAT
+    } else {
+        libc::O_RDONLY
+    };
+    resolve.open(path, flags | libc::O_DIRECT, 0o666)
+}
+
+/// Get a file for direct I/O from an inherited file descriptor, which is
+/// duplicated so the file can be opened again later.
+pub fn from_fd_direct(fd: RawFd) -> io::Result<File> {
+    let new_fd = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+    if new_fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let file = unsafe { File::from_raw_fd(new_fd) };
+    // The flag is shared with the original descriptor, which is fine since
+    // that one is ours too
+    set_direct(&file, true)?;
+    Ok(file)
+}
+
+/// Turn direct I/O on or off for an open file.
+pub fn set_direct(file: &File, direct: bool) -> io::Result<()> {
+    let fd = file.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let flags = if direct {
+        flags | libc::O_DIRECT
+    } else {
+        flags & !libc::O_DIRECT
+    };
+    if unsafe { libc::fcntl(fd, libc::F_SETFL, flags) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::config::hostname;
+use crate::direct::Resolve;
+use crate::metrics::Metrics;
+use crate::prober::{ProbeResult, Prober};
+
+/// Creates a file in the scratch directory and measures `fsync()` of the
+/// directory, which makes the new entry durable. This is the metadata cost
+/// paid by workloads which often create files (e.g. rolling log segments).
+pub struct DirSyncProbe {
+    dir: File,
+    path: PathBuf,
+    resolve: Resolve,
+}
+
+impl DirSyncProbe {
+    pub fn new(scratch_dir: &Path, resolve: &Resolve) -> std::io::Result<DirSyncProbe> {
+        let dir = resolve.open(scratch_dir, libc::O_RDONLY | libc::O_DIRECTORY, 0)?;
+        // Same naming as the named scratch files, so it is cleaned up the
+        // same way if left behind
+        let path = scratch_dir.join(format!(
+            "fs-latency-exporter.{}.{}.dirsync",
+            hostname(),
+            std::process::id()
+        ));
+        Ok(DirSyncProbe {
+            dir,
+            path,
+            resolve: resolve.clone(),
+        })
+    }
+
+    fn create_and_sync(&self) -> Result<f64, String> {
+        self.resolve
+            .open(
+                &self.path,
+                libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL,
+                0o600,
+            )
+            .map_err(|e| format!("Error creating {:?}: {}", self.path, e))?;
+        let start = Instant::now();
+        self.dir.sync_all().map_err(|e| {
+            format!(
+                "Error syncing directory {:?}: {}",
+                self.path.parent().unwrap(),
+                e
+            )
+        })?;
+        Ok(start.elapsed().as_secs_f64())
+    }
+}
+
+impl Prober for DirSyncProbe {
+    fn probe(&mut self, _operation: &str, metrics: &Metrics, target: &str) -> ProbeResult {
+        let result = self.create_and_sync();
+
+        // Remove the file, and sync that too so the next measurement only
+        // covers its own creation
+        let _ = std::fs::remove_file(&self.path);
+        let _ = self.dir.sync_all();
+
+        match result {
+            Ok(duration) => {
+                metrics.observe("dirsync", target, None, duration);
+                Ok(())
+            }
+            Err(e) => {
+                metrics.error("dirsync", target);
+                Err(e)
+            }
+        }
+    }
+}
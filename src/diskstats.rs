@@ -0,0 +1,248 @@
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{CounterVec, GaugeVec};
+use std::fs::{read_dir, read_to_string};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use tracing::{info, warn};
+
+use crate::metrics::{set_counter, Metrics};
+use crate::mounts::find_mount;
+
+/// Get the name of a block device from its number, e.g. "sda1" or "dm-0".
+fn device_name(dev: u64) -> Option<String> {
+    let (major, minor) = unsafe { (libc::major(dev), libc::minor(dev)) };
+    let link = std::fs::read_link(format!("/sys/dev/block/{}:{}", major, minor)).ok()?;
+    Some(link.file_name()?.to_str()?.to_owned())
+}
+
+/// Find the block device that a path is stored on.
+pub fn resolve_device(path: &Path) -> Option<String> {
+    let dev = std::fs::metadata(path).ok()?.dev();
+    if let Some(name) = device_name(dev) {
+        return Some(name);
+    }
+
+    // Some filesystems (btrfs, overlays) report an anonymous device number,
+    // try the source of the mount instead
+    let mount = find_mount(path)?;
+    let rdev = std::fs::metadata(&mount.source).ok()?.rdev();
+    device_name(rdev)
+}
+
+/// The devices under a device-mapper or md device (its "slaves"), e.g. the
+/// paths of a multipath device.
+fn slaves(device: &str) -> Vec<String> {
+    let mut slaves: Vec<String> = read_dir(format!("/sys/class/block/{}/slaves", device))
+        .map(|dir| {
+            dir.flatten()
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+    slaves.sort();
+    slaves
+}
+
+/// Find the physical devices under a device, through the device-mapper,
+/// multipath and md layers. Returns the device itself if it isn't layered.
+pub fn physical_devices(device: &str) -> Vec<String> {
+    let slaves = slaves(device);
+    if slaves.is_empty() {
+        return vec![device.to_owned()];
+    }
+    let mut physical: Vec<String> = slaves.iter().flat_map(|s| physical_devices(s)).collect();
+    physical.dedup();
+    physical
+}
+
+/// Whether a device is a multipath device (a device-mapper device made by
+/// multipathd).
+fn is_multipath(device: &str) -> bool {
+    read_to_string(format!("/sys/class/block/{}/dm/uuid", device))
+        .is_ok_and(|uuid| uuid.starts_with("mpath-"))
+}
+
+/// Find the multipath devices under a device (or the device itself).
+fn multipath_devices(device: &str) -> Vec<String> {
+    if is_multipath(device) {
+        return vec![device.to_owned()];
+    }
+    slaves(device)
+        .iter()
+        .flat_map(|s| multipath_devices(s))
+        .collect()
+}
+
+/// Collects the statistics of the block device of each target from
+/// /proc/diskstats, at scrape time, and of the physical devices under it,
+/// with the number of paths of multipath devices.
+#[derive(Clone)]
+pub struct DiskStatsCollector {
+    targets: Vec<(String, String)>,
+    /// The multipath devices of the targets
+    multipath: Vec<(String, String)>,
+    io_now: GaugeVec,
+    io_time: CounterVec,
+    io_time_weighted: CounterVec,
+    physical: GaugeVec,
+    paths: GaugeVec,
+    active_paths: GaugeVec,
+}
+
+impl DiskStatsCollector {
+    pub fn new<'a, I: IntoIterator<Item = (String, &'a Path)>>(
+        targets: I,
+        metrics: &Metrics,
+    ) -> DiskStatsCollector {
+        let mut devices = Vec::new();
+        let mut physical = Vec::new();
+        let mut multipath = Vec::new();
+        for (target, path) in targets {
+            match resolve_device(path) {
+                Some(device) => {
+                    info!("{:?} is on device {}", path, device);
+                    for mpath in multipath_devices(&device) {
+                        info!("{} is on multipath device {}", device, mpath);
+                        multipath.push((target.clone(), mpath));
+                    }
+                    // Also collect the statistics of the physical devices,
+                    // to find which one is slow
+                    for p in physical_devices(&device) {
+                        if p != device {
+                            info!("{} is on physical device {}", device, p);
+                            devices.push((target.clone(), p.clone()));
+                        }
+                        physical.push((target.clone(), p));
+                    }
+                    devices.push((target, device));
+                }
+                None => warn!("Can't find block device for {:?}", path),
+            }
+        }
+
+        let labels = &["target", "device"];
+        let collector = DiskStatsCollector {
+            targets: devices,
+            multipath,
+            io_now: GaugeVec::new(
+                metrics.opts("device_io_now", "Number of I/Os currently in progress"),
+                labels,
+            )
+            .unwrap(),
+            io_time: CounterVec::new(
+                metrics.opts(
+                    "device_io_time_seconds_total",
+                    "Total time spent doing I/Os (utilization)",
+                ),
+                labels,
+            )
+            .unwrap(),
+            io_time_weighted: CounterVec::new(
+                metrics.opts(
+                    "device_io_time_weighted_seconds_total",
+                    "Time spent doing I/Os weighted by their number (queue depth)",
+                ),
+                labels,
+            )
+            .unwrap(),
+            physical: GaugeVec::new(
+                metrics.opts(
+                    "device_physical_info",
+                    "Physical devices of the target, under the device-mapper, multipath and md layers",
+                ),
+                labels,
+            )
+            .unwrap(),
+            paths: GaugeVec::new(
+                metrics.opts("multipath_paths", "Number of paths of the multipath device"),
+                labels,
+            )
+            .unwrap(),
+            active_paths: GaugeVec::new(
+                metrics.opts(
+                    "multipath_active_paths",
+                    "Number of paths of the multipath device whose SCSI device is running",
+                ),
+                labels,
+            )
+            .unwrap(),
+        };
+        for (target, device) in &physical {
+            collector
+                .physical
+                .with_label_values(&[target, device])
+                .set(1.0);
+        }
+        collector
+    }
+}
+
+impl Collector for DiskStatsCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        let mut descs = Vec::new();
+        descs.extend(self.io_now.desc());
+        descs.extend(self.io_time.desc());
+        descs.extend(self.io_time_weighted.desc());
+        descs.extend(self.physical.desc());
+        descs.extend(self.paths.desc());
+        descs.extend(self.active_paths.desc());
+        descs
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let diskstats = match read_to_string("/proc/diskstats") {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Can't read /proc/diskstats: {}", e);
+                return Vec::new();
+            }
+        };
+        for line in diskstats.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 14 {
+                continue;
+            }
+            let field = |n: usize| fields[n].parse::<f64>().unwrap_or(0.0);
+            for (target, device) in &self.targets {
+                if fields[2] != device {
+                    continue;
+                }
+                let labels = &[target.as_str(), device.as_str()];
+                self.io_now.with_label_values(labels).set(field(11));
+                for (counter, value) in [
+                    (&self.io_time, field(12) / 1000.0),
+                    (&self.io_time_weighted, field(13) / 1000.0),
+                ] {
+                    set_counter(&counter.with_label_values(labels), value);
+                }
+            }
+        }
+
+        // Paths can come and go, count them every time
+        for (target, device) in &self.multipath {
+            let paths = slaves(device);
+            let active = paths
+                .iter()
+                .filter(|p| {
+                    read_to_string(format!("/sys/class/block/{}/device/state", p))
+                        .is_ok_and(|state| state.trim() == "running")
+                })
+                .count();
+            let labels = &[target.as_str(), device.as_str()];
+            self.paths.with_label_values(labels).set(paths.len() as f64);
+            self.active_paths
+                .with_label_values(labels)
+                .set(active as f64);
+        }
+
+        let mut families = Vec::new();
+        families.extend(self.io_now.collect());
+        families.extend(self.io_time.collect());
+        families.extend(self.io_time_weighted.collect());
+        families.extend(self.physical.collect());
+        families.extend(self.paths.collect());
+        families.extend(self.active_paths.collect());
+        families
+    }
+}
@@ -0,0 +1,239 @@
+use rand::Rng;
+use std::ffi::OsString;
+use std::fs::File;
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::Instant;
+
+use crate::direct::{open_direct, AlignedBuffer, Resolve};
+use crate::diskstats::{physical_devices, resolve_device};
+use crate::fsstats::statvfs;
+use crate::mounts::find_mount;
+use crate::overlay::backing_layer;
+use crate::scratch;
+use crate::status::quantile;
+
+const USAGE: &str = "\
+Usage: fs-latency-exporter doctor PATH
+Checks whether the file PATH can be measured (or the directory PATH used as
+scratch directory): reports the filesystem, its mount options, block size,
+direct I/O support and alignment, and a quick latency sample. Exits with
+status 0 if no problem was found, 1 otherwise.";
+
+/// Number of reads for the latency sample.
+const SAMPLES: usize = 20;
+
+/// Check a path and print a report.
+pub fn run(args: Vec<OsString>) -> ! {
+    let path = match args.as_slice() {
+        [arg] if arg == "--help" => {
+            println!("{}", USAGE);
+            exit(0);
+        }
+        [arg] if !arg.as_encoded_bytes().starts_with(b"--") => PathBuf::from(arg),
+        _ => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+    let metadata = match std::fs::metadata(&path) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("Can't access {:?}: {}", path, e);
+            exit(1);
+        }
+    };
+
+    let mut warnings = Vec::new();
+    println!("Path: {}", path.display());
+    check_filesystem(&path, &metadata, &mut warnings);
+    if metadata.is_dir() {
+        check_scratch_dir(&path, &mut warnings);
+    } else {
+        check_file(&path, metadata.len(), &mut warnings);
+    }
+
+    println!();
+    if warnings.is_empty() {
+        println!("No problems found");
+        exit(0);
+    }
+    for warning in &warnings {
+        println!("Warning: {}", warning);
+    }
+    exit(1);
+}
+
+fn check_filesystem(path: &Path, metadata: &std::fs::Metadata, warnings: &mut Vec<String>) {
+    match find_mount(path) {
+        Some(mount) => {
+            println!("Filesystem: {}", mount.fstype);
+            println!("Mount point: {}", mount.mount_point.display());
+            println!("Source: {}", mount.source);
+            println!("Mount options: {}", mount.options);
+            let options: Vec<&str> = mount.options.split(',').collect();
+            match mount.fstype.as_str() {
+                "tmpfs" | "ramfs" => warnings.push(format!(
+                    "{} is in memory, its latency doesn't reflect any storage",
+                    mount.fstype
+                )),
+                "overlay" => match backing_layer(path) {
+                    Some((layer, file)) => {
+                        println!("Overlay layer: {} ({})", layer, file.display());
+                        warnings.push(format!(
+                            "overlayfs forwards reads to the layer which has the file, measure \
+                             {} directly (or use --probe-layer) to know which filesystem it is",
+                            file.display()
+                        ));
+                    }
+                    None => warnings.push(
+                        "overlayfs forwards reads to the layer which has the file, measure that \
+                         layer's filesystem directly to know which one it is"
+                            .to_owned(),
+                    ),
+                },
+                t if t.starts_with("nfs") && options.contains(&"soft") => warnings.push(
+                    "NFS is mounted with 'soft', so a slow server shows up as errors after the \
+                     timeout rather than as latency"
+                        .to_owned(),
+                ),
+                t if t.starts_with("fuse") => warnings.push(format!(
+                    "{} is a FUSE filesystem, whether direct I/O bypasses its caches depends on \
+                     the daemon",
+                    t
+                )),
+                _ => {}
+            }
+        }
+        None => warnings.push("Can't find the mount in /proc/self/mountinfo".to_owned()),
+    }
+    let device = resolve_device(path);
+    println!(
+        "Block device: {}",
+        device.clone().unwrap_or_else(|| "none".to_owned())
+    );
+    if let Some(device) = device {
+        let physical = physical_devices(&device);
+        if physical != [device] {
+            println!("Physical devices: {}", physical.join(", "));
+        }
+    }
+    println!("Block size: {}", metadata.blksize());
+    match statvfs(path) {
+        Ok(stat) => println!(
+            "Free space: {} of {} bytes",
+            stat.f_bavail * stat.f_frsize,
+            stat.f_blocks * stat.f_frsize
+        ),
+        Err(e) => warnings.push(format!("Can't get free space: {}", e)),
+    }
+}
+
+fn check_scratch_dir(path: &Path, warnings: &mut Vec<String>) {
+    match scratch::create(path, "doctor", true, &Resolve::default()) {
+        Ok(scratch) => {
+            println!("Direct I/O: supported ({} scratch file)", scratch.mechanism);
+            if scratch.mechanism == "named" {
+                let _ = std::fs::remove_file(&scratch.path);
+            }
+        }
+        Err(e) => warnings.push(format!(
+            "Can't create a scratch file for direct I/O, write probes won't work: {}",
+            e
+        )),
+    }
+}
+
+fn check_file(path: &Path, size: u64, warnings: &mut Vec<String>) {
+    println!("Size: {}", size);
+    let file = match open_direct(path, false, &Resolve::default()) {
+        Ok(f) => f,
+        Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+            println!("Direct I/O: not supported");
+            warnings.push(
+                "The filesystem doesn't support direct I/O, so read probes can't run; use a \
+                 filesystem which does, or the prefetch and open probes, which go through the \
+                 page cache"
+                    .to_owned(),
+            );
+            return;
+        }
+        Err(e) => {
+            warnings.push(format!("Can't open for direct I/O: {}", e));
+            return;
+        }
+    };
+    println!("Direct I/O: supported");
+    if size < 4096 {
+        warnings.push(format!(
+            "The file is too small to read ({} bytes), it needs at least 4096",
+            size
+        ));
+        return;
+    }
+
+    // Find the smallest alignment that works, with an offset which is only
+    // aligned to that much
+    let mut alignment = None;
+    let mut align = 512;
+    while align <= 1 << 20 && 2 * align <= size {
+        let mut buffer = AlignedBuffer::new(align as usize);
+        if file.read_at(buffer.as_mut_slice(), align).is_ok() {
+            alignment = Some(align);
+            break;
+        }
+        align *= 2;
+    }
+    let alignment = match alignment {
+        Some(a) => a,
+        None => {
+            warnings.push("Direct reads fail at any alignment".to_owned());
+            return;
+        }
+    };
+    println!("Required alignment: {}", alignment);
+    if alignment > 4096 {
+        warnings.push(format!(
+            "Reads need to be aligned to {} bytes, the exporter will adjust automatically \
+             but you can set --read-size {} to avoid the retries",
+            alignment, alignment
+        ));
+    }
+
+    sample_latency(&file, size, alignment.max(4096), warnings);
+}
+
+fn sample_latency(file: &File, size: u64, block: u64, warnings: &mut Vec<String>) {
+    if size < block {
+        return;
+    }
+    let mut rng = rand::thread_rng();
+    let mut buffer = AlignedBuffer::new(block as usize);
+    let mut durations = Vec::with_capacity(SAMPLES);
+    for _ in 0..SAMPLES {
+        let offset = rng.gen_range(0..size / block) * block;
+        let start = Instant::now();
+        if let Err(e) = file.read_at(buffer.as_mut_slice(), offset) {
+            warnings.push(format!("Error reading at offset {}: {}", offset, e));
+            return;
+        }
+        durations.push(start.elapsed().as_secs_f64());
+    }
+    durations.sort_by(f64::total_cmp);
+    let median = quantile(&durations, 0.5).unwrap();
+    println!(
+        "Latency of {} reads: min {:.3}ms, median {:.3}ms, max {:.3}ms",
+        SAMPLES,
+        durations[0] * 1000.0,
+        median * 1000.0,
+        durations[SAMPLES - 1] * 1000.0
+    );
+    if median < 20e-6 {
+        warnings.push(
+            "Reads are suspiciously fast, they may be served from a cache below the \
+             filesystem (e.g. the hypervisor's)"
+                .to_owned(),
+        );
+    }
+}
@@ -0,0 +1,657 @@
+use std::ffi::{OsStr, OsString};
+use std::os::unix::fs::MetadataExt;
+use std::process::exit;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::accept::Accept;
+use crate::aggregator::Aggregator;
+use crate::cifsstats::CifsStatsCollector;
+use crate::config::Config;
+use crate::consul::Consul;
+use crate::control::{Control, Controls};
+use crate::diskstats::DiskStatsCollector;
+use crate::fshealth::FsHealthCollector;
+use crate::fsstats::FsStatsCollector;
+use crate::heartbeat::Heartbeat;
+use crate::metrics::Metrics;
+use crate::mounts::find_mount;
+use crate::mountstats::MountStatsCollector;
+use crate::notify::Notifier;
+use crate::overlay::backing_layer;
+use crate::pool::Pool;
+use crate::scrub::Scrubber;
+use crate::state::State;
+use crate::status::Status;
+use crate::target::Target;
+use crate::{bench, doctor, healthcheck, http, lock, process, push, state, visibility, walk};
+
+/// Start a thread, with a name (truncated to 15 characters by Linux) to find
+/// it in /debug/threads or `top -H`.
+fn spawn<F: FnOnce() + Send + 'static>(name: String, f: F) -> JoinHandle<()> {
+    std::thread::Builder::new().name(name).spawn(f).unwrap()
+}
+
+/// Reply with rendered metrics, or the error, or 404 for an unknown tenant.
+#[cfg(feature = "web")]
+fn metrics_reply(result: Option<Result<Vec<u8>, String>>) -> warp::reply::WithStatus<Vec<u8>> {
+    use warp::http::StatusCode;
+
+    match result {
+        Some(Ok(buffer)) => warp::reply::with_status(buffer, StatusCode::OK),
+        Some(Err(e)) => warp::reply::with_status(e.into_bytes(), StatusCode::BAD_REQUEST),
+        None => warp::reply::with_status(b"Unknown tenant\n".to_vec(), StatusCode::NOT_FOUND),
+    }
+}
+
+/// Run the exporter, with the command line arguments of the process.
+///
+/// Downstream crates can register their own probes with
+/// `prober::register()` before calling this from their `main()`.
+pub fn run() {
+    // Initialize logging
+    pretty_env_logger::init();
+
+    // Subcommands
+    let args: Vec<OsString> = std::env::args_os().skip(1).collect();
+    let subcommand = args.first().map(|a| a.as_os_str());
+    if subcommand == Some(OsStr::new("healthcheck")) {
+        healthcheck::run(args[1..].to_vec());
+    }
+    if subcommand == Some(OsStr::new("doctor")) {
+        doctor::run(args[1..].to_vec());
+    }
+
+    // Parse command line
+    let (config, accept) = if subcommand == Some(OsStr::new("accept")) {
+        let (config, accept) = Accept::from_args(args[1..].to_vec());
+        (config, Some(accept))
+    } else {
+        (Config::from_args(), None)
+    };
+
+    // Set up Prometheus
+    let mut metrics = Metrics::new(
+        &config.metric_prefix,
+        config.regions > 0,
+        config.legacy_metrics,
+        &config.labels,
+    );
+    if config.bench.is_some() {
+        metrics.record_all();
+    }
+    if let Some(window) = config.window {
+        metrics.set_window(
+            window,
+            config
+                .targets
+                .iter()
+                .map(|t| (t.name.as_str(), t.buckets.as_deref())),
+        );
+    }
+
+    // Carry on the counters and histograms from the previous run
+    let signals = if let Some(path) = &config.state_file {
+        if path.exists() {
+            let targets: Vec<String> = config.targets.iter().map(|t| t.name.clone()).collect();
+            match State::load(path, &targets, &config.labels) {
+                Ok(state) => {
+                    info!(
+                        "Restoring state from {:?}, saved {} ago",
+                        path,
+                        humantime::format_duration(Duration::from_secs(
+                            state.saved.elapsed().unwrap_or_default().as_secs()
+                        ))
+                    );
+                    metrics.restore(state);
+                }
+                Err(e) => warn!("{}, starting from 0", e),
+            }
+        }
+        Some(state::block_signals())
+    } else {
+        None
+    };
+    if let Some(window) = config.quantile_window {
+        metrics.set_quantile_window(window);
+    }
+    let metrics = Arc::new(metrics);
+    metrics.register_optional("process", process::ProcessCollector::new(&config.labels));
+    for target in &config.targets {
+        metrics.add_target(&target.name, target.buckets.as_deref());
+        metrics.set_interval(&target.name, target.interval);
+        if !target.thresholds.is_empty() {
+            metrics.set_thresholds(&target.name, &target.thresholds);
+        }
+        if let Some((threshold, objective)) = target.slo {
+            metrics.set_slo(&target.name, threshold, objective);
+        }
+        if let Some(group) = &target.group {
+            metrics.add_to_group(&target.name, group);
+        }
+        if config.correct_omission {
+            metrics.correct_omission(&target.name, target.buckets.as_deref(), target.interval);
+        }
+        if target.microseconds {
+            metrics.add_microseconds(&target.name, target.buckets.as_deref());
+        }
+    }
+    let dropped = prometheus::Counter::with_opts(metrics.opts(
+        "targets_dropped_total",
+        "Number of targets ignored because there were more than --max-targets",
+    ))
+    .unwrap();
+    metrics.register(&dropped);
+    dropped.inc_by(config.dropped_targets as f64);
+
+    // Identify the configuration, to detect drift across instances
+    let config_hash = config.fingerprint();
+    let config_info = prometheus::Gauge::with_opts(
+        metrics
+            .opts(
+                "config_info",
+                "Hash of the effective configuration, always 1",
+            )
+            .const_label("hash", &config_hash),
+    )
+    .unwrap();
+    config_info.set(1.0);
+    metrics.register(&config_info);
+    let config_json = serde_json::json!({
+        "hash": config_hash,
+        "options": config
+            .summary()
+            .into_iter()
+            .map(|(k, v)| (k, v.into()))
+            .collect::<serde_json::Map<_, _>>(),
+    });
+
+    // Keep recent measurements for the dashboard
+    let status = Status::new(
+        config.targets.iter().map(|t| t.name.clone()).collect(),
+        config.summary(),
+        config.keep_samples,
+    );
+
+    // Controls for the API
+    let controls: Controls = Arc::new(
+        config
+            .targets
+            .iter()
+            .map(|t| (t.name.clone(), Arc::new(Control::default())))
+            .collect(),
+    );
+
+    // Receive the metrics pushed by agents
+    let aggregator = if config.aggregator {
+        Some(Arc::new(Aggregator::new(&metrics)))
+    } else {
+        None
+    };
+
+    // One runtime hosts the HTTP server and the background services (the
+    // probes, which block on I/O, keep their own threads)
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .thread_name("runtime")
+        .enable_all()
+        .build()
+        .unwrap();
+
+    // The targets whose metrics are served to each tenant
+    let tenants = Arc::new(config.tenants());
+
+    // Start metrics server (unless we only run a benchmark)
+    #[cfg(feature = "web")]
+    let server = if config.bench.is_none() {
+        use crate::control;
+        use std::collections::HashMap;
+        use warp::Filter;
+
+        let status = status.clone();
+        let controls = controls.clone();
+        let control_api = config.control_api;
+        let aggregator = aggregator.clone();
+        let exporter = metrics.clone();
+        let tenants = tenants.clone();
+        let debug_threads = config.debug_threads;
+        let target_names: Vec<String> = config.targets.iter().map(|t| t.name.clone()).collect();
+        let metrics_addr = config.metrics_addr;
+        Some(rt.spawn(async move {
+            info!("Starting Prometheus HTTP server on {}", metrics_addr);
+
+            let metrics = {
+                let aggregator = aggregator.clone();
+                let exporter = exporter.clone();
+                // Optional query string, with collect[] selecting the collectors
+                let query = warp::query::raw().or(warp::any().map(String::new)).unify();
+                warp::path("metrics").and(query).map(move |query: String| {
+                    metrics_reply(Some(http::render(
+                        &exporter,
+                        aggregator.as_deref(),
+                        &query,
+                        None,
+                    )))
+                })
+            };
+            let tenant_metrics = {
+                let exporter = exporter.clone();
+                let query = warp::query::raw().or(warp::any().map(String::new)).unify();
+                warp::path!("metrics" / String).and(query).map(
+                    move |tenant: String, query: String| {
+                        metrics_reply(http::render_tenant(&exporter, &tenants, &tenant, &query))
+                    },
+                )
+            };
+            let dashboard =
+                warp::path::end().map(|| warp::reply::html(include_str!("dashboard.html")));
+            let status_json = {
+                let status = status.clone();
+                warp::path("status.json").map(move || {
+                    let status = status.lock().unwrap();
+                    warp::reply::json(&status.to_json())
+                })
+            };
+            let config_json = warp::path!("config").map(move || warp::reply::json(&config_json));
+            let healthz = warp::path!("healthz").map(move || {
+                let failing = exporter.failing(&target_names);
+                if failing.is_empty() {
+                    warp::reply::with_status("ok\n".to_owned(), warp::http::StatusCode::OK)
+                } else {
+                    warp::reply::with_status(
+                        format!("Probes failing: {}\n", failing.join(", ")),
+                        warp::http::StatusCode::SERVICE_UNAVAILABLE,
+                    )
+                }
+            });
+            let threads = warp::path!("debug" / "threads")
+                .and(warp::any().and_then(move || {
+                    std::future::ready(if debug_threads {
+                        Ok(())
+                    } else {
+                        Err(warp::reject::not_found())
+                    })
+                }))
+                .untuple_one()
+                .map(|| warp::reply::json(&process::threads_json()));
+            let debug_samples = {
+                let status = status.clone();
+                warp::path!("debug" / "samples").map(move || {
+                    let status = status.lock().unwrap();
+                    warp::reply::json(&status.samples_json())
+                })
+            };
+
+            // Control API, only if enabled
+            let api = warp::path("api").and(
+                warp::any()
+                    .and_then(move || {
+                        std::future::ready(if control_api {
+                            Ok(())
+                        } else {
+                            Err(warp::reject::not_found())
+                        })
+                    })
+                    .untuple_one(),
+            );
+            let api_targets = {
+                let controls = controls.clone();
+                api.and(warp::path!("targets"))
+                    .and(warp::get())
+                    .map(move || {
+                        let status = status.lock().unwrap();
+                        let targets: Vec<_> = controls
+                            .iter()
+                            .map(|(name, control)| {
+                                let mut target = status.target_json(name);
+                                target["paused"] = control.is_paused().into();
+                                target
+                            })
+                            .collect();
+                        warp::reply::json(&targets)
+                    })
+            };
+            let api_action = api
+                .and(warp::path::param::<String>())
+                .and(warp::path::end())
+                .and(warp::post())
+                .and(warp::query::<HashMap<String, String>>())
+                .map(move |action: String, query: HashMap<String, String>| {
+                    let target = query.get("target").map(|t| t.as_str());
+                    match control::apply(&controls, &action, target) {
+                        Some(targets) => warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({ "targets": targets })),
+                            warp::http::StatusCode::OK,
+                        ),
+                        None => warp::reply::with_status(
+                            warp::reply::json(&serde_json::json!({
+                                "error": "Unknown target or action",
+                            })),
+                            warp::http::StatusCode::NOT_FOUND,
+                        ),
+                    }
+                });
+
+            // Metrics pushed by agents, if we are the aggregator
+            let push = warp::path!("push")
+                .and(warp::post())
+                .and(warp::query::<HashMap<String, String>>())
+                .and(warp::body::content_length_limit(16 << 20))
+                .and(warp::body::bytes())
+                .and_then(
+                    move |query: HashMap<String, String>, body: warp::hyper::body::Bytes| {
+                        let result = match &aggregator {
+                            Some(aggregator) => {
+                                let origin = query.get("origin").map(|o| o.as_str()).unwrap_or("");
+                                let text = String::from_utf8(body.to_vec())
+                                    .map_err(|_| "Invalid UTF-8".to_owned());
+                                Ok(text.and_then(|text| aggregator.push(origin, text)).map_err(
+                                    |e| {
+                                        warn!("Invalid push: {}", e);
+                                        e
+                                    },
+                                ))
+                            }
+                            None => Err(warp::reject::not_found()),
+                        };
+                        std::future::ready(result.map(|r| match r {
+                            Ok(()) => warp::reply::with_status(
+                                String::new(),
+                                warp::http::StatusCode::NO_CONTENT,
+                            ),
+                            Err(e) => {
+                                warp::reply::with_status(e, warp::http::StatusCode::BAD_REQUEST)
+                            }
+                        }))
+                    },
+                );
+
+            let routes = tenant_metrics
+                .or(metrics)
+                .or(dashboard)
+                .or(status_json)
+                .or(config_json)
+                .or(healthz)
+                .or(debug_samples)
+                .or(threads)
+                .or(api_targets)
+                .or(api_action)
+                .or(push);
+            warp::serve(routes).run(metrics_addr).await;
+        }))
+    } else {
+        None
+    };
+    #[cfg(not(feature = "web"))]
+    let server = if config.bench.is_none() {
+        let server = http::Server {
+            metrics: metrics.clone(),
+            aggregator: aggregator.clone(),
+            targets: config.targets.iter().map(|t| t.name.clone()).collect(),
+            config_json,
+            tenants: tenants.clone(),
+            tenant: None,
+        };
+        let metrics_addr = config.metrics_addr;
+        Some(rt.spawn_blocking(move || server.run(metrics_addr)))
+    } else {
+        None
+    };
+
+    // Serve the metrics of some tenants on their own address
+    if config.bench.is_none() {
+        for (tenant, addr) in config.tenant_metrics.iter().cloned() {
+            info!("Serving the metrics of tenant {} on {}", tenant, addr);
+            #[cfg(feature = "web")]
+            {
+                use warp::Filter;
+
+                let exporter = metrics.clone();
+                let tenants = tenants.clone();
+                let query = warp::query::raw().or(warp::any().map(String::new)).unify();
+                let route = warp::path!("metrics").and(query).map(move |query: String| {
+                    metrics_reply(http::render_tenant(&exporter, &tenants, &tenant, &query))
+                });
+                rt.spawn(warp::serve(route).run(addr));
+            }
+            #[cfg(not(feature = "web"))]
+            {
+                let server = http::Server {
+                    metrics: metrics.clone(),
+                    aggregator: None,
+                    targets: Vec::new(),
+                    config_json: serde_json::Value::Null,
+                    tenants: tenants.clone(),
+                    tenant: Some(tenant),
+                };
+                rt.spawn_blocking(move || server.run(addr));
+            }
+        }
+    }
+
+    let heartbeat = config
+        .heartbeat_file
+        .clone()
+        .map(|p| Arc::new(Heartbeat::new(p)));
+
+    // Open the targets
+    let mut targets = Vec::new();
+    let mut waiting = Vec::new();
+    for (target, (_, control)) in config.targets.iter().zip(controls.iter()) {
+        match Target::open(
+            target.clone(),
+            config.regions,
+            metrics.clone(),
+            status.clone(),
+            heartbeat.clone(),
+            control.clone(),
+        ) {
+            Ok(t) => targets.push(t),
+            // FUSE daemons are often still connecting to their backend
+            Err(e) if config.wait_for_target || target.fuse.is_some() => {
+                warn!("{}, waiting for it", e);
+                metrics.set_success(&target.name, false);
+                waiting.push((target.clone(), control.clone()));
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        }
+    }
+
+    // Describe the filesystem of each target
+    for target in &config.targets {
+        if let Some(mount) = find_mount(&target.path) {
+            let block_size = std::fs::metadata(&target.path)
+                .map(|m| m.blksize())
+                .unwrap_or(0);
+            metrics.target_info(&target.name, &mount, block_size);
+        }
+        if let Some((layer, path)) = backing_layer(&target.path) {
+            info!(
+                "{:?} is in overlayfs layer {}, {:?}",
+                target.path, layer, path
+            );
+            metrics.overlay_layer(&target.name, &layer, &path);
+        }
+    }
+
+    // Report free space on the filesystems, and the device statistics
+    let mut fs_targets = Vec::new();
+    for target in &config.targets {
+        fs_targets.push((target.name.clone(), target.path.clone()));
+        if let Some(dir) = &target.scratch_dir {
+            fs_targets.push((dir.display().to_string(), dir.clone()));
+        }
+    }
+    metrics.register_optional(
+        "diskstats",
+        DiskStatsCollector::new(
+            fs_targets.iter().map(|(t, p)| (t.clone(), p.as_path())),
+            &metrics,
+        ),
+    );
+    if let Some(collector) = MountStatsCollector::new(
+        fs_targets.iter().map(|(t, p)| (t.clone(), p.as_path())),
+        &metrics,
+    ) {
+        metrics.register_optional("mountstats", collector);
+    }
+    if let Some(collector) = CifsStatsCollector::new(
+        fs_targets.iter().map(|(t, p)| (t.clone(), p.as_path())),
+        &metrics,
+    ) {
+        metrics.register_optional("cifsstats", collector);
+    }
+    if config.fs_health {
+        if let Some(collector) = FsHealthCollector::new(
+            fs_targets.iter().map(|(t, p)| (t.clone(), p.as_path())),
+            &metrics,
+        ) {
+            metrics.register_optional("fshealth", collector);
+        }
+    }
+    metrics.register_optional("filesystem", FsStatsCollector::new(fs_targets, &metrics));
+
+    // Start the scrub threads
+    for target in &config.targets {
+        if let Some(manifest) = &target.scrub_manifest {
+            let scrubber = Scrubber::new(
+                target.path.clone(),
+                manifest.clone(),
+                target.scrub_rate,
+                target.name.clone(),
+                target.resolve.clone(),
+                &metrics,
+            );
+            let metrics = metrics.clone();
+            spawn(format!("scrub {}", target.name), move || {
+                scrubber.run(&metrics)
+            });
+        }
+    }
+
+    // Start the visibility probes
+    if let Some(path) = config.visibility_write.clone() {
+        let metrics = metrics.clone();
+        let interval = config.interval();
+        spawn("visibility-write".to_owned(), move || {
+            visibility::run_writer(path, interval, &metrics)
+        });
+    }
+    if let Some(path) = config.visibility_read.clone() {
+        let metrics = metrics.clone();
+        let poll = config.visibility_poll;
+        spawn("visibility-read".to_owned(), move || {
+            visibility::run_reader(path, poll, &metrics)
+        });
+    }
+
+    // Start the lock probe
+    if let Some(path) = config.lock_file.clone() {
+        let metrics = metrics.clone();
+        let interval = config.interval();
+        let hold = config.lock_hold;
+        spawn("lock".to_owned(), move || {
+            lock::run(path, interval, hold, &metrics)
+        });
+    }
+
+    // Start the tree walk probe
+    if let Some(path) = config.walk_dir.clone() {
+        let metrics = metrics.clone();
+        let interval = config.walk_interval;
+        let max_depth = config.walk_max_depth;
+        let max_entries = config.walk_max_entries;
+        spawn("walk".to_owned(), move || {
+            walk::run(path, interval, max_depth, max_entries, &metrics)
+        });
+    }
+
+    // Run the probes, each target in its own thread, or scheduled from its
+    // own thread on the workers
+    let pool =
+        (config.workers > 0).then(|| Pool::new(config.workers, config.queue_depth, &metrics));
+    let run = move |target: Target, pool: Option<Arc<Pool>>| match pool {
+        Some(pool) => target.run_in(&pool),
+        None => target.run(),
+    };
+    let mut handles: Vec<_> = targets
+        .into_iter()
+        .map(|t| {
+            let pool = pool.clone();
+            spawn(format!("probe {}", t.name()), move || run(t, pool))
+        })
+        .collect();
+    for (target, control) in waiting {
+        let regions = config.regions;
+        let metrics = metrics.clone();
+        let status = status.clone();
+        let heartbeat = heartbeat.clone();
+        let pool = pool.clone();
+        handles.push(spawn(format!("probe {}", target.name), move || {
+            let target =
+                Target::open_with_retry(target, regions, metrics, status, heartbeat, control);
+            run(target, pool)
+        }));
+    }
+
+    // Save the state periodically and on shutdown
+    if let (Some(path), Some(signals)) = (config.state_file.clone(), signals) {
+        let interval = config.state_interval;
+        let metrics = metrics.clone();
+        rt.spawn_blocking(move || state::run(path, interval, signals, &metrics));
+    }
+
+    // Push the metrics to the aggregator
+    if let Some(url) = config.push_to.clone() {
+        let origin = config.origin.clone();
+        let interval = config.push_interval;
+        let metrics = metrics.clone();
+        rt.spawn(push::run(url, origin, interval, metrics));
+    }
+
+    // Register in Consul
+    if let Some(url) = &config.consul {
+        let consul = Consul::new(
+            url,
+            config.consul_service.clone(),
+            &config.origin,
+            config.metrics_addr,
+            &config.labels,
+        );
+        let targets = config.targets.iter().map(|t| t.name.clone()).collect();
+        let metrics = metrics.clone();
+        rt.spawn(consul.run(targets, metrics));
+    }
+
+    // Send notifications when targets are in trouble
+    if let Some(notifier) = Notifier::new(&config, &metrics) {
+        let targets = config.targets.iter().map(|t| t.name.clone()).collect();
+        let status = status.clone();
+        rt.spawn(notifier.run(targets, status));
+    }
+
+    // Print the report at the end of the benchmark
+    if let Some(duration) = config.bench {
+        std::thread::sleep(duration);
+        let recorded = metrics.take_recorded();
+        if let Some(accept) = accept {
+            accept.finish(&config.targets, &recorded, &metrics, duration);
+        }
+        let report = bench::report(&config.targets, &recorded, duration);
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        exit(0);
+    }
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    // Keep serving metrics even with no targets, in aggregator mode
+    if let Some(server) = server {
+        rt.block_on(server).unwrap();
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// fio options that we translate.
+const SUPPORTED: &[&str] = &[
+    "filename",
+    "directory",
+    "rw",
+    "readwrite",
+    "rwmixread",
+    "rwmixwrite",
+    "bs",
+    "blocksize",
+    "rate_iops",
+];
+
+/// fio options that don't change what we measure, and can be ignored.
+const IGNORED: &[&str] = &[
+    "name",
+    "description",
+    "ioengine",
+    "direct",
+    "iodepth",
+    "numjobs",
+    "size",
+    "runtime",
+    "time_based",
+    "ramp_time",
+    "group_reporting",
+    "randrepeat",
+    "norandommap",
+    "invalidate",
+];
+
+/// A job read from a fio job file.
+pub struct Job {
+    pub name: String,
+    pub path: PathBuf,
+    /// Options for the target, as they would be given on the command line
+    pub options: Vec<(&'static str, String)>,
+}
+
+/// Turn the fio options of a job into our options.
+fn translate(name: &str, options: &HashMap<String, String>) -> Result<Job, String> {
+    for key in options.keys() {
+        if !SUPPORTED.contains(&key.as_str()) && !IGNORED.contains(&key.as_str()) {
+            warn!("Ignoring unsupported fio option {} in job {}", key, name);
+        }
+    }
+
+    let rw = options
+        .get("rw")
+        .or_else(|| options.get("readwrite"))
+        .map(|s| s.as_str())
+        .unwrap_or("read");
+    let rw = rw.split(':').next().unwrap();
+    let ops = match rw {
+        "read" | "randread" => "read".to_owned(),
+        "write" | "randwrite" => "write".to_owned(),
+        "rw" | "readwrite" | "randrw" => {
+            let read: u32 = match (options.get("rwmixread"), options.get("rwmixwrite")) {
+                (Some(read), _) => read.parse().ok().filter(|&r| r <= 100),
+                (None, Some(write)) => write
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|&w| w <= 100)
+                    .map(|w| 100 - w),
+                (None, None) => Some(50),
+            }
+            .ok_or_else(|| format!("Invalid rwmixread in fio job {}", name))?;
+            format!("read={},write={}", read, 100 - read)
+        }
+        _ => return Err(format!("Unsupported rw={} in fio job {}", rw, name)),
+    };
+    let directory = options.get("directory").map(PathBuf::from);
+    let path = match (options.get("filename"), &directory) {
+        (Some(filename), Some(dir)) => dir.join(filename),
+        (Some(filename), None) => PathBuf::from(filename),
+        // Without a filename, fio uses a file named after the job, which we
+        // can only use for writes (we don't lay out files to read)
+        (None, Some(dir)) if ops == "write" => dir.join(format!("{}.0.0", name)),
+        (None, _) => return Err(format!("fio job {} has no filename", name)),
+    };
+
+    let mut result = vec![("ops", ops.clone())];
+    if ops.contains("write") {
+        // Writes go to a scratch file, next to the file we read
+        let scratch_dir = directory
+            .or_else(|| path.parent().map(Path::to_owned))
+            .filter(|d| !d.as_os_str().is_empty())
+            .unwrap_or_else(|| PathBuf::from("."));
+        result.push(("scratch-dir", scratch_dir.display().to_string()));
+    }
+    if let Some(bs) = options.get("bs").or_else(|| options.get("blocksize")) {
+        // fio sizes are powers of 1024, with or without a B, and can be
+        // given separately for reads and writes
+        let bs = bs.split(',').next().unwrap();
+        result.push(("read-size", bs.trim_end_matches(['b', 'B']).to_uppercase()));
+    }
+    if let Some(rate) = options.get("rate_iops") {
+        let rate: f64 = rate
+            .parse()
+            .ok()
+            .filter(|&r| r > 0.0)
+            .ok_or_else(|| format!("Invalid rate_iops in fio job {}", name))?;
+        result.push(("interval", (1.0 / rate).to_string()));
+    }
+    Ok(Job {
+        name: name.to_owned(),
+        path,
+        options: result,
+    })
+}
+
+/// Read the jobs from a fio job file.
+///
+/// Only a subset of the syntax is supported: sections, with the options in
+/// `[global]` applying to all jobs, and the options which map to ours
+/// (`filename`, `directory`, `rw`, `rwmixread`, `bs`, `rate_iops`).
+pub fn load_job_file(filename: &Path) -> Result<Vec<Job>, String> {
+    let content = std::fs::read_to_string(filename)
+        .map_err(|e| format!("Can't read {:?}: {}", filename, e))?;
+    let mut global = HashMap::new();
+    let mut sections: Vec<(String, HashMap<String, String>)> = Vec::new();
+    for (lineno, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            sections.push((section.trim().to_owned(), HashMap::new()));
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => (line, ""),
+        };
+        match sections.last_mut() {
+            Some((name, options)) if name != "global" => options,
+            Some(_) => &mut global,
+            None => {
+                return Err(format!(
+                    "Option outside of a section ({}:{})",
+                    filename.display(),
+                    lineno + 1
+                ))
+            }
+        }
+        .insert(key.to_owned(), value.to_owned());
+    }
+
+    let mut jobs = Vec::new();
+    for (name, options) in sections {
+        if name == "global" {
+            continue;
+        }
+        let mut merged = global.clone();
+        merged.extend(options);
+        jobs.push(translate(&name, &merged)?);
+    }
+    if jobs.is_empty() {
+        return Err(format!("No jobs in {:?}", filename));
+    }
+    Ok(jobs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::load_job_file;
+
+    #[test]
+    fn job_file() {
+        let path = std::env::temp_dir().join(format!("fs-latency-fio-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            "; comment\n[global]\ndirectory=/data\nbs=64k\n\n\
+             [reads]\nfilename=file1\nrw=randread\nrate_iops=10\n\n\
+             [mixed]\nfilename=/mnt/file2\nrw=randrw:4\nrwmixwrite=30\ntime_based\n\n\
+             [writes]\nrw=write\n",
+        )
+        .unwrap();
+        let jobs = load_job_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        let jobs = jobs.unwrap();
+        let jobs: Vec<_> = jobs
+            .iter()
+            .map(|j| (j.name.as_str(), j.path.clone(), j.options.clone()))
+            .collect();
+        let options = |o: &[(&'static str, &str)]| -> Vec<(&'static str, String)> {
+            o.iter().map(|&(k, v)| (k, v.to_owned())).collect()
+        };
+        assert_eq!(
+            jobs,
+            [
+                (
+                    "reads",
+                    PathBuf::from("/data/file1"),
+                    options(&[("ops", "read"), ("read-size", "64K"), ("interval", "0.1")])
+                ),
+                (
+                    "mixed",
+                    PathBuf::from("/mnt/file2"),
+                    options(&[
+                        ("ops", "read=70,write=30"),
+                        ("scratch-dir", "/data"),
+                        ("read-size", "64K")
+                    ])
+                ),
+                (
+                    "writes",
+                    PathBuf::from("/data/writes.0.0"),
+                    options(&[
+                        ("ops", "write"),
+                        ("scratch-dir", "/data"),
+                        ("read-size", "64K")
+                    ])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_job_files() {
+        let load = |content: &str| {
+            let path =
+                std::env::temp_dir().join(format!("fs-latency-fio-invalid-{}", std::process::id()));
+            std::fs::write(&path, content).unwrap();
+            let jobs = load_job_file(&path);
+            std::fs::remove_file(&path).unwrap();
+            jobs.map(|_| ())
+        };
+        assert!(load("filename=file\n").is_err());
+        assert!(load("[global]\nfilename=file\n").is_err());
+        assert!(load("[job]\nrw=read\n").is_err());
+        assert!(load("[job]\nfilename=file\nrw=trim\n").is_err());
+        assert!(load("[job]\nfilename=file\nrw=rw\nrwmixread=150\n").is_err());
+    }
+}
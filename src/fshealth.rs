@@ -0,0 +1,286 @@
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{CounterVec, GaugeVec};
+use std::fs::{read_dir, read_to_string, File};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::channel;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::metrics::{set_counter, Metrics};
+use crate::mounts::find_mount;
+
+/// How long `zpool status` can take before we give up on it, since a
+/// suspended pool can make it hang.
+const ZPOOL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// BTRFS_IOC_FS_INFO, `_IOR(0x94, 31, struct btrfs_ioctl_fs_info_args)`
+const BTRFS_IOC_FS_INFO: libc::c_ulong = 0x8400941f;
+
+/// BTRFS_IOC_SCRUB_PROGRESS, `_IOWR(0x94, 29, struct btrfs_ioctl_scrub_args)`
+const BTRFS_IOC_SCRUB_PROGRESS: libc::c_ulong = 0xc400941d;
+
+/// BTRFS_IOC_BALANCE_PROGRESS, `_IOR(0x94, 34, struct btrfs_ioctl_balance_args)`
+const BTRFS_IOC_BALANCE_PROGRESS: libc::c_ulong = 0x84009422;
+
+/// Start of `struct btrfs_ioctl_fs_info_args` (1024 bytes in all).
+#[repr(C)]
+struct FsInfoArgs {
+    max_id: u64,
+    num_devices: u64,
+    fsid: [u8; 16],
+    rest: [u8; 992],
+}
+
+const _: () = assert!(std::mem::size_of::<FsInfoArgs>() == 1024);
+
+#[derive(Clone)]
+enum Filesystem {
+    Zfs { pool: String },
+    Btrfs { path: PathBuf },
+}
+
+/// Collects the health of the ZFS pools and btrfs filesystems the targets
+/// are on, and whether a scrub, resilver or balance is running, at scrape
+/// time. Those explain most latency excursions on these filesystems.
+#[derive(Clone)]
+pub struct FsHealthCollector {
+    targets: Vec<(String, Filesystem)>,
+    zfs_pool: GaugeVec,
+    btrfs: GaugeVec,
+    btrfs_errors: CounterVec,
+}
+
+impl FsHealthCollector {
+    /// Create the collector, for the targets that are on ZFS or btrfs.
+    /// Returns None if there are none.
+    pub fn new<'a, I: IntoIterator<Item = (String, &'a Path)>>(
+        targets: I,
+        metrics: &Metrics,
+    ) -> Option<FsHealthCollector> {
+        let mut found = Vec::new();
+        for (target, path) in targets {
+            let mount = match find_mount(path) {
+                Some(m) => m,
+                None => continue,
+            };
+            let filesystem = match mount.fstype.as_str() {
+                "zfs" => {
+                    let pool = mount.source.split('/').next().unwrap().to_owned();
+                    info!("{:?} is on ZFS pool {}", path, pool);
+                    Filesystem::Zfs { pool }
+                }
+                "btrfs" => {
+                    info!("{:?} is on btrfs {:?}", path, mount.mount_point);
+                    Filesystem::Btrfs {
+                        path: path.to_owned(),
+                    }
+                }
+                _ => continue,
+            };
+            found.push((target, filesystem));
+        }
+        if found.is_empty() {
+            return None;
+        }
+
+        Some(FsHealthCollector {
+            targets: found,
+            zfs_pool: GaugeVec::new(
+                metrics.opts(
+                    "zfs_pool_info",
+                    "State of the ZFS pool of the target, and the scan running on it",
+                ),
+                &["target", "pool", "state", "scan"],
+            )
+            .unwrap(),
+            btrfs: GaugeVec::new(
+                metrics.opts(
+                    "btrfs_info",
+                    "Whether a scrub or balance is running on the btrfs filesystem of the target, and its exclusive operation",
+                ),
+                &["target", "uuid", "scrub", "balance", "exclusive_operation"],
+            )
+            .unwrap(),
+            btrfs_errors: CounterVec::new(
+                metrics.opts(
+                    "btrfs_device_errors_total",
+                    "Number of errors of the devices of the btrfs filesystem of the target, by type",
+                ),
+                &["target", "device", "type"],
+            )
+            .unwrap(),
+        })
+    }
+
+    fn collect_zfs(&self, target: &str, pool: &str) {
+        let state = read_to_string(format!("/proc/spl/kstat/zfs/{}/state", pool))
+            .map(|s| s.trim().to_owned())
+            .unwrap_or_else(|_| "unknown".to_owned());
+        self.zfs_pool
+            .with_label_values(&[target, pool, &state, zpool_scan(pool)])
+            .set(1.0);
+    }
+
+    fn collect_btrfs(&self, target: &str, path: &Path) {
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) => {
+                warn!("Can't open {:?}: {}", path, e);
+                return;
+            }
+        };
+        let fd = file.as_raw_fd();
+        let mut fs_info: FsInfoArgs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(fd, BTRFS_IOC_FS_INFO, &mut fs_info) } != 0 {
+            warn!(
+                "Can't get btrfs information of {:?}: {}",
+                path,
+                std::io::Error::last_os_error()
+            );
+            return;
+        }
+        let uuid = format_uuid(&fs_info.fsid);
+
+        // A scrub runs on each device separately, and needs CAP_SYS_ADMIN
+        let mut scrub = "idle";
+        for devid in 1..=fs_info.max_id {
+            let mut args = [0u64; 128];
+            args[0] = devid;
+            if unsafe { libc::ioctl(fd, BTRFS_IOC_SCRUB_PROGRESS, args.as_mut_ptr()) } == 0 {
+                scrub = "running";
+                break;
+            }
+            match std::io::Error::last_os_error().raw_os_error() {
+                Some(libc::ENOTCONN) | Some(libc::ENODEV) => {}
+                _ => {
+                    scrub = "unknown";
+                    break;
+                }
+            }
+        }
+        let mut args = [0u64; 128];
+        let balance =
+            if unsafe { libc::ioctl(fd, BTRFS_IOC_BALANCE_PROGRESS, args.as_mut_ptr()) } == 0 {
+                "running"
+            } else if std::io::Error::last_os_error().raw_os_error() == Some(libc::ENOTCONN) {
+                "idle"
+            } else {
+                "unknown"
+            };
+
+        let sysfs = Path::new("/sys/fs/btrfs").join(&uuid);
+        let exclusive = read_to_string(sysfs.join("exclusive_operation"))
+            .map(|s| s.trim().to_owned())
+            .unwrap_or_else(|_| "unknown".to_owned());
+        self.btrfs
+            .with_label_values(&[target, &uuid, scrub, balance, &exclusive])
+            .set(1.0);
+
+        // Error counters of each device, in devinfo/DEVID/error_stats
+        let devices = match read_dir(sysfs.join("devinfo")) {
+            Ok(d) => d,
+            Err(_) => return,
+        };
+        for device in devices.flatten() {
+            let stats = match read_to_string(device.path().join("error_stats")) {
+                Ok(s) => s,
+                Err(_) => continue,
+            };
+            let devid = device.file_name().to_string_lossy().into_owned();
+            for line in stats.lines() {
+                if let Some((kind, value)) = line.split_once(' ') {
+                    if let Ok(value) = value.trim().parse() {
+                        set_counter(
+                            &self.btrfs_errors.with_label_values(&[target, &devid, kind]),
+                            value,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Which scan is running on a ZFS pool, from `zpool status`: "scrub",
+/// "resilver", "none", or "unknown" if it can't be run.
+fn zpool_scan(pool: &str) -> &'static str {
+    let child = Command::new("zpool")
+        .args(["status", "-p", pool])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    let child = match child {
+        Ok(c) => c,
+        Err(_) => return "unknown",
+    };
+    let pid = child.id() as libc::pid_t;
+    let (send, receive) = channel();
+    std::thread::spawn(move || {
+        let _ = send.send(child.wait_with_output());
+    });
+    let output = match receive.recv_timeout(ZPOOL_TIMEOUT) {
+        Ok(Ok(output)) if output.status.success() => output.stdout,
+        Ok(_) => return "unknown",
+        Err(_) => {
+            // Killing it also lets the thread waiting for it finish
+            warn!("zpool status {} is taking too long, killing it", pool);
+            unsafe { libc::kill(pid, libc::SIGKILL) };
+            return "unknown";
+        }
+    };
+    let output = String::from_utf8_lossy(&output);
+    let scan = output
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("scan:"))
+        .unwrap_or("");
+    if scan.contains("scrub in progress") {
+        "scrub"
+    } else if scan.contains("resilver in progress") {
+        "resilver"
+    } else {
+        "none"
+    }
+}
+
+fn format_uuid(bytes: &[u8; 16]) -> String {
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+impl Collector for FsHealthCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        let mut descs = Vec::new();
+        descs.extend(self.zfs_pool.desc());
+        descs.extend(self.btrfs.desc());
+        descs.extend(self.btrfs_errors.desc());
+        descs
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.zfs_pool.reset();
+        self.btrfs.reset();
+        for (target, filesystem) in &self.targets {
+            match filesystem {
+                Filesystem::Zfs { pool } => self.collect_zfs(target, pool),
+                Filesystem::Btrfs { path } => self.collect_btrfs(target, path),
+            }
+        }
+
+        let mut families = Vec::new();
+        families.extend(self.zfs_pool.collect());
+        families.extend(self.btrfs.collect());
+        families.extend(self.btrfs_errors.collect());
+        families
+    }
+}
@@ -0,0 +1,88 @@
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::GaugeVec;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::metrics::Metrics;
+
+/// Call statvfs(3) on a path.
+pub fn statvfs(path: &Path) -> std::io::Result<libc::statvfs> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(stat)
+}
+
+/// Collects free space and inodes of the filesystem of each target, at
+/// scrape time.
+#[derive(Clone)]
+pub struct FsStatsCollector {
+    targets: Vec<(String, PathBuf)>,
+    avail: GaugeVec,
+    size: GaugeVec,
+    files: GaugeVec,
+    files_free: GaugeVec,
+}
+
+impl FsStatsCollector {
+    pub fn new(targets: Vec<(String, PathBuf)>, metrics: &Metrics) -> FsStatsCollector {
+        let gauge =
+            |name: &str, help: &str| GaugeVec::new(metrics.opts(name, help), &["target"]).unwrap();
+        FsStatsCollector {
+            targets,
+            avail: gauge(
+                "filesystem_avail_bytes",
+                "Filesystem space available to non-root users in bytes",
+            ),
+            size: gauge("filesystem_size_bytes", "Filesystem size in bytes"),
+            files: gauge("filesystem_files", "Filesystem total file nodes"),
+            files_free: gauge("filesystem_files_free", "Filesystem free file nodes"),
+        }
+    }
+}
+
+impl Collector for FsStatsCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        let mut descs = Vec::new();
+        descs.extend(self.avail.desc());
+        descs.extend(self.size.desc());
+        descs.extend(self.files.desc());
+        descs.extend(self.files_free.desc());
+        descs
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        for (target, path) in &self.targets {
+            match statvfs(path) {
+                Ok(stat) => {
+                    let block_size = stat.f_frsize as f64;
+                    self.avail
+                        .with_label_values(&[target])
+                        .set(stat.f_bavail as f64 * block_size);
+                    self.size
+                        .with_label_values(&[target])
+                        .set(stat.f_blocks as f64 * block_size);
+                    self.files
+                        .with_label_values(&[target])
+                        .set(stat.f_files as f64);
+                    self.files_free
+                        .with_label_values(&[target])
+                        .set(stat.f_ffree as f64);
+                }
+                Err(e) => warn!("Can't statvfs {:?}: {}", path, e),
+            }
+        }
+
+        let mut families = Vec::new();
+        families.extend(self.avail.collect());
+        families.extend(self.size.collect());
+        families.extend(self.files.collect());
+        families.extend(self.files_free.collect());
+        families
+    }
+}
@@ -0,0 +1,62 @@
+use prometheus::{GaugeVec, Opts};
+use std::collections::HashMap;
+
+use crate::series::Series;
+
+/// How much of the average a new measurement makes up.
+const SMOOTHING: f64 = 0.1;
+
+/// Groups of targets which reach the same data over different paths (e.g.
+/// the same export mounted through two interfaces or two protocols), whose
+/// latencies are compared.
+pub struct Groups {
+    /// The group of each target which is in one
+    members: HashMap<String, String>,
+    /// Moving average of the latency, by group and operation, then target
+    averages: Series<HashMap<String, f64>>,
+    difference: GaugeVec,
+}
+
+impl Groups {
+    pub fn new(opts: Opts) -> Groups {
+        Groups {
+            members: HashMap::new(),
+            averages: Series::default(),
+            difference: GaugeVec::new(opts, &["group", "operation"]).unwrap(),
+        }
+    }
+
+    pub fn gauge(&self) -> &GaugeVec {
+        &self.difference
+    }
+
+    pub fn add(&mut self, target: &str, group: &str) {
+        self.members.insert(target.to_owned(), group.to_owned());
+    }
+
+    /// Update the average latency of a target, and the difference between
+    /// the fastest and slowest targets of its group.
+    pub fn observe(&mut self, operation: &str, target: &str, duration: f64) {
+        let group = match self.members.get(target) {
+            Some(g) => g,
+            None => return,
+        };
+        let averages = self.averages.get_or_default(group, operation);
+        match averages.get_mut(target) {
+            Some(average) => *average += SMOOTHING * (duration - *average),
+            None => {
+                averages.insert(target.to_owned(), duration);
+            }
+        }
+
+        // Nothing to compare until two targets of the group were measured
+        if averages.len() < 2 {
+            return;
+        }
+        let min = averages.values().cloned().fold(f64::INFINITY, f64::min);
+        let max = averages.values().cloned().fold(0.0, f64::max);
+        self.difference
+            .with_label_values(&[group, operation])
+            .set(max - min);
+    }
+}
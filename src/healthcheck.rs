@@ -0,0 +1,57 @@
+use std::ffi::OsString;
+use std::net::SocketAddr;
+use std::process::exit;
+
+use crate::client::{request, Url};
+
+const USAGE: &str = "\
+Usage: fs-latency-exporter healthcheck [--metrics ADDRESS]
+Exits with status 0 if the exporter at ADDRESS (default: 127.0.0.1:8080)
+reports that all its probes succeed, 1 otherwise.";
+
+/// Query the /healthz endpoint of a running exporter, and exit accordingly.
+pub fn run(args: Vec<OsString>) -> ! {
+    let mut address: SocketAddr = ([127, 0, 0, 1], 8080).into();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--help") => {
+                println!("{}", USAGE);
+                exit(0);
+            }
+            Some("--metrics") => match args.next().and_then(|v| v.to_str()?.parse().ok()) {
+                Some(a) => address = a,
+                None => {
+                    eprintln!("Invalid value for --metrics");
+                    exit(2);
+                }
+            },
+            _ => {
+                eprintln!("{}", USAGE);
+                exit(2);
+            }
+        }
+    }
+
+    // The exporter usually listens on all addresses
+    if address.ip().is_unspecified() {
+        address.set_ip([127, 0, 0, 1].into());
+    }
+    match request(
+        &Url::address(address.to_string()),
+        "GET",
+        "/healthz",
+        &[],
+        b"",
+    ) {
+        Ok((200, _)) => exit(0),
+        Ok((code, body)) => {
+            eprintln!("Unhealthy ({}): {}", code, body.trim());
+            exit(1);
+        }
+        Err(e) => {
+            eprintln!("Can't reach exporter at {}: {}", address, e);
+            exit(1);
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// A file updated with the current time after every successful round of
+/// probes, for external watchdogs.
+pub struct Heartbeat {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl Heartbeat {
+    pub fn new(path: PathBuf) -> Heartbeat {
+        Heartbeat {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn beat(&self) {
+        let _guard = self.lock.lock().unwrap();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64();
+
+        // Write to a temporary file, then rename it into place, so readers
+        // never see a partial file
+        let mut temp_path = self.path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let result = std::fs::File::create(&temp_path)
+            .and_then(|mut file| writeln!(file, "{:.3}", timestamp))
+            .and_then(|()| std::fs::rename(&temp_path, &self.path));
+        if let Err(e) = result {
+            warn!("Can't write heartbeat file {:?}: {}", self.path, e);
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use prometheus::Encoder;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::aggregator::Aggregator;
+use crate::metrics::Metrics;
+
+/// Largest body accepted on /push.
+const MAX_BODY: usize = 16 << 20;
+
+/// Largest request line and headers accepted.
+const MAX_HEAD: u64 = 64 << 10;
+
+/// How many connections are answered at once, the others being closed right
+/// away.
+const MAX_CONNECTIONS: usize = 64;
+
+/// Render the metrics in the text format, with the collectors selected by
+/// `collect[]` in the query string, and those pushed by agents if we are the
+/// aggregator. With `targets`, for a tenant, only the series of those
+/// targets are rendered.
+pub fn render(
+    metrics: &Metrics,
+    aggregator: Option<&Aggregator>,
+    query: &str,
+    targets: Option<&[String]>,
+) -> Result<Vec<u8>, String> {
+    let collect: Vec<String> = query
+        .split('&')
+        .filter_map(|p| p.split_once('='))
+        .filter(|(k, _)| *k == "collect[]" || *k == "collect%5B%5D")
+        .map(|(_, v)| v.to_owned())
+        .collect();
+    let mut metric_families = metrics.gather(&collect)?;
+    if let Some(targets) = targets {
+        for family in &mut metric_families {
+            family.mut_metric().retain(|m| {
+                m.get_label()
+                    .iter()
+                    .any(|l| l.get_name() == "target" && targets.iter().any(|t| t == l.get_value()))
+            });
+        }
+        metric_families.retain(|f| !f.get_metric().is_empty());
+    }
+    let mut buffer = Vec::new();
+    let encoder = prometheus::TextEncoder::new();
+    encoder.encode(&metric_families, &mut buffer).unwrap();
+    Ok(match aggregator {
+        Some(aggregator) => aggregator
+            .render(&String::from_utf8(buffer).unwrap())
+            .into_bytes(),
+        None => buffer,
+    })
+}
+
+/// Render the metrics of a tenant, from /metrics/TENANT or its own address.
+pub fn render_tenant(
+    metrics: &Metrics,
+    tenants: &HashMap<String, Vec<String>>,
+    tenant: &str,
+    query: &str,
+) -> Option<Result<Vec<u8>, String>> {
+    let targets = tenants.get(tenant)?;
+    Some(render(metrics, None, query, Some(targets)))
+}
+
+/// A minimal HTTP server, used instead of warp when built without the `web`
+/// feature: it only serves /metrics (and /metrics/TENANT), /healthz, /config
+/// and /push, which is all Prometheus and the agents need.
+pub struct Server {
+    pub metrics: Arc<Metrics>,
+    pub aggregator: Option<Arc<Aggregator>>,
+    pub targets: Vec<String>,
+    pub config_json: serde_json::Value,
+    pub tenants: Arc<HashMap<String, Vec<String>>>,
+    /// Only serve the metrics of this tenant, on its own address
+    pub tenant: Option<String>,
+}
+
+type Response = (u16, &'static str, Vec<u8>);
+
+/// Counts a connection as open until dropped.
+struct Open(Arc<AtomicUsize>);
+
+impl Drop for Open {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Server {
+    /// Serve forever, answering each connection from its own thread, up to
+    /// `MAX_CONNECTIONS` at once.
+    pub fn run(self, addr: SocketAddr) {
+        let listener = match TcpListener::bind(addr) {
+            Ok(l) => l,
+            Err(e) => {
+                eprintln!("Can't listen on {}: {}", addr, e);
+                std::process::exit(1);
+            }
+        };
+        info!("Listening on http://{}", addr);
+        let server = Arc::new(self);
+        let connections = Arc::new(AtomicUsize::new(0));
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("Can't accept connection: {}", e);
+                    continue;
+                }
+            };
+            if connections.fetch_add(1, Ordering::Relaxed) >= MAX_CONNECTIONS {
+                connections.fetch_sub(1, Ordering::Relaxed);
+                warn!(
+                    "Closing connection from {:?}, already answering {} connections",
+                    stream.peer_addr().ok(),
+                    MAX_CONNECTIONS
+                );
+                continue;
+            }
+            let open = Open(connections.clone());
+            let server = server.clone();
+            std::thread::spawn(move || {
+                let _open = open;
+                if let Err(e) = server.handle(stream) {
+                    debug!("Error handling request: {}", e);
+                }
+            });
+        }
+    }
+
+    fn handle(&self, stream: TcpStream) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+        stream.set_write_timeout(Some(Duration::from_secs(30)))?;
+        let mut reader = BufReader::new(stream);
+
+        // Read the request line and the headers we need, up to MAX_HEAD
+        let mut head = reader.by_ref().take(MAX_HEAD);
+        let mut line = String::new();
+        head.read_line(&mut line)?;
+        let mut words = line.split_whitespace();
+        let (method, target) = match (words.next(), words.next()) {
+            (Some(m), Some(t)) => (m.to_owned(), t.to_owned()),
+            _ => return Ok(()),
+        };
+        let mut length = 0;
+        loop {
+            line.clear();
+            if head.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        let truncated = head.limit() == 0;
+
+        let (code, content_type, body) = if truncated {
+            (431, "text/plain", b"Headers too large\n".to_vec())
+        } else if length > MAX_BODY {
+            (413, "text/plain", b"Body too large\n".to_vec())
+        } else {
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body)?;
+            let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+            self.respond(&method, path, query, body)
+        };
+
+        let mut stream = reader.into_inner();
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            code,
+            reason(code),
+            content_type,
+            body.len()
+        )?;
+        stream.write_all(&body)?;
+        stream.flush()
+    }
+
+    fn respond(&self, method: &str, path: &str, query: &str, body: Vec<u8>) -> Response {
+        let text = |code, message: String| (code, "text/plain", message.into_bytes());
+        let metrics = |result| match result {
+            Some(Ok(buffer)) => (200, "text/plain; version=0.0.4", buffer),
+            Some(Err(e)) => text(400, e),
+            None => text(404, "Unknown tenant\n".to_owned()),
+        };
+        if let Some(tenant) = &self.tenant {
+            return match (method, path) {
+                ("GET", "/metrics") => {
+                    metrics(render_tenant(&self.metrics, &self.tenants, tenant, query))
+                }
+                _ => text(404, "Not found\n".to_owned()),
+            };
+        }
+        match (method, path) {
+            ("GET", "/metrics") => metrics(Some(render(
+                &self.metrics,
+                self.aggregator.as_deref(),
+                query,
+                None,
+            ))),
+            ("GET", path) if path.starts_with("/metrics/") => metrics(render_tenant(
+                &self.metrics,
+                &self.tenants,
+                &path["/metrics/".len()..],
+                query,
+            )),
+            ("GET", "/healthz") => {
+                let failing = self.metrics.failing(&self.targets);
+                if failing.is_empty() {
+                    text(200, "ok\n".to_owned())
+                } else {
+                    text(503, format!("Probes failing: {}\n", failing.join(", ")))
+                }
+            }
+            ("GET", "/config") => (
+                200,
+                "application/json",
+                self.config_json.to_string().into_bytes(),
+            ),
+            ("POST", "/push") if self.aggregator.is_some() => {
+                let origin = query
+                    .split('&')
+                    .find_map(|p| p.strip_prefix("origin="))
+                    .map(decode)
+                    .unwrap_or_default();
+                let result = String::from_utf8(body)
+                    .map_err(|_| "Invalid UTF-8".to_owned())
+                    .and_then(|text| self.aggregator.as_ref().unwrap().push(&origin, text));
+                match result {
+                    Ok(()) => (204, "text/plain", Vec::new()),
+                    Err(e) => {
+                        warn!("Invalid push: {}", e);
+                        text(400, e)
+                    }
+                }
+            }
+            _ => text(404, "Not found\n".to_owned()),
+        }
+    }
+}
+
+/// Decode a percent-encoded value from a query string.
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn reason(code: u16) -> &'static str {
+    match code {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Not Found",
+    }
+}
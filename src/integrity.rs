@@ -0,0 +1,143 @@
+use rand::RngCore;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::checksum::{fnv1a, FNV_OFFSET};
+use crate::direct::{AlignedBuffer, Resolve};
+use crate::metrics::Metrics;
+use crate::prober::{ProbeResult, Prober};
+use crate::scratch;
+
+const MAGIC: &[u8; 8] = b"FSLATCHK";
+pub const BLOCK_SIZE: usize = 4096;
+
+/// Checksum of a block, skipping the checksum field itself (bytes 24..32).
+fn block_checksum(block: &[u8]) -> u64 {
+    fnv1a(fnv1a(FNV_OFFSET, &block[..24]), &block[32..])
+}
+
+/// Writes a block with a checksum and timestamp to a scratch file, syncs it,
+/// reads it back and checks that we got the same data.
+pub struct IntegrityProbe {
+    path: PathBuf,
+    file: File,
+    mechanism: &'static str,
+    written: AlignedBuffer,
+    read: AlignedBuffer,
+    sequence: u64,
+}
+
+impl IntegrityProbe {
+    pub fn new(scratch_dir: &Path, resolve: &Resolve) -> std::io::Result<IntegrityProbe> {
+        let scratch = scratch::create(scratch_dir, "integrity", true, resolve)?;
+        Ok(IntegrityProbe {
+            path: scratch.path,
+            file: scratch.file,
+            mechanism: scratch.mechanism,
+            written: AlignedBuffer::new(BLOCK_SIZE),
+            read: AlignedBuffer::new(BLOCK_SIZE),
+            sequence: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// How the scratch file was created, see `scratch::create()`.
+    pub fn mechanism(&self) -> &'static str {
+        self.mechanism
+    }
+
+    /// Fill the block to write with new data.
+    fn fill_block(&mut self) {
+        self.sequence += 1;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        let block = self.written.as_mut_slice();
+        rand::thread_rng().fill_bytes(&mut block[32..]);
+        block[0..8].copy_from_slice(MAGIC);
+        block[8..16].copy_from_slice(&self.sequence.to_le_bytes());
+        block[16..24].copy_from_slice(&timestamp.to_le_bytes());
+        let checksum = block_checksum(block);
+        block[24..32].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    /// Run the probe, recording the metrics. Returns a description of the
+    /// problem if an operation failed or corruption was detected.
+    pub fn run(&mut self, metrics: &Metrics, target: &str) -> Result<(), String> {
+        self.fill_block();
+
+        // Write
+        let start = Instant::now();
+        if let Err(e) = self.file.write_all_at(self.written.as_slice(), 0) {
+            metrics.error("write", target);
+            return Err(format!("Error writing to {:?}: {}", self.path, e));
+        }
+        metrics.observe("write", target, None, start.elapsed().as_secs_f64());
+
+        self.sync(metrics, target)?;
+
+        // Read back and compare
+        let start = Instant::now();
+        if let Err(e) = self.file.read_exact_at(self.read.as_mut_slice(), 0) {
+            metrics.error("verify", target);
+            return Err(format!("Error reading back {:?}: {}", self.path, e));
+        }
+        metrics.observe("verify", target, None, start.elapsed().as_secs_f64());
+        let read = self.read.as_slice();
+        if read != self.written.as_slice() {
+            metrics.corruption_detected(target);
+            let stored_checksum = u64::from_le_bytes(read[24..32].try_into().unwrap());
+            return Err(format!(
+                "Corruption detected in {:?}: read back different data (header {}, checksum {})",
+                self.path,
+                if &read[0..8] == MAGIC {
+                    "intact"
+                } else {
+                    "damaged"
+                },
+                if stored_checksum == block_checksum(read) {
+                    "valid"
+                } else {
+                    "invalid"
+                },
+            ));
+        }
+        Ok(())
+    }
+
+    /// Write a new block without measuring it, then only measure the sync,
+    /// for the fsync operation.
+    pub fn run_fsync(&mut self, metrics: &Metrics, target: &str) -> Result<(), String> {
+        self.fill_block();
+        if let Err(e) = self.file.write_all_at(self.written.as_slice(), 0) {
+            metrics.error("fsync", target);
+            return Err(format!("Error writing to {:?}: {}", self.path, e));
+        }
+        self.sync(metrics, target)
+    }
+
+    fn sync(&mut self, metrics: &Metrics, target: &str) -> Result<(), String> {
+        let start = Instant::now();
+        if let Err(e) = self.file.sync_all() {
+            metrics.error("fsync", target);
+            return Err(format!("Error syncing {:?}: {}", self.path, e));
+        }
+        metrics.observe("fsync", target, None, start.elapsed().as_secs_f64());
+        Ok(())
+    }
+}
+
+impl Prober for IntegrityProbe {
+    fn probe(&mut self, operation: &str, metrics: &Metrics, target: &str) -> ProbeResult {
+        match operation {
+            "fsync" => self.run_fsync(metrics, target),
+            _ => self.run(metrics, target),
+        }
+    }
+}
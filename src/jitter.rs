@@ -0,0 +1,63 @@
+use prometheus::{GaugeVec, Opts};
+use std::collections::VecDeque;
+
+use crate::series::Series;
+use crate::status::quantile;
+
+/// Number of recent operations the jitter is computed over.
+const WINDOW: usize = 100;
+
+/// How much the latency of the recent operations varies: standard deviation,
+/// and median absolute deviation (which a few outliers don't move).
+pub struct Jitter {
+    /// Latency of the recent operations, by target and operation
+    recent: Series<VecDeque<f64>>,
+    /// Buffers for the median computations, to not allocate every time
+    sorted: Vec<f64>,
+    deviations: Vec<f64>,
+    stddev: GaugeVec,
+    mad: GaugeVec,
+}
+
+impl Jitter {
+    pub fn new(stddev_opts: Opts, mad_opts: Opts) -> Jitter {
+        Jitter {
+            recent: Series::default(),
+            sorted: Vec::with_capacity(WINDOW),
+            deviations: Vec::with_capacity(WINDOW),
+            stddev: GaugeVec::new(stddev_opts, &["operation", "target"]).unwrap(),
+            mad: GaugeVec::new(mad_opts, &["operation", "target"]).unwrap(),
+        }
+    }
+
+    pub fn gauges(&self) -> [&GaugeVec; 2] {
+        [&self.stddev, &self.mad]
+    }
+
+    pub fn observe(&mut self, operation: &str, target: &str, duration: f64) {
+        let recent = self.recent.get_or_default(target, operation);
+        if recent.len() == WINDOW {
+            recent.pop_front();
+        }
+        recent.push_back(duration);
+
+        let n = recent.len() as f64;
+        let mean = recent.iter().sum::<f64>() / n;
+        let variance = recent.iter().map(|d| (d - mean) * (d - mean)).sum::<f64>() / n;
+        self.stddev
+            .with_label_values(&[operation, target])
+            .set(variance.sqrt());
+
+        self.sorted.clear();
+        self.sorted.extend(recent.iter().copied());
+        self.sorted.sort_by(f64::total_cmp);
+        let median = quantile(&self.sorted, 0.5).unwrap();
+        self.deviations.clear();
+        self.deviations
+            .extend(self.sorted.iter().map(|d| (d - median).abs()));
+        self.deviations.sort_by(f64::total_cmp);
+        self.mad
+            .with_label_values(&[operation, target])
+            .set(quantile(&self.deviations, 0.5).unwrap());
+    }
+}
@@ -0,0 +1,68 @@
+//! Measures the latency of filesystem operations, and exports it as
+//! Prometheus metrics.
+//!
+//! The `fs-latency-exporter` binary is a thin wrapper around this library,
+//! which can also be embedded in other services: implement [`Prober`] for
+//! custom checks and run them with a [`Scheduler`].
+
+pub mod accept;
+pub mod aggregator;
+pub mod aio;
+pub mod apdex;
+pub mod bench;
+pub mod checksum;
+pub mod cifsstats;
+pub mod client;
+pub mod coldopen;
+pub mod command;
+pub mod config;
+pub mod consul;
+pub mod control;
+pub mod direct;
+pub mod dirsync;
+pub mod diskstats;
+pub mod doctor;
+pub mod exporter;
+pub mod fio;
+pub mod fshealth;
+pub mod fsstats;
+pub mod groups;
+pub mod healthcheck;
+pub mod heartbeat;
+pub mod http;
+pub mod integrity;
+pub mod jitter;
+pub mod lock;
+pub mod metadata;
+pub mod metrics;
+pub mod mmap;
+pub mod mounts;
+pub mod mountstats;
+pub mod notify;
+pub mod overlay;
+pub mod pattern;
+pub mod pool;
+pub mod prefetch;
+pub mod prober;
+pub mod process;
+pub mod push;
+pub mod readv;
+pub mod replay;
+pub mod rolling;
+pub mod scratch;
+pub mod scrub;
+pub mod sendfile;
+pub mod series;
+pub mod simulate;
+pub mod sketch;
+pub mod slo;
+pub mod state;
+pub mod status;
+pub mod syncwrite;
+pub mod target;
+pub mod template;
+pub mod trim;
+pub mod visibility;
+pub mod walk;
+
+pub use prober::{ProbeResult, Prober, Scheduler};
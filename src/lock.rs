@@ -0,0 +1,82 @@
+use prometheus::HistogramVec;
+use rand::Rng;
+use std::fs::{File, OpenOptions};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tracing::{debug, error};
+
+use crate::metrics::Metrics;
+
+fn set_lock(file: &File, lock_type: libc::c_int, wait: bool) -> std::io::Result<()> {
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = lock_type as libc::c_short;
+    lock.l_whence = libc::SEEK_SET as libc::c_short;
+    let cmd = if wait { libc::F_SETLKW } else { libc::F_SETLK };
+    if unsafe { libc::fcntl(file.as_raw_fd(), cmd, &lock) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Repeatedly takes a lock on a shared file, which other instances on other
+/// clients also take, and measures how long it takes to acquire it.
+pub fn run(path: PathBuf, interval: Duration, hold: Duration, metrics: &Metrics) {
+    let target = path.display().to_string();
+    let latency = HistogramVec::new(
+        metrics.histogram_opts("lock_acquire_seconds", "Time taken to acquire a lock"),
+        &["target", "contended"],
+    )
+    .unwrap();
+    metrics.register(&latency);
+    metrics.init_operation("lock", &target);
+
+    let file = match OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+    {
+        Ok(f) => f,
+        Err(e) => {
+            error!("Can't open lock file {:?}: {}", path, e);
+            metrics.error("lock", &target);
+            return;
+        }
+    };
+
+    let mut rng = rand::thread_rng();
+    loop {
+        // Try without waiting first, to know whether someone else has it
+        let start = Instant::now();
+        let result = match set_lock(&file, libc::F_WRLCK, false) {
+            Ok(()) => Ok(false),
+            Err(e) if matches!(e.raw_os_error(), Some(libc::EAGAIN) | Some(libc::EACCES)) => {
+                set_lock(&file, libc::F_WRLCK, true).map(|()| true)
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(contended) => {
+                let duration = start.elapsed().as_secs_f64();
+                debug!("Lock acquired in {} (contended: {})", duration, contended);
+                latency
+                    .with_label_values(&[&target, if contended { "true" } else { "false" }])
+                    .observe(duration);
+                std::thread::sleep(hold);
+                if let Err(e) = set_lock(&file, libc::F_UNLCK, false) {
+                    error!("Error unlocking {:?}: {}", path, e);
+                    metrics.error("lock", &target);
+                }
+            }
+            Err(e) => {
+                error!("Error locking {:?}: {}", path, e);
+                metrics.error("lock", &target);
+            }
+        }
+
+        // Add some jitter, so instances don't stay in lockstep
+        std::thread::sleep(interval.mul_f64(rng.gen_range(0.5..1.5)));
+    }
+}
@@ -0,0 +1,258 @@
+use std::ffi::CString;
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::config::hostname;
+use crate::direct::Resolve;
+use crate::integrity::BLOCK_SIZE;
+use crate::metrics::Metrics;
+use crate::prober::{ProbeResult, Prober};
+use crate::scratch::{self, ScratchFile};
+
+/// Metadata operations on files in the scratch directory, which go through
+/// different paths than reads and writes (e.g. NFS CREATE, the MDS of a
+/// parallel filesystem).
+pub struct MetadataProbe {
+    dir: PathBuf,
+    resolve: Resolve,
+    /// The scratch directory, opened on first use
+    dir_file: Option<File>,
+    /// To give each created file a unique name
+    count: u64,
+    /// File whose attributes are changed, created on first use
+    attributes: Option<ScratchFile>,
+    /// File which is hard-linked, created on first use
+    link_source: Option<PathBuf>,
+    /// How many files the storm operation creates
+    storm_files: usize,
+}
+
+impl MetadataProbe {
+    pub fn new(scratch_dir: &Path, storm_files: usize, resolve: &Resolve) -> MetadataProbe {
+        MetadataProbe {
+            dir: scratch_dir.to_owned(),
+            resolve: resolve.clone(),
+            dir_file: None,
+            count: 0,
+            attributes: None,
+            link_source: None,
+            storm_files,
+        }
+    }
+
+    /// A new unique path in the scratch directory, named like the scratch
+    /// files so it is cleaned up the same way if left behind.
+    fn unique_path(&mut self, suffix: &str) -> PathBuf {
+        self.count += 1;
+        self.dir.join(format!(
+            "fs-latency-exporter.{}.{}.{}.{}",
+            hostname(),
+            std::process::id(),
+            suffix,
+            self.count
+        ))
+    }
+
+    /// The scratch directory, opened through `resolve`, so that the files in
+    /// it can be linked and removed with `linkat()` and `unlinkat()` without
+    /// resolving its path again.
+    fn dir(&mut self) -> Result<RawFd, String> {
+        if self.dir_file.is_none() {
+            let file = self
+                .resolve
+                .open(&self.dir, libc::O_RDONLY | libc::O_DIRECTORY, 0)
+                .map_err(|e| format!("Can't open {:?}: {}", self.dir, e))?;
+            self.dir_file = Some(file);
+        }
+        Ok(self.dir_file.as_ref().unwrap().as_raw_fd())
+    }
+
+    /// Measure an update of the permissions (`chmod`), owner (`chown`, to the
+    /// same owner), or timestamps (`utime`) of a scratch file.
+    pub fn run_attribute(
+        &mut self,
+        operation: &str,
+        metrics: &Metrics,
+        target: &str,
+    ) -> Result<(), String> {
+        if self.attributes.is_none() {
+            let scratch = scratch::create(&self.dir, "attributes", false, &self.resolve)
+                .map_err(|e| format!("Can't create scratch file in {:?}: {}", self.dir, e))?;
+            self.attributes = Some(scratch);
+        }
+        let scratch = self.attributes.as_ref().unwrap();
+        let fd = scratch.file.as_raw_fd();
+
+        let start = Instant::now();
+        let ret = match operation {
+            // Alternate between two modes so it's a real change
+            "chmod" => unsafe { libc::fchmod(fd, [0o600, 0o640][self.count as usize % 2]) },
+            "chown" => unsafe { libc::fchown(fd, libc::geteuid(), libc::getegid()) },
+            "utime" => unsafe { libc::futimens(fd, std::ptr::null()) },
+            _ => unreachable!(),
+        };
+        let duration = start.elapsed().as_secs_f64();
+        self.count += 1;
+        if ret != 0 {
+            metrics.error(operation, target);
+            return Err(format!(
+                "Error changing attributes of {:?} ({}): {}",
+                scratch.path,
+                operation,
+                std::io::Error::last_os_error()
+            ));
+        }
+        metrics.observe(operation, target, None, duration);
+        Ok(())
+    }
+
+    /// Measure the creation of a hard link to a scratch file with `linkat()`,
+    /// then its removal with `unlinkat()`.
+    pub fn run_link(&mut self, metrics: &Metrics, target: &str) -> Result<(), String> {
+        let dir = self.dir()?;
+        if self.link_source.is_none() {
+            let path = self.unique_path("link");
+            self.resolve
+                .open(&path, libc::O_WRONLY | libc::O_CREAT, 0o600)
+                .map_err(|e| format!("Error creating {:?}: {}", path, e))?;
+            self.link_source = Some(path);
+        }
+        let source = self.link_source.clone().unwrap();
+        let path = self.unique_path("link");
+        let (source_name, name) = (file_name(&source), file_name(&path));
+
+        let start = Instant::now();
+        if unsafe { libc::linkat(dir, source_name.as_ptr(), dir, name.as_ptr(), 0) } != 0 {
+            let e = std::io::Error::last_os_error();
+            metrics.error("link", target);
+            return Err(format!("Error linking {:?} to {:?}: {}", path, source, e));
+        }
+        metrics.observe("link", target, None, start.elapsed().as_secs_f64());
+
+        let start = Instant::now();
+        if let Err(e) = unlink(dir, &path) {
+            metrics.error("unlink", target);
+            return Err(format!("Error unlinking {:?}: {}", path, e));
+        }
+        metrics.observe("unlink", target, None, start.elapsed().as_secs_f64());
+        Ok(())
+    }
+
+    /// Measure the exclusive creation of a uniquely named file.
+    pub fn run_create(&mut self, metrics: &Metrics, target: &str) -> Result<(), String> {
+        let dir = self.dir()?;
+        let path = self.unique_path("create");
+        let start = Instant::now();
+        let result = self
+            .resolve
+            .open(&path, libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL, 0o600);
+        let duration = start.elapsed().as_secs_f64();
+        match result {
+            Ok(file) => {
+                drop(file);
+                metrics.observe("create", target, None, duration);
+                unlink(dir, &path).map_err(|e| format!("Error removing {:?}: {}", path, e))
+            }
+            Err(e) => {
+                metrics.error("create", target);
+                Err(format!("Error creating {:?}: {}", path, e))
+            }
+        }
+    }
+
+    /// Create many small files as fast as possible, the way untar does,
+    /// then remove them. Each creation (with the write of a block and the
+    /// close) is measured as storm_create, and the whole storm as storm.
+    pub fn run_storm(&mut self, metrics: &Metrics, target: &str) -> Result<(), String> {
+        let dir = self.dir()?;
+        let block = [0; BLOCK_SIZE];
+        let mut paths = Vec::with_capacity(self.storm_files);
+        let mut result = Ok(());
+        let start = Instant::now();
+        for _ in 0..self.storm_files {
+            let path = self.unique_path("storm");
+            let create = Instant::now();
+            let mut file =
+                match self
+                    .resolve
+                    .open(&path, libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL, 0o600)
+                {
+                    Ok(f) => f,
+                    Err(e) => {
+                        result = Err(format!("Error creating {:?}: {}", path, e));
+                        break;
+                    }
+                };
+            paths.push(path);
+            if let Err(e) = file.write_all(&block) {
+                result = Err(format!("Error writing {:?}: {}", paths.last().unwrap(), e));
+                break;
+            }
+            drop(file);
+            metrics.observe("storm_create", target, None, create.elapsed().as_secs_f64());
+        }
+        let duration = start.elapsed().as_secs_f64();
+
+        // Clean up, even after a failure
+        for path in &paths {
+            if let Err(e) = unlink(dir, path) {
+                if result.is_ok() {
+                    result = Err(format!("Error removing {:?}: {}", path, e));
+                }
+            }
+        }
+        match result {
+            Ok(()) => {
+                metrics.observe("storm", target, None, duration);
+                metrics.storm_rate(target, paths.len() as f64 / duration);
+                Ok(())
+            }
+            Err(e) => {
+                metrics.error("storm", target);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// The name of a file in the scratch directory, for the `*at()` calls.
+fn file_name(path: &Path) -> CString {
+    CString::new(path.file_name().unwrap().as_bytes()).unwrap()
+}
+
+/// Remove a file from the scratch directory, opened as `dir`.
+fn unlink(dir: RawFd, path: &Path) -> std::io::Result<()> {
+    let name = file_name(path);
+    if unsafe { libc::unlinkat(dir, name.as_ptr(), 0) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl Prober for MetadataProbe {
+    fn probe(&mut self, operation: &str, metrics: &Metrics, target: &str) -> ProbeResult {
+        match operation {
+            "link" => self.run_link(metrics, target),
+            "storm" => self.run_storm(metrics, target),
+            "create" => self.run_create(metrics, target),
+            _ => self.run_attribute(operation, metrics, target),
+        }
+    }
+}
+
+impl Drop for MetadataProbe {
+    fn drop(&mut self) {
+        if let Some(scratch) = &self.attributes {
+            if scratch.mechanism == "named" {
+                let _ = std::fs::remove_file(&scratch.path);
+            }
+        }
+        if let (Some(path), Some(dir)) = (&self.link_source, &self.dir_file) {
+            let _ = unlink(dir.as_raw_fd(), path);
+        }
+    }
+}
@@ -0,0 +1,1103 @@
+use prometheus::core::Collector;
+use prometheus::proto::MetricFamily;
+use prometheus::{Counter, CounterVec, GaugeVec, HistogramOpts, HistogramVec, Opts, Registry};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{error, warn};
+
+use crate::apdex::Thresholds;
+use crate::groups::Groups;
+use crate::jitter::Jitter;
+use crate::mounts::{fuse_type, relevant_options, MountInfo};
+use crate::process::ThreadUsage;
+use crate::rolling::RollingCollector;
+use crate::sketch::QuantileCollector;
+use crate::slo::SloCollector;
+use crate::state::{self, State};
+
+#[rustfmt::skip]
+const LATENCY_BUCKETS: &[f64] = &[
+    0.0001,
+    0.00025, 0.0005, 0.001,
+    0.0025, 0.005, 0.01,
+    0.025, 0.05, 0.1,
+    0.25, 0.5, 1.0,
+    2.5, 5.0, 10.0,
+];
+
+/// Buckets of the histograms in microseconds, finer at the low end.
+#[rustfmt::skip]
+const MICROSECOND_BUCKETS: &[f64] = &[
+    1.0, 2.5, 5.0,
+    10.0, 25.0, 50.0,
+    100.0, 250.0, 500.0,
+    1000.0, 2500.0, 5000.0,
+    10000.0, 100000.0, 1000000.0,
+];
+
+/// The names of the labels set by the exporter on its own metrics (and by
+/// Prometheus on histograms and summaries), which custom labels would clash
+/// with.
+const RESERVED_LABELS: &[&str] = &[
+    "le",
+    "quantile",
+    "operation",
+    "target",
+    "region",
+    "group",
+    "origin",
+    "mode",
+    "phase",
+    "reason",
+    "threshold",
+    "window",
+    "concurrency",
+    "condition",
+    "state",
+    "contended",
+    "device",
+    "type",
+    "kind",
+    "layer",
+    "path",
+    "mechanism",
+    "fstype",
+    "source",
+    "options",
+    "block_size",
+    "fuse",
+    "pool",
+    "scan",
+    "uuid",
+    "scrub",
+    "balance",
+    "exclusive_operation",
+];
+
+/// Set a counter to a value read from the system, for collectors.
+pub fn set_counter(counter: &Counter, value: f64) {
+    let past = counter.get();
+    if value > past {
+        counter.inc_by(value - past);
+    }
+}
+
+/// Latencies by target and operation.
+pub type Recorded = HashMap<(String, String), Vec<f64>>;
+
+/// The metrics we export, labeled by operation and target.
+pub struct Metrics {
+    /// Latency is registered separately for each target, so they can have
+    /// different buckets
+    latency: Mutex<HashMap<String, HistogramVec>>,
+    errors: CounterVec,
+    corruption: CounterVec,
+    verification: CounterVec,
+    success: GaugeVec,
+    last_probe: GaugeVec,
+    /// Targets which finished at least one round of probes
+    probed: Mutex<HashSet<String>>,
+    probe_interval: GaugeVec,
+    skipped: CounterVec,
+    panics: CounterVec,
+    throttled: CounterVec,
+    probe_skipped: CounterVec,
+    scratch: GaugeVec,
+    target_info: GaugeVec,
+    overlay_layer: GaugeVec,
+    groups: Mutex<Groups>,
+    thresholds: Mutex<Thresholds>,
+    slo: SloCollector,
+    jitter: Mutex<Jitter>,
+    concurrent: HistogramVec,
+    probe_cpu: CounterVec,
+    alignment: GaugeVec,
+    cache_bypass: GaugeVec,
+    storm_rate: GaugeVec,
+    throughput: GaugeVec,
+    command_status: GaugeVec,
+    phases: HistogramVec,
+    first_byte: HistogramVec,
+    probe_switches: CounterVec,
+    regions: bool,
+    legacy: Option<(HistogramVec, Counter)>,
+    prefix: String,
+    const_labels: HashMap<String, String>,
+    /// Every latency measured, by target and operation, for `--bench`
+    recorded: Option<Mutex<Recorded>>,
+    rolling: Option<RollingCollector>,
+    quantiles: Option<QuantileCollector>,
+    /// Values saved by a previous run, added to the current ones
+    restored: Option<State>,
+    /// Histograms corrected for coordinated omission, and the interval of
+    /// their target
+    corrected: Mutex<HashMap<String, (HistogramVec, f64)>>,
+    /// Histograms in microseconds, for the targets which want them
+    microseconds: Mutex<HashMap<String, HistogramVec>>,
+    /// Targets which are gone, whose metrics are not exported
+    removed: Mutex<HashSet<String>>,
+    targets_removed: Counter,
+    /// Registry of the core collectors, our own rather than the global one,
+    /// so several instances can live in the same process
+    registry: Registry,
+    /// Collectors which can be selected with `collect[]`, each in its own
+    /// registry so they are only run when selected
+    optional: Mutex<Vec<(String, Registry)>>,
+}
+
+impl Metrics {
+    /// Create and register the metrics.
+    ///
+    /// If `regions` is set, latency gets an additional `region` label. If
+    /// `legacy` is set, reads are also recorded under the old metric names
+    /// `read_time_seconds` and `errors_total`. `prefix` is prepended to the
+    /// name of the (non-legacy) metrics, and `const_labels` are added to
+    /// every metric.
+    pub fn new(
+        prefix: &str,
+        regions: bool,
+        legacy: bool,
+        const_labels: &HashMap<String, String>,
+    ) -> Metrics {
+        let registry = Registry::new();
+
+        let errors_opts = Opts::new(
+            format!("{}errors_total", prefix),
+            "Number of failed operations",
+        )
+        .const_labels(const_labels.clone());
+        let errors = CounterVec::new(errors_opts, &["operation", "target"]).unwrap();
+        registry.register(Box::new(errors.clone())).unwrap();
+        let corruption_opts = Opts::new(
+            format!("{}corruption_detected_total", prefix),
+            "Number of times data read back didn't match what was written",
+        )
+        .const_labels(const_labels.clone());
+        let corruption = CounterVec::new(corruption_opts, &["target"]).unwrap();
+        registry.register(Box::new(corruption.clone())).unwrap();
+        let verification_opts = Opts::new(
+            format!("{}verification_failures_total", prefix),
+            "Number of reads which returned data different from the pattern written to the file",
+        )
+        .const_labels(const_labels.clone());
+        let verification = CounterVec::new(verification_opts, &["target"]).unwrap();
+        registry.register(Box::new(verification.clone())).unwrap();
+        let success_opts = Opts::new(
+            format!("{}probe_success", prefix),
+            "Whether the last round of probes of the target succeeded",
+        )
+        .const_labels(const_labels.clone());
+        let success = GaugeVec::new(success_opts, &["target"]).unwrap();
+        registry.register(Box::new(success.clone())).unwrap();
+        let last_probe_opts = Opts::new(
+            format!("{}last_probe_timestamp_seconds", prefix),
+            "When the last round of probes of the target finished, as a Unix timestamp",
+        )
+        .const_labels(const_labels.clone());
+        let last_probe = GaugeVec::new(last_probe_opts, &["target"]).unwrap();
+        registry.register(Box::new(last_probe.clone())).unwrap();
+        let probe_interval_opts = Opts::new(
+            format!("{}probe_interval_seconds", prefix),
+            "How often the target is probed",
+        )
+        .const_labels(const_labels.clone());
+        let probe_interval = GaugeVec::new(probe_interval_opts, &["target"]).unwrap();
+        registry.register(Box::new(probe_interval.clone())).unwrap();
+        let skipped_opts = Opts::new(
+            format!("{}skipped_cycles_total", prefix),
+            "Number of measurements skipped because the previous one took longer than the interval",
+        )
+        .const_labels(const_labels.clone());
+        let skipped = CounterVec::new(skipped_opts, &["target"]).unwrap();
+        registry.register(Box::new(skipped.clone())).unwrap();
+        let panics_opts = Opts::new(
+            format!("{}probe_panics_total", prefix),
+            "Number of rounds of probes which panicked (a bug in the exporter)",
+        )
+        .const_labels(const_labels.clone());
+        let panics = CounterVec::new(panics_opts, &["target"]).unwrap();
+        registry.register(Box::new(panics.clone())).unwrap();
+        let throttled_opts = Opts::new(
+            format!("{}probe_throttled_total", prefix),
+            "Number of rounds of probes skipped to stay under the max probe time fraction",
+        )
+        .const_labels(const_labels.clone());
+        let throttled = CounterVec::new(throttled_opts, &["target"]).unwrap();
+        registry.register(Box::new(throttled.clone())).unwrap();
+        let probe_skipped_opts = Opts::new(
+            format!("{}probe_skipped_total", prefix),
+            "Number of probes not run, by reason (low_space)",
+        )
+        .const_labels(const_labels.clone());
+        let probe_skipped =
+            CounterVec::new(probe_skipped_opts, &["operation", "target", "reason"]).unwrap();
+        registry.register(Box::new(probe_skipped.clone())).unwrap();
+
+        let scratch_opts = Opts::new(
+            format!("{}scratch_file_info", prefix),
+            "How the scratch file of write probes was created (tmpfile or named)",
+        )
+        .const_labels(const_labels.clone());
+        let scratch = GaugeVec::new(scratch_opts, &["target", "mechanism"]).unwrap();
+        registry.register(Box::new(scratch.clone())).unwrap();
+        let targets_removed = Counter::with_opts(
+            Opts::new(
+                format!("{}targets_removed_total", prefix),
+                "Number of times the metrics of a target were removed because it was gone",
+            )
+            .const_labels(const_labels.clone()),
+        )
+        .unwrap();
+        registry
+            .register(Box::new(targets_removed.clone()))
+            .unwrap();
+        let target_info_opts = Opts::new(
+            format!("{}target_info", prefix),
+            "Filesystem of the target, with its relevant mount options and block size",
+        )
+        .const_labels(const_labels.clone());
+        let target_info = GaugeVec::new(
+            target_info_opts,
+            &[
+                "target",
+                "fstype",
+                "source",
+                "options",
+                "block_size",
+                "fuse",
+            ],
+        )
+        .unwrap();
+        registry.register(Box::new(target_info.clone())).unwrap();
+        let overlay_layer_opts = Opts::new(
+            format!("{}overlay_layer_info", prefix),
+            "The overlayfs layer which holds the file of the target (upper, or lowerN from the top), and the path of the file in it",
+        )
+        .const_labels(const_labels.clone());
+        let overlay_layer =
+            GaugeVec::new(overlay_layer_opts, &["target", "layer", "path"]).unwrap();
+        registry.register(Box::new(overlay_layer.clone())).unwrap();
+
+        let groups = Groups::new(
+            Opts::new(
+                format!("{}group_latency_difference_seconds", prefix),
+                "Difference between the average latencies of the slowest and fastest targets of a group",
+            )
+            .const_labels(const_labels.clone()),
+        );
+        registry.register(Box::new(groups.gauge().clone())).unwrap();
+
+        let thresholds = Thresholds::new(
+            Opts::new(
+                format!("{}within_threshold_total", prefix),
+                "Number of successful operations which took at most the threshold",
+            )
+            .const_labels(const_labels.clone()),
+            Opts::new(
+                format!("{}over_threshold_total", prefix),
+                "Number of successful operations which took longer than the threshold",
+            )
+            .const_labels(const_labels.clone()),
+            Opts::new(
+                format!("{}apdex_ratio", prefix),
+                "Apdex score of the last 100 operations, using the first threshold",
+            )
+            .const_labels(const_labels.clone()),
+        );
+        for counter in thresholds.counters() {
+            registry.register(Box::new(counter.clone())).unwrap();
+        }
+        registry
+            .register(Box::new(thresholds.gauge().clone()))
+            .unwrap();
+
+        let slo = SloCollector::new(
+            Opts::new(
+                format!("{}slo_burn_rate", prefix),
+                "Rate at which the error budget of the latency SLO is used up over the window, \
+                 1 using it up exactly over the SLO period",
+            )
+            .const_labels(const_labels.clone()),
+            Opts::new(
+                format!("{}slo_objective_ratio", prefix),
+                "Ratio of operations which should take at most the SLO threshold",
+            )
+            .const_labels(const_labels.clone()),
+        );
+        registry.register(Box::new(slo.clone())).unwrap();
+
+        let jitter = Jitter::new(
+            Opts::new(
+                format!("{}stddev_seconds", prefix),
+                "Standard deviation of the latency of the last 100 operations",
+            )
+            .const_labels(const_labels.clone()),
+            Opts::new(
+                format!("{}mad_seconds", prefix),
+                "Median absolute deviation of the latency of the last 100 operations",
+            )
+            .const_labels(const_labels.clone()),
+        );
+        for gauge in jitter.gauges() {
+            registry.register(Box::new(gauge.clone())).unwrap();
+        }
+
+        let alignment_opts = Opts::new(
+            format!("{}read_alignment_bytes", prefix),
+            "Alignment of the offset and size of direct reads, if a larger one than 4096 was needed",
+        )
+        .const_labels(const_labels.clone());
+        let alignment = GaugeVec::new(alignment_opts, &["target"]).unwrap();
+        registry.register(Box::new(alignment.clone())).unwrap();
+        let cache_bypass_opts = Opts::new(
+            format!("{}cache_bypass_effective", prefix),
+            "Whether reads of the target bypass the page cache, from a check after the first read (0 if repeated reads come from memory)",
+        )
+        .const_labels(const_labels.clone());
+        let cache_bypass = GaugeVec::new(cache_bypass_opts, &["target"]).unwrap();
+        registry.register(Box::new(cache_bypass.clone())).unwrap();
+        let storm_rate_opts = Opts::new(
+            format!("{}storm_creates_per_second", prefix),
+            "Number of files created per second by the last storm probe",
+        )
+        .const_labels(const_labels.clone());
+        let storm_rate = GaugeVec::new(storm_rate_opts, &["target"]).unwrap();
+        registry.register(Box::new(storm_rate.clone())).unwrap();
+        let command_status_opts = Opts::new(
+            format!("{}command_exit_status", prefix),
+            "Exit status of the last run of the command probe (-1 if it was killed)",
+        )
+        .const_labels(const_labels.clone());
+        let command_status = GaugeVec::new(command_status_opts, &["target"]).unwrap();
+        registry.register(Box::new(command_status.clone())).unwrap();
+        let throughput_opts = Opts::new(
+            format!("{}throughput_bytes_per_second", prefix),
+            "Throughput of the last operation, for those transferring a large amount of data",
+        )
+        .const_labels(const_labels.clone());
+        let throughput = GaugeVec::new(throughput_opts, &["operation", "target"]).unwrap();
+        registry.register(Box::new(throughput.clone())).unwrap();
+        let phases_opts = HistogramOpts::new(
+            format!("{}phase_seconds", prefix),
+            "Time taken by each phase of operations made of several steps",
+        )
+        .const_labels(const_labels.clone())
+        .buckets(LATENCY_BUCKETS.to_vec());
+        let phases = HistogramVec::new(phases_opts, &["operation", "target", "phase"]).unwrap();
+        registry.register(Box::new(phases.clone())).unwrap();
+        let first_byte_opts = HistogramOpts::new(
+            format!("{}first_byte_seconds", prefix),
+            "Time until the first block of large reads was read, the rest being the transfer",
+        )
+        .const_labels(const_labels.clone())
+        .buckets(LATENCY_BUCKETS.to_vec());
+        let first_byte = HistogramVec::new(first_byte_opts, &["operation", "target"]).unwrap();
+        registry.register(Box::new(first_byte.clone())).unwrap();
+        let probe_cpu_opts = Opts::new(
+            format!("{}probe_cpu_seconds_total", prefix),
+            "CPU time used by the probes, by mode (user or system)",
+        )
+        .const_labels(const_labels.clone());
+        let probe_cpu = CounterVec::new(probe_cpu_opts, &["operation", "target", "mode"]).unwrap();
+        registry.register(Box::new(probe_cpu.clone())).unwrap();
+        let probe_switches_opts = Opts::new(
+            format!("{}probe_involuntary_context_switches_total", prefix),
+            "Number of times the probes were preempted (e.g. for lack of CPU)",
+        )
+        .const_labels(const_labels.clone());
+        let probe_switches =
+            CounterVec::new(probe_switches_opts, &["operation", "target"]).unwrap();
+        registry.register(Box::new(probe_switches.clone())).unwrap();
+
+        let concurrent_opts = HistogramOpts::new(
+            format!("{}concurrent_read_seconds", prefix),
+            "Time taken by reads run simultaneously from several threads",
+        )
+        .const_labels(const_labels.clone())
+        .buckets(LATENCY_BUCKETS.to_vec());
+        let concurrent = HistogramVec::new(concurrent_opts, &["target", "concurrency"]).unwrap();
+        registry.register(Box::new(concurrent.clone())).unwrap();
+
+        let legacy = if legacy {
+            let register_legacy = || -> prometheus::Result<_> {
+                let errors_opts = Opts::new("errors_total", "Number of read errors")
+                    .const_labels(const_labels.clone());
+                let errors = Counter::with_opts(errors_opts)?;
+                let latency_opts =
+                    HistogramOpts::new("read_time_seconds", "Time taken to read (latency)")
+                        .const_labels(const_labels.clone())
+                        .buckets(LATENCY_BUCKETS.to_vec());
+                let latency_labels: &[&str] = if regions { &["region"] } else { &[] };
+                let latency = HistogramVec::new(latency_opts, latency_labels)?;
+                registry.register(Box::new(errors.clone()))?;
+                if let Err(e) = registry.register(Box::new(latency.clone())) {
+                    registry.unregister(Box::new(errors))?;
+                    return Err(e);
+                }
+                Ok((latency, errors))
+            };
+            match register_legacy() {
+                Ok(legacy) => Some(legacy),
+                Err(e) => {
+                    warn!("Can't export the legacy metrics: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        Metrics {
+            latency: Mutex::new(HashMap::new()),
+            errors,
+            corruption,
+            verification,
+            success,
+            last_probe,
+            probed: Mutex::new(HashSet::new()),
+            probe_interval,
+            skipped,
+            panics,
+            throttled,
+            probe_skipped,
+            scratch,
+            target_info,
+            overlay_layer,
+            groups: Mutex::new(groups),
+            thresholds: Mutex::new(thresholds),
+            slo,
+            jitter: Mutex::new(jitter),
+            concurrent,
+            probe_cpu,
+            alignment,
+            cache_bypass,
+            storm_rate,
+            throughput,
+            command_status,
+            phases,
+            first_byte,
+            probe_switches,
+            regions,
+            legacy,
+            prefix: prefix.to_owned(),
+            const_labels: const_labels.clone(),
+            recorded: None,
+            rolling: None,
+            quantiles: None,
+            restored: None,
+            corrected: Mutex::new(HashMap::new()),
+            microseconds: Mutex::new(HashMap::new()),
+            removed: Mutex::new(HashSet::new()),
+            targets_removed,
+            registry,
+            optional: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Also keep every latency measured, to be retrieved with
+    /// `take_recorded()`.
+    pub fn record_all(&mut self) {
+        self.recorded = Some(Mutex::new(HashMap::new()));
+    }
+
+    /// Carry on the counters and histograms from a previous run.
+    pub fn restore(&mut self, state: State) {
+        self.restored = Some(state);
+    }
+
+    /// Collect the counters and histograms from the core collectors
+    /// (including the restored values), except those which are only about
+    /// the present, and save them to a file.
+    pub fn save_state(&self, path: &Path) -> Result<(), String> {
+        let window = format!("{}window_seconds", self.prefix);
+        let families: Vec<_> = self
+            .gather_core()
+            .into_iter()
+            .filter(|f| f.get_name() != window)
+            .collect();
+        state::save(path, &families)
+    }
+
+    fn gather_core(&self) -> Vec<MetricFamily> {
+        let mut families = self.registry.gather();
+        if let Some(restored) = &self.restored {
+            restored.apply(&mut families);
+        }
+        families
+    }
+
+    /// Also export quantiles of the latency over the last `window`.
+    pub fn set_quantile_window(&mut self, window: Duration) {
+        let quantiles = QuantileCollector::new(
+            window,
+            self.opts(
+                "quantile_seconds",
+                &format!(
+                    "Quantiles of the time taken by operations over the last {}",
+                    humantime::format_duration(window)
+                ),
+            ),
+        );
+        self.register(&quantiles);
+        self.quantiles = Some(quantiles);
+    }
+
+    /// Also export histograms of the latency over the last `window`, for
+    /// these targets (and their custom buckets).
+    pub fn set_window<'a, I: IntoIterator<Item = (&'a str, Option<&'a [f64]>)>>(
+        &mut self,
+        window: Duration,
+        targets: I,
+    ) {
+        let targets = targets
+            .into_iter()
+            .map(|(target, buckets)| {
+                let mut const_labels = self.const_labels.clone();
+                const_labels.insert("target".to_owned(), target.to_owned());
+                let opts = HistogramOpts::new(
+                    format!("{}window_seconds", self.prefix),
+                    format!(
+                        "Time taken by operations over the last {}",
+                        humantime::format_duration(window)
+                    ),
+                )
+                .const_labels(const_labels)
+                .buckets(buckets.unwrap_or(LATENCY_BUCKETS).to_vec());
+                (target.to_owned(), opts)
+            })
+            .collect();
+        let rolling = RollingCollector::new(window, targets);
+        self.register(&rolling);
+        self.rolling = Some(rolling);
+    }
+
+    /// Get the latencies recorded so far, by target and operation.
+    pub fn take_recorded(&self) -> Recorded {
+        match &self.recorded {
+            Some(recorded) => std::mem::take(&mut *recorded.lock().unwrap()),
+            None => HashMap::new(),
+        }
+    }
+
+    /// Register the latency histogram of a target, with custom buckets.
+    pub fn add_target(&self, target: &str, buckets: Option<&[f64]>) {
+        self.panics.with_label_values(&[target]);
+        let mut latency = self.latency.lock().unwrap();
+        self.add_target_locked(&mut latency, target, buckets);
+    }
+
+    fn add_target_locked(
+        &self,
+        latency: &mut HashMap<String, HistogramVec>,
+        target: &str,
+        buckets: Option<&[f64]>,
+    ) -> HistogramVec {
+        let mut const_labels = self.const_labels.clone();
+        const_labels.insert("target".to_owned(), target.to_owned());
+        let opts = HistogramOpts::new(
+            format!("{}seconds", self.prefix),
+            "Time taken by operations",
+        )
+        .const_labels(const_labels)
+        .buckets(buckets.unwrap_or(LATENCY_BUCKETS).to_vec());
+        let labels: &[&str] = if self.regions {
+            &["operation", "region"]
+        } else {
+            &["operation"]
+        };
+        let histogram = HistogramVec::new(opts, labels).unwrap();
+        self.register(&histogram);
+        latency.insert(target.to_owned(), histogram.clone());
+        histogram
+    }
+
+    /// Compare the latency of a target with the others in `group`.
+    pub fn add_to_group(&self, target: &str, group: &str) {
+        self.groups.lock().unwrap().add(target, group);
+    }
+
+    /// Also export a histogram of the latency of a target corrected for
+    /// coordinated omission: an operation taking longer than the interval
+    /// delayed the ones which should have started meanwhile, which are
+    /// recorded too, as if they had waited.
+    pub fn correct_omission(&self, target: &str, buckets: Option<&[f64]>, interval: Duration) {
+        if interval.is_zero() {
+            return;
+        }
+        let mut const_labels = self.const_labels.clone();
+        const_labels.insert("target".to_owned(), target.to_owned());
+        let opts = HistogramOpts::new(
+            format!("{}corrected_seconds", self.prefix),
+            "Time taken by operations, including the ones delayed by slow operations",
+        )
+        .const_labels(const_labels)
+        .buckets(buckets.unwrap_or(LATENCY_BUCKETS).to_vec());
+        let histogram = HistogramVec::new(opts, &["operation"]).unwrap();
+        self.register(&histogram);
+        self.corrected
+            .lock()
+            .unwrap()
+            .insert(target.to_owned(), (histogram, interval.as_secs_f64()));
+    }
+
+    /// Also export a histogram of the latency of a target in microseconds,
+    /// whose finer buckets tell apart devices answering in a few of them.
+    pub fn add_microseconds(&self, target: &str, buckets: Option<&[f64]>) {
+        let buckets = match buckets {
+            Some(buckets) => buckets.iter().map(|b| b * 1e6).collect(),
+            None => MICROSECOND_BUCKETS.to_vec(),
+        };
+        let mut const_labels = self.const_labels.clone();
+        const_labels.insert("target".to_owned(), target.to_owned());
+        let opts = HistogramOpts::new(
+            format!("{}microseconds", self.prefix),
+            "Time taken by operations, in microseconds",
+        )
+        .const_labels(const_labels)
+        .buckets(buckets);
+        let histogram = HistogramVec::new(opts, &["operation"]).unwrap();
+        self.register(&histogram);
+        self.microseconds
+            .lock()
+            .unwrap()
+            .insert(target.to_owned(), histogram);
+    }
+
+    /// Export the burn rate of a latency SLO for a target.
+    pub fn set_slo(&self, target: &str, threshold: Duration, objective: f64) {
+        self.slo.add(target, threshold, objective);
+    }
+
+    /// Count the operations of a target within each threshold.
+    pub fn set_thresholds(&self, target: &str, thresholds: &[Duration]) {
+        self.thresholds.lock().unwrap().add(target, thresholds);
+    }
+
+    /// Options for an additional metric, with our prefix and constant labels.
+    pub fn opts(&self, name: &str, help: &str) -> Opts {
+        Opts::new(format!("{}{}", self.prefix, name), help).const_labels(self.const_labels.clone())
+    }
+
+    /// Options for an additional histogram, with our prefix, constant labels,
+    /// and the latency buckets.
+    pub fn histogram_opts(&self, name: &str, help: &str) -> HistogramOpts {
+        HistogramOpts::from(self.opts(name, help)).buckets(LATENCY_BUCKETS.to_vec())
+    }
+
+    /// Register an additional metric.
+    pub fn register<C: Collector + Clone + 'static>(&self, collector: &C) {
+        self.registry.register(Box::new(collector.clone())).unwrap();
+    }
+
+    /// The registry of the core metrics, for embedders which gather or
+    /// encode them themselves.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Register a collector which can be selected with `collect[]`, under
+    /// that name.
+    pub fn register_optional<C: Collector + 'static>(&self, name: &str, collector: C) {
+        let mut optional = self.optional.lock().unwrap();
+        let index = match optional.iter().position(|(n, _)| n == name) {
+            Some(index) => index,
+            None => {
+                optional.push((name.to_owned(), Registry::new()));
+                optional.len() - 1
+            }
+        };
+        optional[index].1.register(Box::new(collector)).unwrap();
+    }
+
+    /// Collect the metrics, from all the collectors or only the ones named
+    /// in `collect` ("core" being everything which isn't optional).
+    pub fn gather(&self, collect: &[String]) -> Result<Vec<MetricFamily>, String> {
+        let optional = self.optional.lock().unwrap();
+        if let Some(name) = collect
+            .iter()
+            .find(|c| *c != "core" && !optional.iter().any(|(n, _)| n == *c))
+        {
+            return Err(format!("Unknown collector {}", name));
+        }
+        let selected = |name: &str| collect.is_empty() || collect.iter().any(|c| c == name);
+        let mut families = Vec::new();
+        if selected("core") {
+            families.extend(self.gather_core());
+        }
+        for (name, registry) in optional.iter() {
+            if selected(name) {
+                families.extend(registry.gather());
+            }
+        }
+        families.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+
+        // Leave out the targets which are gone
+        let removed = self.removed.lock().unwrap();
+        if !removed.is_empty() {
+            for family in &mut families {
+                family.mut_metric().retain(|m| {
+                    !m.get_label()
+                        .iter()
+                        .any(|l| l.get_name() == "target" && removed.contains(l.get_value()))
+                });
+            }
+            families.retain(|f| !f.get_metric().is_empty());
+        }
+        Ok(families)
+    }
+
+    /// Stop exporting the metrics of a target which no longer exists.
+    pub fn remove_target(&self, target: &str) {
+        if self.removed.lock().unwrap().insert(target.to_owned()) {
+            self.targets_removed.inc();
+        }
+    }
+
+    /// Export the metrics of a removed target again.
+    pub fn restore_target(&self, target: &str) {
+        self.removed.lock().unwrap().remove(target);
+    }
+
+    /// Check that a string can be used as a prefix for metric names.
+    pub fn valid_prefix(prefix: &str) -> bool {
+        !prefix.starts_with(|c: char| c.is_ascii_digit())
+            && prefix
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+    }
+
+    /// Check that a name is a valid label name.
+    pub fn valid_label_name(name: &str) -> bool {
+        let mut chars = name.chars();
+        match chars.next() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+            _ => return false,
+        }
+        if !chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return false;
+        }
+        !name.starts_with("__")
+    }
+
+    /// Check whether a label name is already used by the exporter's own
+    /// metrics, so it can't be used for a custom label.
+    pub fn reserved_label_name(name: &str) -> bool {
+        RESERVED_LABELS.contains(&name)
+    }
+
+    /// Make sure the error counter for an operation is exported, even if no
+    /// error happened yet.
+    pub fn init_operation(&self, operation: &str, target: &str) {
+        self.errors.with_label_values(&[operation, target]);
+    }
+
+    /// Make sure the corruption counter for a target is exported.
+    pub fn init_corruption(&self, target: &str) {
+        self.corruption.with_label_values(&[target]);
+    }
+
+    /// Make sure the verification failure counter for a target is exported.
+    pub fn init_verification(&self, target: &str) {
+        self.verification.with_label_values(&[target]);
+    }
+
+    /// Record the duration of a successful operation.
+    pub fn observe(&self, operation: &str, target: &str, region: Option<u64>, duration: f64) {
+        // Format the region on the stack, this runs after every operation
+        let mut buffer = [0; 20];
+        let region = match region {
+            Some(region) => {
+                let mut cursor = &mut buffer[..];
+                write!(cursor, "{}", region).unwrap();
+                let len = 20 - cursor.len();
+                std::str::from_utf8(&buffer[..len]).unwrap()
+            }
+            None => "",
+        };
+        let both = [operation, region];
+        let labels = if self.regions { &both[..] } else { &both[..1] };
+        let histogram = {
+            let mut latency = self.latency.lock().unwrap();
+            match latency.get(target) {
+                Some(h) => h.clone(),
+                None => self.add_target_locked(&mut latency, target, None),
+            }
+        };
+        histogram.with_label_values(labels).observe(duration);
+        self.groups
+            .lock()
+            .unwrap()
+            .observe(operation, target, duration);
+        self.thresholds
+            .lock()
+            .unwrap()
+            .observe(operation, target, duration);
+        self.slo.observe(operation, target, duration);
+        self.jitter
+            .lock()
+            .unwrap()
+            .observe(operation, target, duration);
+
+        if let Some((histogram, interval)) = self.corrected.lock().unwrap().get(target) {
+            let histogram = histogram.with_label_values(&[operation]);
+            histogram.observe(duration);
+            let mut delayed = duration - interval;
+            while delayed > 0.0 {
+                histogram.observe(delayed);
+                delayed -= interval;
+            }
+        }
+        if let Some(histogram) = self.microseconds.lock().unwrap().get(target) {
+            histogram
+                .with_label_values(&[operation])
+                .observe(duration * 1e6);
+        }
+
+        if let Some(rolling) = &self.rolling {
+            rolling.observe(operation, target, duration);
+        }
+        if let Some(quantiles) = &self.quantiles {
+            quantiles.observe(operation, target, duration);
+        }
+
+        if let Some(recorded) = &self.recorded {
+            recorded
+                .lock()
+                .unwrap()
+                .entry((target.to_owned(), operation.to_owned()))
+                .or_default()
+                .push(duration);
+        }
+
+        if let Some((legacy_latency, _)) = &self.legacy {
+            if operation == "read" {
+                legacy_latency
+                    .with_label_values(&labels[1..])
+                    .observe(duration);
+            }
+        }
+    }
+
+    /// Record the duration of a read run from `concurrency` threads at once.
+    pub fn observe_concurrent(&self, target: &str, concurrency: usize, duration: f64) {
+        self.concurrent
+            .with_label_values(&[target, &concurrency.to_string()])
+            .observe(duration);
+    }
+
+    /// Record the alignment that direct reads from a target turned out to need.
+    pub fn read_alignment(&self, target: &str, alignment: u64) {
+        self.alignment
+            .with_label_values(&[target])
+            .set(alignment as f64);
+    }
+
+    /// Record whether reads from a target bypass the page cache.
+    pub fn cache_bypass(&self, target: &str, effective: bool) {
+        self.cache_bypass
+            .with_label_values(&[target])
+            .set(if effective { 1.0 } else { 0.0 });
+    }
+
+    /// Record the rate at which the storm probe created files.
+    pub fn storm_rate(&self, target: &str, rate: f64) {
+        self.storm_rate.with_label_values(&[target]).set(rate);
+    }
+
+    /// Record the exit status of the command probe.
+    pub fn command_status(&self, target: &str, status: i32) {
+        self.command_status
+            .with_label_values(&[target])
+            .set(status as f64);
+    }
+
+    /// Record the duration of one phase of a successful operation.
+    pub fn observe_phase(&self, operation: &str, target: &str, phase: &str, duration: f64) {
+        self.phases
+            .with_label_values(&[operation, target, phase])
+            .observe(duration);
+    }
+
+    /// Record the time to first byte of a large read.
+    pub fn observe_first_byte(&self, operation: &str, target: &str, duration: f64) {
+        self.first_byte
+            .with_label_values(&[operation, target])
+            .observe(duration);
+    }
+
+    /// Record the throughput of an operation which transferred `bytes`.
+    pub fn throughput(&self, operation: &str, target: &str, bytes: u64, duration: f64) {
+        self.throughput
+            .with_label_values(&[operation, target])
+            .set(bytes as f64 / duration);
+    }
+
+    /// Record the CPU used by a probe, between two measurements.
+    pub fn probe_usage(
+        &self,
+        operation: &str,
+        target: &str,
+        before: &ThreadUsage,
+        after: &ThreadUsage,
+    ) {
+        self.probe_cpu
+            .with_label_values(&[operation, target, "user"])
+            .inc_by((after.user_seconds - before.user_seconds).max(0.0));
+        self.probe_cpu
+            .with_label_values(&[operation, target, "system"])
+            .inc_by((after.system_seconds - before.system_seconds).max(0.0));
+        self.probe_switches
+            .with_label_values(&[operation, target])
+            .inc_by(
+                after
+                    .involuntary_switches
+                    .saturating_sub(before.involuntary_switches) as f64,
+            );
+    }
+
+    /// The number of failed operations so far.
+    pub fn error_count(&self, operation: &str, target: &str) -> u64 {
+        self.errors.with_label_values(&[operation, target]).get() as u64
+    }
+
+    /// Record a failed operation.
+    pub fn error(&self, operation: &str, target: &str) {
+        self.errors.with_label_values(&[operation, target]).inc();
+        self.thresholds.lock().unwrap().error(operation, target);
+        self.slo.error(operation, target);
+
+        if let Some((_, legacy_errors)) = &self.legacy {
+            if operation == "read" {
+                legacy_errors.inc();
+            }
+        }
+    }
+
+    /// Record that data read back didn't match what was written.
+    pub fn corruption_detected(&self, target: &str) {
+        self.corruption.with_label_values(&[target]).inc();
+    }
+
+    /// Record that a read didn't return the pattern of the file.
+    pub fn verification_failed(&self, target: &str) {
+        self.verification.with_label_values(&[target]).inc();
+    }
+
+    /// Record whether the last round of probes of a target succeeded.
+    pub fn set_success(&self, target: &str, success: bool) {
+        self.success
+            .with_label_values(&[target])
+            .set(if success { 1.0 } else { 0.0 });
+    }
+
+    /// Record that a round of probes of a target just finished, so stale
+    /// data (paused or stuck probes) can be told apart from fresh data.
+    pub fn set_last_probe(&self, target: &str) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64();
+        self.last_probe.with_label_values(&[target]).set(now);
+        let mut probed = self.probed.lock().unwrap();
+        if !probed.contains(target) {
+            probed.insert(target.to_owned());
+        }
+    }
+
+    pub fn set_interval(&self, target: &str, interval: Duration) {
+        self.probe_interval
+            .with_label_values(&[target])
+            .set(interval.as_secs_f64());
+    }
+
+    /// Export the info series of a target: the type, source and relevant
+    /// mount options of its filesystem, its block size, and the kind of FUSE
+    /// daemon serving it if any.
+    pub fn target_info(&self, target: &str, mount: &MountInfo, block_size: u64) {
+        self.target_info
+            .with_label_values(&[
+                target,
+                &mount.fstype,
+                &mount.source,
+                &relevant_options(&mount.options),
+                &block_size.to_string(),
+                &fuse_type(mount).unwrap_or_default(),
+            ])
+            .set(1.0);
+    }
+
+    /// Record which overlayfs layer holds the file of a target.
+    pub fn overlay_layer(&self, target: &str, layer: &str, path: &Path) {
+        self.overlay_layer
+            .with_label_values(&[target, layer, &path.display().to_string()])
+            .set(1.0);
+    }
+
+    /// Record how the scratch file of a target was created.
+    pub fn scratch_mechanism(&self, target: &str, mechanism: &str) {
+        self.scratch
+            .with_label_values(&[target, mechanism])
+            .set(1.0);
+    }
+
+    /// The targets whose last round of probes failed. Those which haven't
+    /// finished a round yet (still starting, or waiting for their file)
+    /// don't count as failing.
+    pub fn failing<'a>(&self, targets: &'a [String]) -> Vec<&'a str> {
+        let probed = self.probed.lock().unwrap();
+        targets
+            .iter()
+            .filter(|t| probed.contains(*t) && self.success.with_label_values(&[t]).get() <= 0.0)
+            .map(|t| t.as_str())
+            .collect()
+    }
+
+    /// Record a round of probes which panicked, and was given up. It counts
+    /// as a failed round.
+    pub fn probe_panicked(&self, target: &str) {
+        error!("The probes of {} panicked", target);
+        self.panics.with_label_values(&[target]).inc();
+        self.set_success(target, false);
+        self.set_last_probe(target);
+    }
+
+    /// Record measurements that were skipped because the probes overran.
+    pub fn skipped_cycles(&self, target: &str, count: u32) {
+        self.skipped
+            .with_label_values(&[target])
+            .inc_by(count as f64);
+    }
+
+    /// Record rounds of probes skipped by `--max-probe-time-fraction`.
+    pub fn rounds_throttled(&self, target: &str, count: u32) {
+        self.throttled
+            .with_label_values(&[target])
+            .inc_by(count as f64);
+    }
+
+    /// Record a probe that was not run, and why.
+    pub fn probe_skipped(&self, operation: &str, target: &str, reason: &str) {
+        self.probe_skipped
+            .with_label_values(&[operation, target, reason])
+            .inc();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metrics;
+
+    #[test]
+    fn reserved_label_names() {
+        for name in ["le", "quantile", "target", "operation", "mode", "origin"] {
+            assert!(Metrics::reserved_label_name(name), "{}", name);
+        }
+        assert!(!Metrics::reserved_label_name("datacenter"));
+        assert!(Metrics::valid_label_name("datacenter"));
+        assert!(!Metrics::valid_label_name("__name"));
+        assert!(!Metrics::valid_label_name("1st"));
+    }
+}
@@ -0,0 +1,96 @@
+use rand::{Rng, RngCore};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::direct::Resolve;
+use crate::metrics::Metrics;
+use crate::prober::{ProbeResult, Prober};
+use crate::scratch;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Size of the mapped region; a random page of it is dirtied each time.
+pub const REGION_SIZE: usize = 16 * PAGE_SIZE;
+
+/// Writes to a memory-mapped scratch file and measures `msync(MS_SYNC)`, the
+/// way databases persisting through mmap do.
+pub struct MmapProbe {
+    path: PathBuf,
+    mechanism: &'static str,
+    region: *mut u8,
+}
+
+// The mapping is only used from the thread that owns the probe
+unsafe impl Send for MmapProbe {}
+
+impl MmapProbe {
+    pub fn new(scratch_dir: &Path, resolve: &Resolve) -> std::io::Result<MmapProbe> {
+        let scratch::ScratchFile {
+            file,
+            path,
+            mechanism,
+        } = scratch::create(scratch_dir, "mmap", false, resolve)?;
+        file.set_len(REGION_SIZE as u64)?;
+        let region = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                REGION_SIZE,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if region == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(MmapProbe {
+            path,
+            mechanism,
+            region: region as *mut u8,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// How the scratch file was created, see `scratch::create()`.
+    pub fn mechanism(&self) -> &'static str {
+        self.mechanism
+    }
+}
+
+impl Prober for MmapProbe {
+    /// Dirty a page of the mapping, and measure how long it takes to sync it.
+    fn probe(&mut self, _operation: &str, metrics: &Metrics, target: &str) -> ProbeResult {
+        let mut rng = rand::thread_rng();
+        let page = rng.gen_range(0..REGION_SIZE / PAGE_SIZE);
+        let data =
+            unsafe { std::slice::from_raw_parts_mut(self.region.add(page * PAGE_SIZE), PAGE_SIZE) };
+        rng.fill_bytes(data);
+
+        let start = Instant::now();
+        let ret =
+            unsafe { libc::msync(self.region as *mut libc::c_void, REGION_SIZE, libc::MS_SYNC) };
+        if ret != 0 {
+            metrics.error("msync", target);
+            return Err(format!(
+                "Error syncing mapping of {:?}: {}",
+                self.path,
+                std::io::Error::last_os_error()
+            ));
+        }
+        metrics.observe("msync", target, None, start.elapsed().as_secs_f64());
+        Ok(())
+    }
+}
+
+impl Drop for MmapProbe {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.region as *mut libc::c_void, REGION_SIZE);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+/// An entry from /proc/self/mountinfo.
+#[derive(Clone, Debug)]
+pub struct MountInfo {
+    pub mount_point: PathBuf,
+    pub fstype: String,
+    pub source: String,
+    /// Mount options, followed by the filesystem's own options
+    pub options: String,
+}
+
+/// Mount options which change how latency should be interpreted.
+const RELEVANT_OPTIONS: &[&str] = &[
+    "ro",
+    "sync",
+    "dirsync",
+    "noatime",
+    "relatime",
+    "strictatime",
+    "lazytime",
+    "soft",
+    "hard",
+    "noac",
+    "discard",
+    "dax",
+];
+
+/// Mount options with a value which change how latency should be
+/// interpreted (NFS version and transport, cache timeouts, ...).
+const RELEVANT_PREFIXES: &[&str] = &[
+    "vers=",
+    "proto=",
+    "nconnect=",
+    "rsize=",
+    "wsize=",
+    "actimeo=",
+    "timeo=",
+    "cache=",
+    "commit=",
+    "data=",
+];
+
+/// Keep only the mount options which are relevant to latency.
+pub fn relevant_options(options: &str) -> String {
+    options
+        .split(',')
+        .filter(|o| {
+            RELEVANT_OPTIONS.contains(o) || RELEVANT_PREFIXES.iter().any(|p| o.starts_with(p))
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Undo the octal escaping of spaces and such in mountinfo.
+pub fn unescape(field: &str) -> String {
+    let mut result = Vec::with_capacity(field.len());
+    let bytes = field.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && i + 4 <= bytes.len()
+            && bytes[i + 1..i + 4]
+                .iter()
+                .all(|b| (b'0'..=b'7').contains(b))
+        {
+            let c = bytes[i + 1..i + 4]
+                .iter()
+                .fold(0u8, |c, b| c.wrapping_mul(8).wrapping_add(b - b'0'));
+            result.push(c);
+            i += 4;
+            continue;
+        }
+        result.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+fn parse_line(line: &str) -> Option<MountInfo> {
+    let (before, after) = line.split_once(" - ")?;
+    let mut before = before.split(' ');
+    let _mount_id = before.next()?;
+    let _parent_id = before.next()?;
+    let _device = before.next()?;
+    let _root = before.next()?;
+    let mount_point = unescape(before.next()?);
+    let mount_options = before.next()?;
+    let mut after = after.split(' ');
+    let fstype = after.next()?.to_owned();
+    let source = unescape(after.next()?);
+    let mut options: Vec<&str> = mount_options.split(',').collect();
+    for option in after.next().unwrap_or("").split(',') {
+        if !option.is_empty() && !options.contains(&option) {
+            options.push(option);
+        }
+    }
+    Some(MountInfo {
+        mount_point: mount_point.into(),
+        fstype,
+        source,
+        options: options.join(","),
+    })
+}
+
+/// The type of a FUSE filesystem (the daemon, e.g. "s3fs" for "fuse.s3fs"),
+/// or None if it is not FUSE.
+pub fn fuse_type(mount: &MountInfo) -> Option<String> {
+    match mount.fstype.strip_prefix("fuse.") {
+        Some(subtype) => Some(subtype.to_owned()),
+        None if mount.fstype.starts_with("fuse") => Some(mount.fstype.clone()),
+        None => None,
+    }
+}
+
+/// Read all the mounts of this process.
+pub fn read_mounts() -> std::io::Result<Vec<MountInfo>> {
+    let mountinfo = read_to_string("/proc/self/mountinfo")?;
+    Ok(mountinfo.lines().filter_map(parse_line).collect())
+}
+
+/// Find the mount that a path is on.
+pub fn find_mount(path: &Path) -> Option<MountInfo> {
+    let path = path.canonicalize().ok()?;
+    // Later mounts hide earlier ones, so take the last longest match
+    read_mounts()
+        .ok()?
+        .into_iter()
+        .filter(|m| path.starts_with(&m.mount_point))
+        .fold(None, |best: Option<MountInfo>, m| match best {
+            Some(b) if b.mount_point.as_os_str().len() > m.mount_point.as_os_str().len() => Some(b),
+            _ => Some(m),
+        })
+}
@@ -0,0 +1,153 @@
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::CounterVec;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::metrics::{set_counter, Metrics};
+use crate::mounts::{find_mount, unescape};
+
+/// Collects the RPC statistics of the NFS mount of each target, from
+/// /proc/self/mountstats, at scrape time.
+#[derive(Clone)]
+pub struct MountStatsCollector {
+    targets: Vec<(String, PathBuf)>,
+    operations: CounterVec,
+    retransmissions: CounterVec,
+    timeouts: CounterVec,
+    queue: CounterVec,
+    rtt: CounterVec,
+    execute: CounterVec,
+}
+
+impl MountStatsCollector {
+    /// Create the collector, for the targets that are on NFS. Returns None
+    /// if there are none.
+    pub fn new<'a, I: IntoIterator<Item = (String, &'a Path)>>(
+        targets: I,
+        metrics: &Metrics,
+    ) -> Option<MountStatsCollector> {
+        let mut nfs_targets = Vec::new();
+        for (target, path) in targets {
+            if let Some(mount) = find_mount(path) {
+                if mount.fstype.starts_with("nfs") {
+                    info!("{:?} is on NFS mount {:?}", path, mount.mount_point);
+                    nfs_targets.push((target, mount.mount_point));
+                }
+            }
+        }
+        if nfs_targets.is_empty() {
+            return None;
+        }
+
+        let counter = |name: &str, help: &str| {
+            CounterVec::new(metrics.opts(name, help), &["target", "operation"]).unwrap()
+        };
+        Some(MountStatsCollector {
+            targets: nfs_targets,
+            operations: counter("nfs_rpc_operations_total", "Number of NFS RPC requests"),
+            retransmissions: counter(
+                "nfs_rpc_retransmissions_total",
+                "Number of NFS RPC retransmissions",
+            ),
+            timeouts: counter("nfs_rpc_timeouts_total", "Number of NFS RPC major timeouts"),
+            queue: counter(
+                "nfs_rpc_queue_seconds_total",
+                "Time NFS RPC requests spent queued on the client before being sent",
+            ),
+            rtt: counter(
+                "nfs_rpc_rtt_seconds_total",
+                "Time spent waiting for the server to reply to NFS RPC requests",
+            ),
+            execute: counter(
+                "nfs_rpc_execute_seconds_total",
+                "Total time of NFS RPC requests, from queueing to completion",
+            ),
+        })
+    }
+
+    fn record(&self, target: &str, line: &str) {
+        // Per-operation line: "OP: ops trans timeouts sent recv queue rtt execute"
+        let (op, values) = match line.trim().split_once(':') {
+            Some(v) => v,
+            None => return,
+        };
+        let values: Vec<f64> = values
+            .split_whitespace()
+            .filter_map(|v| v.parse().ok())
+            .collect();
+        if values.len() < 8 || values[0] == 0.0 {
+            return;
+        }
+        let labels = &[target, op];
+        set_counter(&self.operations.with_label_values(labels), values[0]);
+        set_counter(
+            &self.retransmissions.with_label_values(labels),
+            values[1] - values[0],
+        );
+        set_counter(&self.timeouts.with_label_values(labels), values[2]);
+        set_counter(&self.queue.with_label_values(labels), values[5] / 1000.0);
+        set_counter(&self.rtt.with_label_values(labels), values[6] / 1000.0);
+        set_counter(&self.execute.with_label_values(labels), values[7] / 1000.0);
+    }
+}
+
+impl Collector for MountStatsCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        let mut descs = Vec::new();
+        descs.extend(self.operations.desc());
+        descs.extend(self.retransmissions.desc());
+        descs.extend(self.timeouts.desc());
+        descs.extend(self.queue.desc());
+        descs.extend(self.rtt.desc());
+        descs.extend(self.execute.desc());
+        descs
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let mountstats = match read_to_string("/proc/self/mountstats") {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Can't read /proc/self/mountstats: {}", e);
+                return Vec::new();
+            }
+        };
+
+        // Sections start with "device SOURCE mounted on MOUNTPOINT with ..."
+        let mut current: Vec<&str> = Vec::new();
+        let mut in_ops = false;
+        for line in mountstats.lines() {
+            if let Some(rest) = line.strip_prefix("device ") {
+                in_ops = false;
+                current.clear();
+                let mount_point = rest
+                    .split_once(" mounted on ")
+                    .and_then(|(_, r)| r.split_once(" with "))
+                    .map(|(m, _)| PathBuf::from(unescape(m)));
+                if let Some(mount_point) = mount_point {
+                    for (target, target_mount) in &self.targets {
+                        if *target_mount == mount_point {
+                            current.push(target);
+                        }
+                    }
+                }
+            } else if line.trim() == "per-op statistics" {
+                in_ops = true;
+            } else if in_ops {
+                for target in &current {
+                    self.record(target, line);
+                }
+            }
+        }
+
+        let mut families = Vec::new();
+        families.extend(self.operations.collect());
+        families.extend(self.retransmissions.collect());
+        families.extend(self.timeouts.collect());
+        families.extend(self.queue.collect());
+        families.extend(self.rtt.collect());
+        families.extend(self.execute.collect());
+        families
+    }
+}
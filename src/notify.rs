@@ -0,0 +1,202 @@
+use prometheus::{Counter, CounterVec};
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::task::block_in_place;
+use tracing::{info, warn};
+
+use crate::client::{parse_url, request, Url};
+use crate::config::Config;
+use crate::metrics::Metrics;
+use crate::status::{quantile, SharedStatus};
+
+/// How often the conditions are checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Raises the flag itself when a target is in trouble, for deployments
+/// without an Alertmanager: posts to a webhook and/or runs a command when the
+/// p99 latency crosses a threshold or the probes keep failing, then again
+/// every cooldown while it lasts, and once when it recovers.
+pub struct Notifier {
+    webhook: Option<Url>,
+    command: Option<String>,
+    p99: Option<f64>,
+    window: Duration,
+    failures: Option<u32>,
+    cooldown: Duration,
+    /// When each ongoing problem, by target and condition, was last notified
+    active: HashMap<(String, &'static str), Instant>,
+    sent: CounterVec,
+    errors: Counter,
+}
+
+impl Notifier {
+    /// Set up the notifier, if a webhook or command and a condition are
+    /// configured.
+    pub fn new(config: &Config, metrics: &Metrics) -> Option<Notifier> {
+        if config.notify_webhook.is_none() && config.notify_command.is_none() {
+            return None;
+        }
+        if config.notify_p99.is_none() && config.notify_failures.is_none() {
+            warn!("No --notify-p99 or --notify-failures, no notifications will be sent");
+            return None;
+        }
+        let sent = CounterVec::new(
+            metrics.opts("notifications_total", "Number of notifications sent"),
+            &["target", "condition", "state"],
+        )
+        .unwrap();
+        metrics.register(&sent);
+        let errors = Counter::with_opts(metrics.opts(
+            "notification_errors_total",
+            "Number of notifications which couldn't be sent",
+        ))
+        .unwrap();
+        metrics.register(&errors);
+        Some(Notifier {
+            webhook: config
+                .notify_webhook
+                .as_deref()
+                .map(|url| parse_url(url).unwrap()),
+            command: config.notify_command.clone(),
+            p99: config.notify_p99.map(|d| d.as_secs_f64()),
+            window: config.notify_window,
+            failures: config.notify_failures,
+            cooldown: config.notify_cooldown,
+            active: HashMap::new(),
+            sent,
+            errors,
+        })
+    }
+
+    /// Check the targets forever, on a multi-threaded runtime.
+    pub async fn run(mut self, targets: Vec<String>, status: SharedStatus) {
+        loop {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+            block_in_place(|| self.check(&targets, &status));
+        }
+    }
+
+    fn check(&mut self, targets: &[String], status: &SharedStatus) {
+        for target in targets {
+            if let Some(threshold) = self.p99 {
+                let latencies = status
+                    .lock()
+                    .unwrap()
+                    .recent_latencies(target, self.window.as_secs_f64());
+                if let Some(p99) = quantile(&latencies, 0.99) {
+                    self.update(
+                        target,
+                        "latency",
+                        p99 > threshold,
+                        format!(
+                            "p99 latency over the last {} is {:?} (threshold {:?})",
+                            humantime::format_duration(self.window),
+                            Duration::from_secs_f64(p99),
+                            Duration::from_secs_f64(threshold)
+                        ),
+                    );
+                }
+            }
+            if let Some(threshold) = self.failures {
+                let failures = status.lock().unwrap().consecutive_failures(target);
+                self.update(
+                    target,
+                    "failures",
+                    failures >= threshold,
+                    format!("{} consecutive failed probes", failures),
+                );
+            }
+        }
+    }
+
+    /// Notify of a problem when it starts, after each cooldown while it
+    /// lasts, and when it ends.
+    fn update(&mut self, target: &str, condition: &'static str, failing: bool, message: String) {
+        let key = (target.to_owned(), condition);
+        let state = match (failing, self.active.get(&key)) {
+            (true, Some(last)) if last.elapsed() < self.cooldown => return,
+            (true, _) => {
+                self.active.insert(key, Instant::now());
+                "firing"
+            }
+            (false, Some(_)) => {
+                self.active.remove(&key);
+                "resolved"
+            }
+            (false, None) => return,
+        };
+        info!("Notifying: {} {} {}: {}", target, condition, state, message);
+        self.sent
+            .with_label_values(&[target, condition, state])
+            .inc();
+        let mut results = Vec::new();
+        if let Some(webhook) = &self.webhook {
+            results.push(Self::post(webhook, target, condition, state, &message));
+        }
+        if let Some(command) = &self.command {
+            results.push(Self::run_command(
+                command, target, condition, state, &message,
+            ));
+        }
+        for result in results {
+            if let Err(e) = result {
+                warn!("Can't send notification: {}", e);
+                self.errors.inc();
+            }
+        }
+    }
+
+    fn post(
+        webhook: &Url,
+        target: &str,
+        condition: &str,
+        state: &str,
+        message: &str,
+    ) -> Result<(), String> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64();
+        let body = serde_json::json!({
+            "target": target,
+            "condition": condition,
+            "state": state,
+            "message": message,
+            "timestamp": timestamp,
+        });
+        let (code, _) = request(
+            webhook,
+            "POST",
+            "",
+            &[("Content-Type", "application/json")],
+            body.to_string().as_bytes(),
+        )?;
+        if !(200..300).contains(&code) {
+            return Err(format!("Webhook replied with status {}", code));
+        }
+        Ok(())
+    }
+
+    fn run_command(
+        command: &str,
+        target: &str,
+        condition: &str,
+        state: &str,
+        message: &str,
+    ) -> Result<(), String> {
+        let mut child = Command::new("/bin/sh")
+            .arg("-c")
+            .arg(command)
+            .env("FS_LATENCY_TARGET", target)
+            .env("FS_LATENCY_CONDITION", condition)
+            .env("FS_LATENCY_STATE", state)
+            .env("FS_LATENCY_MESSAGE", message)
+            .stdin(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("Can't run {:?}: {}", command, e))?;
+        // Don't hold up the other notifications
+        std::thread::spawn(move || child.wait());
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+use std::path::{Path, PathBuf};
+
+use crate::mounts::{find_mount, unescape, MountInfo};
+
+/// The layers of an overlayfs mount, from its options: the upper directory
+/// (if it is writable), then the lower directories from the top.
+fn layers(mount: &MountInfo) -> Vec<(String, PathBuf)> {
+    let mut upper = Vec::new();
+    let mut lower = Vec::new();
+    for option in mount.options.split(',') {
+        let option = unescape(option);
+        if let Some(dir) = option.strip_prefix("upperdir=") {
+            upper.push(("upper".to_owned(), PathBuf::from(dir)));
+        } else if let Some(dirs) = option.strip_prefix("lowerdir=") {
+            // Colons in the paths are escaped with a backslash
+            let mut dir = String::new();
+            let mut chars = dirs.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => dir.extend(chars.next()),
+                    ':' => lower.push(std::mem::take(&mut dir)),
+                    c => dir.push(c),
+                }
+            }
+            lower.push(dir);
+        } else if let Some(dir) = option.strip_prefix("lowerdir+=") {
+            lower.push(dir.to_owned());
+        }
+    }
+    upper.extend(
+        lower
+            .into_iter()
+            .filter(|d| !d.is_empty())
+            .enumerate()
+            .map(|(i, d)| (format!("lower{}", i + 1), PathBuf::from(d))),
+    );
+    upper
+}
+
+/// If a file is on overlayfs, find the layer which holds it: "upper" or
+/// "lowerN" (1 being the top one), and the path of the file in it. Returns
+/// None if it isn't on overlayfs, or the layers can't be accessed (e.g. from
+/// inside the container).
+pub fn backing_layer(path: &Path) -> Option<(String, PathBuf)> {
+    let mount = find_mount(path).filter(|m| m.fstype == "overlay")?;
+    let relative = path
+        .canonicalize()
+        .ok()?
+        .strip_prefix(&mount.mount_point)
+        .ok()?
+        .to_owned();
+    layers(&mount)
+        .into_iter()
+        .map(|(layer, dir)| (layer, dir.join(&relative)))
+        .find(|(_, file)| file.symlink_metadata().is_ok())
+}
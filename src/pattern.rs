@@ -0,0 +1,65 @@
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use tracing::info;
+
+use crate::direct::Resolve;
+
+/// Size of the chunks the pattern is written in.
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// The 8 bytes of the pattern at `offset` (splitmix64 of the offset), which
+/// are different at every offset, so a read returning the data of another
+/// offset doesn't match either.
+fn word(offset: u64) -> [u8; 8] {
+    let mut z = offset.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    (z ^ (z >> 31)).to_le_bytes()
+}
+
+/// Fill a buffer with the pattern expected at `offset`, a multiple of 8.
+pub fn fill(buffer: &mut [u8], offset: u64) {
+    for (i, chunk) in buffer.chunks_mut(8).enumerate() {
+        chunk.copy_from_slice(&word(offset + 8 * i as u64)[..chunk.len()]);
+    }
+}
+
+/// Check data read at `offset`, a multiple of 8, against the pattern.
+/// Returns the offset of the first wrong byte.
+pub fn check(buffer: &[u8], offset: u64) -> Option<u64> {
+    for (i, chunk) in buffer.chunks(8).enumerate() {
+        let expected = word(offset + 8 * i as u64);
+        if let Some(j) = chunk.iter().zip(expected).position(|(a, b)| *a != b) {
+            return Some(offset + (8 * i + j) as u64);
+        }
+    }
+    None
+}
+
+/// Write the pattern over the whole file, unless its first and last blocks
+/// already hold it (from a previous run). Returns whether it was written.
+pub fn initialize(path: &Path, resolve: &Resolve) -> io::Result<bool> {
+    let file = resolve.open(path, libc::O_RDWR, 0)?;
+    let size = file.metadata()?.len();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let last = size.saturating_sub(4096) & !7;
+    let initialized = [0, last].iter().all(|&offset| {
+        let block = &mut buffer[..(size - offset).min(4096) as usize];
+        file.read_exact_at(block, offset).is_ok() && check(block, offset).is_none()
+    });
+    if initialized {
+        return Ok(false);
+    }
+
+    info!("Writing the verification pattern to {:?}", path);
+    let mut offset = 0;
+    while offset < size {
+        let chunk = &mut buffer[..(size - offset).min(CHUNK_SIZE as u64) as usize];
+        fill(chunk, offset);
+        file.write_all_at(chunk, offset)?;
+        offset += chunk.len() as u64;
+    }
+    file.sync_data()?;
+    Ok(true)
+}
@@ -0,0 +1,149 @@
+use prometheus::{CounterVec, GaugeVec};
+use std::collections::VecDeque;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use tracing::error;
+
+use crate::metrics::Metrics;
+
+/// A round of probes of a target, run by the workers.
+pub type Job = Box<dyn FnMut() + Send>;
+
+struct Slot {
+    target: String,
+    /// Rounds waiting or running
+    pending: usize,
+    running: bool,
+    job: Arc<Mutex<Job>>,
+}
+
+#[derive(Default)]
+struct Queue {
+    slots: Vec<Slot>,
+    /// Rounds waiting for a worker, by slot, oldest first
+    waiting: VecDeque<usize>,
+}
+
+/// Runs the probes of all the targets on a fixed number of threads
+/// (`--workers`) rather than one thread per target. Each target can only
+/// have `--queue-depth` rounds waiting or running: when its probes take
+/// longer than its interval, the next rounds are dropped and counted, instead
+/// of piling up or holding up the other targets.
+pub struct Pool {
+    depth: usize,
+    queue: Mutex<Queue>,
+    wake: Condvar,
+    queue_depth: GaugeVec,
+    dropped: CounterVec,
+}
+
+impl Pool {
+    /// Start the workers.
+    pub fn new(workers: usize, depth: usize, metrics: &Metrics) -> Arc<Pool> {
+        let queue_depth = GaugeVec::new(
+            metrics.opts(
+                "probe_queue_depth",
+                "Number of rounds of probes waiting for a worker or running",
+            ),
+            &["target"],
+        )
+        .unwrap();
+        metrics.register(&queue_depth);
+        let dropped = CounterVec::new(
+            metrics.opts(
+                "probe_dropped_total",
+                "Number of rounds of probes dropped because the queue of the target was full",
+            ),
+            &["target"],
+        )
+        .unwrap();
+        metrics.register(&dropped);
+        let pool = Arc::new(Pool {
+            depth,
+            queue: Mutex::new(Queue::default()),
+            wake: Condvar::new(),
+            queue_depth,
+            dropped,
+        });
+        for i in 0..workers {
+            let pool = pool.clone();
+            std::thread::Builder::new()
+                .name(format!("worker {}", i))
+                .spawn(move || pool.work())
+                .unwrap();
+        }
+        pool
+    }
+
+    /// Add a target, whose rounds of probes run `job`. Returns the index to
+    /// submit them with.
+    pub fn add(&self, target: &str, job: Job) -> usize {
+        self.queue_depth.with_label_values(&[target]).set(0.0);
+        self.dropped.with_label_values(&[target]);
+        let mut queue = self.queue.lock().unwrap();
+        queue.slots.push(Slot {
+            target: target.to_owned(),
+            pending: 0,
+            running: false,
+            job: Arc::new(Mutex::new(job)),
+        });
+        queue.slots.len() - 1
+    }
+
+    /// Queue a round of probes of a target, unless its queue is full.
+    /// Returns whether it was queued.
+    pub fn submit(&self, index: usize) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        let slot = &mut queue.slots[index];
+        if slot.pending >= self.depth {
+            self.dropped.with_label_values(&[&slot.target]).inc();
+            return false;
+        }
+        slot.pending += 1;
+        self.queue_depth
+            .with_label_values(&[&slot.target])
+            .set(slot.pending as f64);
+        queue.waiting.push_back(index);
+        self.wake.notify_one();
+        true
+    }
+
+    fn work(&self) {
+        loop {
+            let (index, job) = {
+                let mut queue = self.queue.lock().unwrap();
+                loop {
+                    // The rounds of a target run one at a time
+                    let next = queue.waiting.iter().position(|&i| !queue.slots[i].running);
+                    if let Some(position) = next {
+                        let index = queue.waiting.remove(position).unwrap();
+                        let slot = &mut queue.slots[index];
+                        slot.running = true;
+                        break (index, slot.job.clone());
+                    }
+                    queue = self.wake.wait(queue).unwrap();
+                }
+            };
+            // A panic in a job mustn't take the worker down, or leave the
+            // target marked as running forever (the rounds of probes of
+            // targets already catch theirs)
+            let panicked = {
+                let mut job = job.lock().unwrap();
+                catch_unwind(AssertUnwindSafe(|| (*job)())).is_err()
+            };
+
+            let mut queue = self.queue.lock().unwrap();
+            let slot = &mut queue.slots[index];
+            if panicked {
+                error!("The job of {} panicked", slot.target);
+            }
+            slot.running = false;
+            slot.pending -= 1;
+            self.queue_depth
+                .with_label_values(&[&slot.target])
+                .set(slot.pending as f64);
+            // Another round of that target can run now
+            self.wake.notify_all();
+        }
+    }
+}
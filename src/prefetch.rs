@@ -0,0 +1,99 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::direct::Resolve;
+use crate::metrics::Metrics;
+use crate::prober::{ProbeResult, Prober};
+
+/// Asks the kernel to prefetch a range of the target with `readahead()`,
+/// and measures how long it takes until a buffered read of that range
+/// completes, the way streaming readers rely on the page cache.
+pub struct PrefetchProbe {
+    path: PathBuf,
+    /// Opened without direct I/O, to go through the page cache
+    file: File,
+    file_size: u64,
+    buffer: Vec<u8>,
+    rng: StdRng,
+}
+
+impl PrefetchProbe {
+    pub fn new(
+        path: &Path,
+        size: usize,
+        resolve: &Resolve,
+        rng: StdRng,
+    ) -> Result<PrefetchProbe, String> {
+        let file = resolve
+            .open(path, libc::O_RDONLY, 0)
+            .map_err(|e| format!("Can't open {:?}: {}", path, e))?;
+        let file_size = file
+            .metadata()
+            .map_err(|e| format!("Can't read file length: {}", e))?
+            .len();
+        if file_size < size as u64 {
+            return Err(format!(
+                "File is too small to prefetch {} bytes: {} bytes",
+                size, file_size
+            ));
+        }
+        Ok(PrefetchProbe {
+            path: path.to_owned(),
+            file,
+            file_size,
+            buffer: vec![0; size],
+            rng,
+        })
+    }
+}
+
+impl Prober for PrefetchProbe {
+    fn probe(&mut self, _operation: &str, metrics: &Metrics, target: &str) -> ProbeResult {
+        let size = self.buffer.len() as u64;
+        let offset = self.rng.gen_range(0..(self.file_size - size) / 4096 + 1) * 4096;
+        let fd = self.file.as_raw_fd();
+
+        // Drop the range from the cache first, so it really gets read
+        unsafe {
+            libc::posix_fadvise(
+                fd,
+                offset as libc::off_t,
+                size as libc::off_t,
+                libc::POSIX_FADV_DONTNEED,
+            )
+        };
+
+        let start = Instant::now();
+        let ret = unsafe { libc::readahead(fd, offset as libc::off64_t, size as usize) };
+        if ret != 0 {
+            // Not supported on this file, use the portable hint
+            unsafe {
+                libc::posix_fadvise(
+                    fd,
+                    offset as libc::off_t,
+                    size as libc::off_t,
+                    libc::POSIX_FADV_WILLNEED,
+                )
+            };
+        }
+        let readahead = start.elapsed().as_secs_f64();
+        let start = Instant::now();
+        if let Err(e) = self.file.read_exact_at(&mut self.buffer, offset) {
+            metrics.error("prefetch", target);
+            return Err(format!(
+                "Error reading {:?} at offset {} after prefetch: {}",
+                self.path, offset, e
+            ));
+        }
+        let read = start.elapsed().as_secs_f64();
+        metrics.observe("prefetch", target, None, readahead + read);
+        metrics.observe_phase("prefetch", target, "readahead", readahead);
+        metrics.observe_phase("prefetch", target, "read", read);
+        Ok(())
+    }
+}
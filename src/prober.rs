@@ -0,0 +1,166 @@
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::config::TargetConfig;
+use crate::control::Control;
+use crate::metrics::Metrics;
+use crate::target::schedule;
+
+/// The outcome of running a probe once: `Ok` if the operation succeeded
+/// (its latency having been recorded), else the error message (the error
+/// having been counted).
+pub type ProbeResult = Result<(), String>;
+
+/// A probe measuring one or more operations on a target.
+///
+/// Implementations record the latency of the operations they run with
+/// `Metrics::observe()` and their failures with `Metrics::error()`, labeled
+/// with `target`, so they can time exactly the part that matters (e.g. not
+/// the setup of a scratch file).
+pub trait Prober: Send {
+    /// Run `operation` once.
+    fn probe(&mut self, operation: &str, metrics: &Metrics, target: &str) -> ProbeResult;
+}
+
+/// Creates the prober of a registered operation for a target, from the path
+/// of the target (after resolving templates) and its configuration.
+pub type ProberFactory = fn(&Path, &TargetConfig) -> Result<Box<dyn Prober>, String>;
+
+/// The operations added by downstream crates.
+static REGISTERED: Mutex<Vec<(String, ProberFactory)>> = Mutex::new(Vec::new());
+
+/// Add an operation which can then be selected with `--ops` like the
+/// built-in ones, for site-specific checks (e.g. opening a proprietary
+/// container format). This has to be called before parsing the
+/// configuration, usually at the start of `main()` before
+/// `exporter::run()`.
+pub fn register(operation: &str, factory: ProberFactory) {
+    let mut registered = REGISTERED.lock().unwrap();
+    registered.retain(|(o, _)| o != operation);
+    registered.push((operation.to_owned(), factory));
+}
+
+/// Get the factory of an operation added with `register()`.
+pub fn registered(operation: &str) -> Option<ProberFactory> {
+    REGISTERED
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(o, _)| o == operation)
+        .map(|(_, f)| *f)
+}
+
+/// Runs probers at a fixed interval, for services embedding the library.
+/// The exporter itself runs its targets with `Target::run()`, which adds
+/// templates, pausing and the control API.
+pub struct Scheduler {
+    target: String,
+    interval: Duration,
+    probers: Vec<(String, Box<dyn Prober>)>,
+}
+
+impl Scheduler {
+    pub fn new(target: String, interval: Duration) -> Scheduler {
+        Scheduler {
+            target,
+            interval,
+            probers: Vec::new(),
+        }
+    }
+
+    /// Add a prober, which will be run for `operation` every interval.
+    pub fn add(&mut self, operation: &str, prober: Box<dyn Prober>) {
+        self.probers.push((operation.to_owned(), prober));
+    }
+
+    /// Run every prober once, and return their results in order.
+    pub fn run_once(&mut self, metrics: &Metrics) -> Vec<(String, ProbeResult)> {
+        let mut results = Vec::with_capacity(self.probers.len());
+        for (operation, prober) in &mut self.probers {
+            let result = prober.probe(operation, metrics, &self.target);
+            if let Err(e) = &result {
+                warn!("{}", e);
+            }
+            results.push((operation.clone(), result));
+        }
+        metrics.set_success(&self.target, results.iter().all(|(_, r)| r.is_ok()));
+        metrics.set_last_probe(&self.target);
+        results
+    }
+
+    /// Run the probers forever. Like the targets, measurements that can't
+    /// start on time because the probes overran are skipped.
+    pub fn run(mut self, metrics: &Metrics) -> ! {
+        metrics.set_interval(&self.target, self.interval);
+        let (target, interval) = (self.target.clone(), self.interval);
+        schedule(&Control::default(), interval, metrics, &target, || {
+            self.run_once(metrics);
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use super::{ProbeResult, Prober, Scheduler};
+    use crate::metrics::Metrics;
+
+    /// Succeeds, or fails every other time.
+    struct Fake {
+        flaky: bool,
+        runs: u32,
+    }
+
+    impl Prober for Fake {
+        fn probe(&mut self, operation: &str, metrics: &Metrics, target: &str) -> ProbeResult {
+            self.runs += 1;
+            if self.flaky && self.runs % 2 == 1 {
+                metrics.error(operation, target);
+                return Err(format!("{} failed", operation));
+            }
+            metrics.observe(operation, target, None, 0.001);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn run_once() {
+        let metrics = Metrics::new("fs_latency_", false, false, &HashMap::new());
+        let mut scheduler = Scheduler::new("svc".to_owned(), Duration::from_secs(1));
+        scheduler.add(
+            "ok",
+            Box::new(Fake {
+                flaky: false,
+                runs: 0,
+            }),
+        );
+        scheduler.add(
+            "flaky",
+            Box::new(Fake {
+                flaky: true,
+                runs: 0,
+            }),
+        );
+        let targets = ["svc".to_owned()];
+
+        let results = scheduler.run_once(&metrics);
+        assert_eq!(
+            results,
+            [
+                ("ok".to_owned(), Ok(())),
+                ("flaky".to_owned(), Err("flaky failed".to_owned()))
+            ]
+        );
+        assert_eq!(metrics.failing(&targets), ["svc"]);
+        assert_eq!(metrics.error_count("flaky", "svc"), 1);
+
+        let results = scheduler.run_once(&metrics);
+        assert!(results.iter().all(|(_, r)| r.is_ok()));
+        assert!(metrics.failing(&targets).is_empty());
+        assert_eq!(metrics.error_count("flaky", "svc"), 1);
+    }
+}
@@ -0,0 +1,197 @@
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{Counter, Gauge, IntGauge, Opts};
+use std::collections::HashMap;
+use std::fs::{read_dir, read_to_string};
+
+use crate::metrics::set_counter;
+
+/// Collects metrics about the exporter process itself, from /proc/self.
+pub struct ProcessCollector {
+    cpu_total: Counter,
+    open_fds: IntGauge,
+    max_fds: IntGauge,
+    vsize: IntGauge,
+    rss: IntGauge,
+    start_time: Gauge,
+    threads: IntGauge,
+    descs: Vec<Desc>,
+}
+
+impl ProcessCollector {
+    pub fn new(const_labels: &HashMap<String, String>) -> ProcessCollector {
+        let opts =
+            |name: &str, help: &str| Opts::new(name, help).const_labels(const_labels.clone());
+        let cpu_total = Counter::with_opts(opts(
+            "process_cpu_seconds_total",
+            "Total user and system CPU time spent in seconds",
+        ))
+        .unwrap();
+        let open_fds =
+            IntGauge::with_opts(opts("process_open_fds", "Number of open file descriptors"))
+                .unwrap();
+        let max_fds = IntGauge::with_opts(opts(
+            "process_max_fds",
+            "Maximum number of open file descriptors",
+        ))
+        .unwrap();
+        let vsize = IntGauge::with_opts(opts(
+            "process_virtual_memory_bytes",
+            "Virtual memory size in bytes",
+        ))
+        .unwrap();
+        let rss = IntGauge::with_opts(opts(
+            "process_resident_memory_bytes",
+            "Resident memory size in bytes",
+        ))
+        .unwrap();
+        let start_time = Gauge::with_opts(opts(
+            "process_start_time_seconds",
+            "Start time of the process since unix epoch in seconds",
+        ))
+        .unwrap();
+        let threads = IntGauge::with_opts(opts(
+            "process_threads",
+            "Number of OS threads in the process",
+        ))
+        .unwrap();
+
+        let mut descs = Vec::new();
+        descs.extend(cpu_total.desc().into_iter().cloned());
+        descs.extend(open_fds.desc().into_iter().cloned());
+        descs.extend(max_fds.desc().into_iter().cloned());
+        descs.extend(vsize.desc().into_iter().cloned());
+        descs.extend(rss.desc().into_iter().cloned());
+        descs.extend(start_time.desc().into_iter().cloned());
+        descs.extend(threads.desc().into_iter().cloned());
+
+        ProcessCollector {
+            cpu_total,
+            open_fds,
+            max_fds,
+            vsize,
+            rss,
+            start_time,
+            threads,
+            descs,
+        }
+    }
+}
+
+/// Read the fields of a stat file (/proc/self/stat), starting with field 3
+/// (state). Also returns the command name (field 2).
+fn read_stat(path: &str) -> Option<(String, Vec<String>)> {
+    let stat = read_to_string(path).ok()?;
+    // The command name is in parentheses and may contain spaces
+    let (start, rest) = stat.rsplit_once(')')?;
+    let (_, name) = start.split_once('(')?;
+    Some((
+        name.to_owned(),
+        rest.split_whitespace().map(|s| s.to_owned()).collect(),
+    ))
+}
+
+/// The CPU time used by each thread of the exporter, as JSON, to find out
+/// which part of it is busy.
+pub fn threads_json() -> serde_json::Value {
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as f64;
+    let mut threads = Vec::new();
+    if let Ok(entries) = read_dir("/proc/self/task") {
+        for entry in entries.flatten() {
+            let tid = entry.file_name().to_string_lossy().into_owned();
+            let path = format!("/proc/self/task/{}/stat", tid);
+            if let Some((name, stat)) = read_stat(&path) {
+                let field = |n: usize| -> f64 {
+                    stat.get(n - 3).and_then(|v| v.parse().ok()).unwrap_or(0.0)
+                };
+                threads.push(serde_json::json!({
+                    "tid": tid.parse::<u64>().unwrap_or(0),
+                    "name": name,
+                    "user_seconds": field(14) / ticks,
+                    "system_seconds": field(15) / ticks,
+                }));
+            }
+        }
+    }
+    threads.sort_by_key(|t| t["tid"].as_u64());
+    threads.into()
+}
+
+/// Resource usage of the calling thread.
+pub struct ThreadUsage {
+    pub user_seconds: f64,
+    pub system_seconds: f64,
+    /// Number of times the thread was preempted
+    pub involuntary_switches: u64,
+}
+
+impl ThreadUsage {
+    pub fn now() -> ThreadUsage {
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        unsafe { libc::getrusage(libc::RUSAGE_THREAD, &mut usage) };
+        let seconds = |t: libc::timeval| t.tv_sec as f64 + t.tv_usec as f64 / 1e6;
+        ThreadUsage {
+            user_seconds: seconds(usage.ru_utime),
+            system_seconds: seconds(usage.ru_stime),
+            involuntary_switches: usage.ru_nivcsw as u64,
+        }
+    }
+}
+
+/// Read the boot time from /proc/stat.
+fn read_boot_time() -> Option<u64> {
+    let stat = read_to_string("/proc/stat").ok()?;
+    stat.lines()
+        .find_map(|l| l.strip_prefix("btime "))
+        .and_then(|v| v.trim().parse().ok())
+}
+
+/// Read the soft limit on open files from /proc/self/limits.
+fn read_max_fds() -> Option<i64> {
+    let limits = read_to_string("/proc/self/limits").ok()?;
+    let line = limits.lines().find(|l| l.starts_with("Max open files"))?;
+    line["Max open files".len()..]
+        .split_whitespace()
+        .next()
+        .and_then(|v| v.parse().ok())
+}
+
+impl Collector for ProcessCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as f64;
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as i64;
+
+        // Fields are numbered from 3 in proc(5)
+        if let Some((_, stat)) = read_stat("/proc/self/stat") {
+            let field =
+                |n: usize| -> f64 { stat.get(n - 3).and_then(|v| v.parse().ok()).unwrap_or(0.0) };
+            set_counter(&self.cpu_total, (field(14) + field(15)) / ticks);
+            self.threads.set(field(20) as i64);
+            self.vsize.set(field(23) as i64);
+            self.rss.set(field(24) as i64 * page_size);
+            if let Some(boot_time) = read_boot_time() {
+                self.start_time.set(boot_time as f64 + field(22) / ticks);
+            }
+        }
+        if let Ok(fds) = read_dir("/proc/self/fd") {
+            self.open_fds.set(fds.count() as i64);
+        }
+        if let Some(max) = read_max_fds() {
+            self.max_fds.set(max);
+        }
+
+        let mut families = Vec::new();
+        families.extend(self.cpu_total.collect());
+        families.extend(self.open_fds.collect());
+        families.extend(self.max_fds.collect());
+        families.extend(self.vsize.collect());
+        families.extend(self.rss.collect());
+        families.extend(self.start_time.collect());
+        families.extend(self.threads.collect());
+        families
+    }
+}
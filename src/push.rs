@@ -0,0 +1,45 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::block_in_place;
+use tracing::{debug, warn};
+
+use crate::client::{encode, parse_url, request, Url};
+use crate::config::redact_url;
+use crate::metrics::Metrics;
+
+/// Send our metrics to the aggregator once.
+fn push(url: &Url, origin: &str, body: &[u8]) -> Result<(), String> {
+    let (code, _) = request(
+        url,
+        "POST",
+        &format!("/push?origin={}", encode(origin)),
+        &[("Content-Type", "text/plain; version=0.0.4")],
+        body,
+    )?;
+    if (200..300).contains(&code) {
+        Ok(())
+    } else {
+        Err(format!("Aggregator replied with status {}", code))
+    }
+}
+
+/// Push our metrics to the aggregator forever, on a multi-threaded runtime.
+pub async fn run(url: String, origin: String, interval: Duration, metrics: Arc<Metrics>) {
+    use prometheus::Encoder;
+
+    let aggregator = parse_url(&url).unwrap();
+    let url = redact_url(&url);
+    loop {
+        block_in_place(|| {
+            let mut body = Vec::new();
+            prometheus::TextEncoder::new()
+                .encode(&metrics.gather(&[]).unwrap(), &mut body)
+                .unwrap();
+            match push(&aggregator, &origin, &body) {
+                Ok(()) => debug!("Pushed metrics to {}", url),
+                Err(e) => warn!("Can't push metrics to {}: {}", url, e),
+            }
+        });
+        tokio::time::sleep(interval).await;
+    }
+}
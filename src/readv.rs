@@ -0,0 +1,110 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::direct::{open_direct, AlignedBuffer, BufferOptions, Resolve};
+use crate::metrics::Metrics;
+use crate::prober::{ProbeResult, Prober};
+
+/// Reads several blocks of the target with a single direct `preadv()`, each
+/// into its own buffer, the way databases fill the pages of their buffer
+/// pool with vectored I/O.
+pub struct ReadvProbe {
+    path: PathBuf,
+    file: File,
+    file_size: u64,
+    /// One separately allocated buffer per segment
+    segments: Vec<AlignedBuffer>,
+    rng: StdRng,
+}
+
+impl ReadvProbe {
+    pub fn new(
+        path: &Path,
+        segments: usize,
+        segment_size: usize,
+        buffers: &BufferOptions,
+        resolve: &Resolve,
+        rng: StdRng,
+    ) -> Result<ReadvProbe, String> {
+        let file = open_direct(path, false, resolve)
+            .map_err(|e| format!("Can't open {:?}: {}", path, e))?;
+        let file_size = file
+            .metadata()
+            .map_err(|e| format!("Can't read file length: {}", e))?
+            .len();
+        let size = (segments * segment_size) as u64;
+        if file_size < size {
+            return Err(format!(
+                "File is too small to read {} segments of {} bytes: {} bytes",
+                segments, segment_size, file_size
+            ));
+        }
+        let segments = (0..segments)
+            .map(|_| AlignedBuffer::allocate(segment_size, buffers))
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("Can't allocate read buffer: {}", e))?;
+        Ok(ReadvProbe {
+            path: path.to_owned(),
+            file,
+            file_size,
+            segments,
+            rng,
+        })
+    }
+}
+
+impl Prober for ReadvProbe {
+    fn probe(&mut self, _operation: &str, metrics: &Metrics, target: &str) -> ProbeResult {
+        let size: u64 = self
+            .segments
+            .iter()
+            .map(|s| s.as_slice().len() as u64)
+            .sum();
+        let offset = self.rng.gen_range(0..(self.file_size - size) / 4096 + 1) * 4096;
+        let iovecs: Vec<libc::iovec> = self
+            .segments
+            .iter_mut()
+            .map(|s| {
+                let slice = s.as_mut_slice();
+                libc::iovec {
+                    iov_base: slice.as_mut_ptr() as *mut libc::c_void,
+                    iov_len: slice.len(),
+                }
+            })
+            .collect();
+
+        let start = Instant::now();
+        let ret = unsafe {
+            libc::preadv(
+                self.file.as_raw_fd(),
+                iovecs.as_ptr(),
+                iovecs.len() as libc::c_int,
+                offset as libc::off_t,
+            )
+        };
+        let duration = start.elapsed().as_secs_f64();
+        let error = if ret < 0 {
+            Some(std::io::Error::last_os_error().to_string())
+        } else if (ret as u64) < size {
+            Some(format!("short read of {} bytes", ret))
+        } else {
+            None
+        };
+        if let Some(e) = error {
+            metrics.error("readv", target);
+            return Err(format!(
+                "Error reading {} segments of {:?} at offset {}: {}",
+                self.segments.len(),
+                self.path,
+                offset,
+                e
+            ));
+        }
+        metrics.observe("readv", target, None, duration);
+        Ok(())
+    }
+}
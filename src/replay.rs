@@ -0,0 +1,113 @@
+use std::path::Path;
+
+/// One operation of a trace, and the offset to read for reads.
+#[derive(Debug)]
+pub struct Step {
+    pub operation: String,
+    pub offset: Option<u64>,
+}
+
+/// A recorded sequence of operations, replayed one per round instead of
+/// picking them and their offsets at random, starting over at the end.
+pub struct Replay {
+    steps: Vec<Step>,
+    next: usize,
+}
+
+impl Replay {
+    /// Read a trace: one operation per line, `OPERATION [OFFSET]`, or only
+    /// an `OFFSET` for a read, with comments starting with `#`.
+    pub fn load(filename: &Path) -> Result<Replay, String> {
+        let content = std::fs::read_to_string(filename)
+            .map_err(|e| format!("Can't read {:?}: {}", filename, e))?;
+        let mut steps = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || format!("Invalid line {} in {:?}", number + 1, filename);
+            let mut words = line.split_whitespace();
+            let first = words.next().unwrap();
+            let step = match first.parse() {
+                Ok(offset) => Step {
+                    operation: "read".to_owned(),
+                    offset: Some(offset),
+                },
+                Err(_) => Step {
+                    operation: first.to_owned(),
+                    offset: match words.next() {
+                        Some(offset) => Some(offset.parse().map_err(|_| invalid())?),
+                        None => None,
+                    },
+                },
+            };
+            if words.next().is_some() || (step.offset.is_some() && step.operation != "read") {
+                return Err(invalid());
+            }
+            steps.push(step);
+        }
+        if steps.is_empty() {
+            return Err(format!("No operations in {:?}", filename));
+        }
+        Ok(Replay { steps, next: 0 })
+    }
+
+    /// The operations of the trace.
+    pub fn operations(&self) -> impl Iterator<Item = &str> {
+        self.steps.iter().map(|s| s.operation.as_str())
+    }
+
+    /// The next step of the trace.
+    pub fn next_step(&mut self) -> &Step {
+        let step = &self.steps[self.next];
+        self.next = (self.next + 1) % self.steps.len();
+        step
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Replay;
+
+    /// Load a trace from a temporary file.
+    fn load(name: &str, content: &str) -> Result<Replay, String> {
+        let path = std::env::temp_dir().join(format!("fs-latency-{}-{}", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        let replay = Replay::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        replay
+    }
+
+    #[test]
+    fn load_trace() {
+        let mut replay = load("trace", "# trace\n4096\n\nread 8192 # again\nfsync\n").unwrap();
+        assert_eq!(
+            replay.operations().collect::<Vec<_>>(),
+            ["read", "read", "fsync"]
+        );
+        let steps: Vec<_> = (0..4)
+            .map(|_| {
+                let step = replay.next_step();
+                (step.operation.clone(), step.offset)
+            })
+            .collect();
+        assert_eq!(
+            steps,
+            [
+                ("read".to_owned(), Some(4096)),
+                ("read".to_owned(), Some(8192)),
+                ("fsync".to_owned(), None),
+                ("read".to_owned(), Some(4096)),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_traces() {
+        assert!(load("empty", "# nothing\n").is_err());
+        assert!(load("offset", "read x\n").is_err());
+        assert!(load("fsync", "fsync 4096\n").is_err());
+        assert!(load("extra", "read 4096 8192\n").is_err());
+    }
+}
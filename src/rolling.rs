@@ -0,0 +1,83 @@
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{HistogramOpts, HistogramVec};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::series::Series;
+
+/// Latency of the recent operations, by target and operation.
+type Samples = Series<VecDeque<(Instant, f64)>>;
+
+/// Histograms of the latency over a rolling window (e.g. the last 5
+/// minutes), which unlike the cumulative ones show the current latency
+/// directly, for instances which are read without `rate()` (pushed, or read
+/// from a textfile).
+#[derive(Clone)]
+pub struct RollingCollector {
+    window: Duration,
+    /// Options of the histogram of each target (with its buckets)
+    targets: Vec<(String, HistogramOpts)>,
+    descs: Vec<Desc>,
+    samples: Arc<Mutex<Samples>>,
+}
+
+impl RollingCollector {
+    pub fn new(window: Duration, targets: Vec<(String, HistogramOpts)>) -> RollingCollector {
+        let descs = targets
+            .iter()
+            .flat_map(|(_, opts)| {
+                let histogram = HistogramVec::new(opts.clone(), &["operation"]).unwrap();
+                histogram.desc().into_iter().cloned().collect::<Vec<_>>()
+            })
+            .collect();
+        RollingCollector {
+            window,
+            targets,
+            descs,
+            samples: Arc::new(Mutex::new(Series::default())),
+        }
+    }
+
+    pub fn observe(&self, operation: &str, target: &str, duration: f64) {
+        let now = Instant::now();
+        let mut samples = self.samples.lock().unwrap();
+        let recent = samples.get_or_default(target, operation);
+        recent.push_back((now, duration));
+        Self::expire(recent, now, self.window);
+    }
+
+    fn expire(recent: &mut VecDeque<(Instant, f64)>, now: Instant, window: Duration) {
+        while let Some((time, _)) = recent.front() {
+            if now.duration_since(*time) <= window {
+                break;
+            }
+            recent.pop_front();
+        }
+    }
+}
+
+impl Collector for RollingCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let now = Instant::now();
+        let mut samples = self.samples.lock().unwrap();
+        let mut families = Vec::new();
+        for (target, opts) in &self.targets {
+            let histogram = HistogramVec::new(opts.clone(), &["operation"]).unwrap();
+            for (operation, recent) in samples.target_mut(target) {
+                Self::expire(recent, now, self.window);
+                let histogram = histogram.with_label_values(&[operation]);
+                for (_, duration) in recent.iter() {
+                    histogram.observe(*duration);
+                }
+            }
+            families.extend(histogram.collect());
+        }
+        families
+    }
+}
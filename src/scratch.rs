@@ -0,0 +1,143 @@
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::config::hostname;
+use crate::direct::Resolve;
+use crate::fsstats::statvfs;
+
+/// A file to run write probes against.
+pub struct ScratchFile {
+    pub file: File,
+    /// Path of the file, or of the directory for an unnamed file
+    pub path: PathBuf,
+    /// How the file was created: "tmpfile" or "named"
+    pub mechanism: &'static str,
+}
+
+/// Create a scratch file in `dir`.
+///
+/// Where supported, this uses O_TMPFILE, so the file is never visible and
+/// disappears when the exporter stops, even when it crashes. Otherwise the
+/// file is named `fs-latency-exporter.HOST.PID.SUFFIX`, and removed by
+/// `cleanup()` the next time the exporter starts.
+pub fn create(
+    dir: &Path,
+    suffix: &str,
+    direct: bool,
+    resolve: &Resolve,
+) -> io::Result<ScratchFile> {
+    let flags = if direct { libc::O_DIRECT } else { 0 };
+    create_with_flags(dir, suffix, flags, resolve)
+}
+
+/// Create a scratch file in `dir` like `create()`, opened with additional
+/// `flags` (e.g. O_SYNC).
+pub fn create_with_flags(
+    dir: &Path,
+    suffix: &str,
+    flags: libc::c_int,
+    resolve: &Resolve,
+) -> io::Result<ScratchFile> {
+    let flags = libc::O_RDWR | flags;
+    match resolve.open(dir, flags | libc::O_TMPFILE, 0o600) {
+        Ok(file) => {
+            return Ok(ScratchFile {
+                file,
+                path: dir.to_owned(),
+                mechanism: "tmpfile",
+            })
+        }
+        // Not supported by the filesystem or kernel, use a named file
+        Err(e)
+            if [libc::EOPNOTSUPP, libc::EISDIR, libc::EINVAL]
+                .contains(&e.raw_os_error().unwrap_or(0)) => {}
+        Err(e) => return Err(e),
+    }
+
+    let path = dir.join(format!(
+        "fs-latency-exporter.{}.{}.{}",
+        hostname(),
+        std::process::id(),
+        suffix
+    ));
+    let file = resolve.open(&path, flags | libc::O_CREAT, 0o666)?;
+    Ok(ScratchFile {
+        file,
+        path,
+        mechanism: "named",
+    })
+}
+
+/// Get the scratch directory ready for write probes needing `needed` bytes.
+///
+/// This removes the files left behind by previous runs, and checks that the
+/// files of the exporter in the directory (from all instances) stay within
+/// `budget` bytes, and that the filesystem keeps at least `min_free` bytes
+/// available.
+pub fn prepare(dir: &Path, needed: u64, budget: u64, min_free: u64) -> Result<(), String> {
+    cleanup(dir);
+
+    let mut used = 0;
+    let entries =
+        std::fs::read_dir(dir).map_err(|e| format!("Can't list scratch dir {:?}: {}", dir, e))?;
+    for entry in entries.flatten() {
+        if entry
+            .file_name()
+            .to_str()
+            .is_some_and(|n| n.starts_with("fs-latency-exporter."))
+        {
+            used += entry.metadata().map(|m| m.len()).unwrap_or(0);
+        }
+    }
+    if used + needed > budget {
+        return Err(format!(
+            "Scratch files in {:?} would use {} bytes, more than the scratch-budget of {}",
+            dir,
+            used + needed,
+            budget
+        ));
+    }
+
+    let free = free_space(dir).map_err(|e| format!("Can't statvfs {:?}: {}", dir, e))?;
+    if free < min_free + needed {
+        return Err(format!(
+            "Only {} bytes free in {:?}, less than the scratch-min-free of {}",
+            free, dir, min_free
+        ));
+    }
+    Ok(())
+}
+
+/// The space available to unprivileged users on the filesystem of `dir`.
+pub fn free_space(dir: &Path) -> io::Result<u64> {
+    let stat = statvfs(dir)?;
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Remove the named scratch files left in `dir` by instances of the exporter
+/// which ran on this machine and are no longer running.
+fn cleanup(dir: &Path) {
+    let prefix = format!("fs-latency-exporter.{}.", hostname());
+    let entries = match std::fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let pid = name
+            .to_str()
+            .and_then(|n| n.strip_prefix(&prefix))
+            .and_then(|n| n.split('.').next())
+            .and_then(|n| n.parse::<u32>().ok());
+        if let Some(pid) = pid {
+            if pid != std::process::id() && !Path::new(&format!("/proc/{}", pid)).exists() {
+                match std::fs::remove_file(entry.path()) {
+                    Ok(()) => info!("Removed stale scratch file {:?}", entry.path()),
+                    Err(e) => warn!("Can't remove stale scratch file {:?}: {}", entry.path(), e),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use prometheus::{Counter, CounterVec, Gauge, GaugeVec};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use crate::checksum::{fnv1a, FNV_OFFSET};
+use crate::direct::{open_direct, AlignedBuffer, Resolve};
+use crate::metrics::Metrics;
+
+const CHUNK_SIZE: usize = 1 << 20;
+const MANIFEST_HEADER: &str = "fs-latency-exporter scrub manifest v1";
+
+/// Stored checksums for each chunk of the file.
+struct Manifest {
+    file_size: u64,
+    chunk_size: usize,
+    checksums: Vec<u64>,
+}
+
+impl Manifest {
+    fn load(path: &Path) -> std::io::Result<Manifest> {
+        let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid manifest");
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        if lines.next().transpose()?.as_deref() != Some(MANIFEST_HEADER) {
+            return Err(invalid());
+        }
+        let mut field = |name: &str| -> std::io::Result<u64> {
+            let line = lines.next().transpose()?.ok_or_else(invalid)?;
+            line.strip_prefix(name)
+                .and_then(|v| v.strip_prefix(' '))
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(invalid)
+        };
+        let file_size = field("size")?;
+        let chunk_size = field("chunk_size")? as usize;
+        let mut checksums = Vec::new();
+        for line in lines {
+            let checksum = u64::from_str_radix(line?.trim(), 16).map_err(|_| invalid())?;
+            checksums.push(checksum);
+        }
+        Ok(Manifest {
+            file_size,
+            chunk_size,
+            checksums,
+        })
+    }
+
+    fn save(&self, path: &Path) -> std::io::Result<()> {
+        // Write to a temporary file, then rename it into place
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+        let mut file = File::create(&temp_path)?;
+        writeln!(file, "{}", MANIFEST_HEADER)?;
+        writeln!(file, "size {}", self.file_size)?;
+        writeln!(file, "chunk_size {}", self.chunk_size)?;
+        for checksum in &self.checksums {
+            writeln!(file, "{:016x}", checksum)?;
+        }
+        file.sync_all()?;
+        std::fs::rename(&temp_path, path)
+    }
+}
+
+/// Reads the whole file sequentially at a low rate, checking each chunk
+/// against the checksums stored in a manifest.
+pub struct Scrubber {
+    filename: PathBuf,
+    manifest_path: PathBuf,
+    rate: u64,
+    target: String,
+    resolve: Resolve,
+    progress: Gauge,
+    bytes: Counter,
+    mismatches: Counter,
+    passes: Counter,
+}
+
+impl Scrubber {
+    pub fn new(
+        filename: PathBuf,
+        manifest_path: PathBuf,
+        rate: u64,
+        target: String,
+        resolve: Resolve,
+        metrics: &Metrics,
+    ) -> Scrubber {
+        let progress = GaugeVec::new(
+            metrics.opts(
+                "scrub_progress_ratio",
+                "Position of the scrub in the current pass, from 0 to 1",
+            ),
+            &["target"],
+        )
+        .unwrap();
+        metrics.register(&progress);
+        let bytes = CounterVec::new(
+            metrics.opts(
+                "scrub_read_bytes_total",
+                "Number of bytes read by the scrub",
+            ),
+            &["target"],
+        )
+        .unwrap();
+        metrics.register(&bytes);
+        let mismatches = CounterVec::new(
+            metrics.opts(
+                "scrub_mismatches_total",
+                "Number of chunks which didn't match the manifest",
+            ),
+            &["target"],
+        )
+        .unwrap();
+        metrics.register(&mismatches);
+        let passes = CounterVec::new(
+            metrics.opts("scrub_passes_total", "Number of complete scrub passes"),
+            &["target"],
+        )
+        .unwrap();
+        metrics.register(&passes);
+        metrics.init_operation("scrub", &target);
+
+        Scrubber {
+            progress: progress.with_label_values(&[&target]),
+            bytes: bytes.with_label_values(&[&target]),
+            mismatches: mismatches.with_label_values(&[&target]),
+            passes: passes.with_label_values(&[&target]),
+            filename,
+            manifest_path,
+            rate,
+            target,
+            resolve,
+        }
+    }
+
+    /// Scrub forever, should be run in its own thread.
+    pub fn run(self, metrics: &Metrics) {
+        let file = match open_direct(&self.filename, false, &self.resolve) {
+            Ok(f) => f,
+            Err(e) => {
+                error!("Scrub can't open {:?}: {}", self.filename, e);
+                metrics.error("scrub", &self.target);
+                return;
+            }
+        };
+        let mut buffer = AlignedBuffer::new(CHUNK_SIZE);
+
+        loop {
+            let file_size = match file.metadata() {
+                Ok(m) => m.len(),
+                Err(e) => {
+                    error!("Scrub can't read file length: {}", e);
+                    metrics.error("scrub", &self.target);
+                    std::thread::sleep(Duration::from_secs(60));
+                    continue;
+                }
+            };
+
+            // Load the manifest, or build a new one if there is none
+            let existing = match Manifest::load(&self.manifest_path) {
+                Ok(m) if m.file_size == file_size && m.chunk_size == CHUNK_SIZE => Some(m),
+                Ok(_) => {
+                    warn!("Scrub manifest doesn't match the file, building a new one");
+                    None
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    info!("No scrub manifest, building one");
+                    None
+                }
+                Err(e) => {
+                    warn!("Can't load scrub manifest, building a new one: {}", e);
+                    None
+                }
+            };
+            let mut checksums = Vec::new();
+
+            let mut offset = 0;
+            while offset < file_size {
+                // Read a chunk
+                let mut len = 0;
+                let mut failed = false;
+                while len < CHUNK_SIZE {
+                    match file.read_at(&mut buffer.as_mut_slice()[len..], offset + len as u64) {
+                        Ok(0) => break,
+                        Ok(n) => len += n,
+                        Err(e) => {
+                            error!("Scrub error reading at offset {}: {}", offset, e);
+                            metrics.error("scrub", &self.target);
+                            failed = true;
+                            break;
+                        }
+                    }
+                }
+                if failed {
+                    break;
+                }
+
+                // Compare the checksum
+                let checksum = fnv1a(FNV_OFFSET, &buffer.as_slice()[..len]);
+                let index = checksums.len();
+                if let Some(manifest) = &existing {
+                    if manifest.checksums.get(index) != Some(&checksum) {
+                        error!("Scrub mismatch in chunk at offset {}", offset);
+                        self.mismatches.inc();
+                    }
+                }
+                checksums.push(checksum);
+
+                offset += len as u64;
+                self.bytes.inc_by(len as f64);
+                self.progress.set(offset as f64 / file_size as f64);
+
+                // Throttle
+                std::thread::sleep(Duration::from_secs_f64(len as f64 / self.rate as f64));
+            }
+
+            if offset >= file_size {
+                self.passes.inc();
+                info!("Scrub pass complete");
+                if existing.is_none() {
+                    let manifest = Manifest {
+                        file_size,
+                        chunk_size: CHUNK_SIZE,
+                        checksums,
+                    };
+                    if let Err(e) = manifest.save(&self.manifest_path) {
+                        error!("Can't write scrub manifest: {}", e);
+                    }
+                }
+            } else {
+                std::thread::sleep(Duration::from_secs(60));
+            }
+        }
+    }
+}
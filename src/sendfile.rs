@@ -0,0 +1,162 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::direct::Resolve;
+use crate::metrics::Metrics;
+use crate::prober::{ProbeResult, Prober};
+
+/// Copies a range of the target to `/dev/null` with `sendfile()`, the
+/// zero-copy path used by file servers, falling back to `splice()` through a
+/// pipe where `sendfile()` isn't supported.
+pub struct SendfileProbe {
+    path: PathBuf,
+    file: File,
+    file_size: u64,
+    size: usize,
+    null: File,
+    rng: StdRng,
+}
+
+impl SendfileProbe {
+    pub fn new(
+        path: &Path,
+        size: usize,
+        resolve: &Resolve,
+        rng: StdRng,
+    ) -> Result<SendfileProbe, String> {
+        let file = resolve
+            .open(path, libc::O_RDONLY, 0)
+            .map_err(|e| format!("Can't open {:?}: {}", path, e))?;
+        let file_size = file
+            .metadata()
+            .map_err(|e| format!("Can't read file length: {}", e))?
+            .len();
+        if file_size < size as u64 {
+            return Err(format!(
+                "File is too small to send {} bytes: {} bytes",
+                size, file_size
+            ));
+        }
+        let null = std::fs::OpenOptions::new()
+            .write(true)
+            .open("/dev/null")
+            .map_err(|e| format!("Can't open /dev/null: {}", e))?;
+        Ok(SendfileProbe {
+            path: path.to_owned(),
+            file,
+            file_size,
+            size,
+            null,
+            rng,
+        })
+    }
+
+    fn send(&self, offset: u64) -> std::io::Result<()> {
+        let mut position = offset as libc::off_t;
+        let end = position + self.size as libc::off_t;
+        while position < end {
+            let ret = unsafe {
+                libc::sendfile(
+                    self.null.as_raw_fd(),
+                    self.file.as_raw_fd(),
+                    &mut position,
+                    (end - position) as usize,
+                )
+            };
+            if ret < 0 {
+                let err = std::io::Error::last_os_error();
+                return match err.raw_os_error() {
+                    Some(libc::EINVAL) | Some(libc::ENOSYS) => self.splice(position, end),
+                    _ => Err(err),
+                };
+            } else if ret == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        Ok(())
+    }
+
+    fn splice(&self, mut position: libc::off_t, end: libc::off_t) -> std::io::Result<()> {
+        let mut pipe = [0; 2];
+        if unsafe { libc::pipe2(pipe.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let result = (|| {
+            while position < end {
+                let ret = unsafe {
+                    libc::splice(
+                        self.file.as_raw_fd(),
+                        &mut position,
+                        pipe[1],
+                        std::ptr::null_mut(),
+                        (end - position) as usize,
+                        libc::SPLICE_F_MOVE,
+                    )
+                };
+                if ret < 0 {
+                    return Err(std::io::Error::last_os_error());
+                } else if ret == 0 {
+                    return Err(std::io::ErrorKind::UnexpectedEof.into());
+                }
+                // Empty the pipe
+                let mut remaining = ret as usize;
+                while remaining > 0 {
+                    let ret = unsafe {
+                        libc::splice(
+                            pipe[0],
+                            std::ptr::null_mut(),
+                            self.null.as_raw_fd(),
+                            std::ptr::null_mut(),
+                            remaining,
+                            libc::SPLICE_F_MOVE,
+                        )
+                    };
+                    if ret <= 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    remaining -= ret as usize;
+                }
+            }
+            Ok(())
+        })();
+        unsafe {
+            libc::close(pipe[0]);
+            libc::close(pipe[1]);
+        }
+        result
+    }
+}
+
+impl Prober for SendfileProbe {
+    fn probe(&mut self, _operation: &str, metrics: &Metrics, target: &str) -> ProbeResult {
+        let size = self.size as u64;
+        let offset = self.rng.gen_range(0..(self.file_size - size) / 4096 + 1) * 4096;
+
+        // Drop the range from the cache first, so it really gets read
+        unsafe {
+            libc::posix_fadvise(
+                self.file.as_raw_fd(),
+                offset as libc::off_t,
+                size as libc::off_t,
+                libc::POSIX_FADV_DONTNEED,
+            )
+        };
+
+        let start = Instant::now();
+        if let Err(e) = self.send(offset) {
+            metrics.error("sendfile", target);
+            return Err(format!(
+                "Error sending {:?} at offset {}: {}",
+                self.path, offset, e
+            ));
+        }
+        let duration = start.elapsed().as_secs_f64();
+        metrics.observe("sendfile", target, None, duration);
+        metrics.throughput("sendfile", target, size, duration);
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+
+/// Values kept for each target and operation, which can be looked up from
+/// `&str`s without allocating (unlike a map keyed by `(String, String)`),
+/// since that happens after every operation.
+pub struct Series<V>(HashMap<String, HashMap<String, V>>);
+
+impl<V> Default for Series<V> {
+    fn default() -> Series<V> {
+        Series(HashMap::new())
+    }
+}
+
+impl<V: Default> Series<V> {
+    /// Get the value of a target and operation, adding it the first time.
+    pub fn get_or_default(&mut self, target: &str, operation: &str) -> &mut V {
+        if !self
+            .0
+            .get(target)
+            .is_some_and(|ops| ops.contains_key(operation))
+        {
+            self.0
+                .entry(target.to_owned())
+                .or_default()
+                .insert(operation.to_owned(), V::default());
+        }
+        self.0.get_mut(target).unwrap().get_mut(operation).unwrap()
+    }
+}
+
+impl<V> Series<V> {
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str, &V)> {
+        self.0.iter().flat_map(|(target, ops)| {
+            ops.iter()
+                .map(move |(operation, v)| (target.as_str(), operation.as_str(), v))
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&str, &str, &mut V)> {
+        self.0.iter_mut().flat_map(|(target, ops)| {
+            ops.iter_mut()
+                .map(move |(operation, v)| (target.as_str(), operation.as_str(), v))
+        })
+    }
+
+    /// The values of the operations of a target.
+    pub fn target_mut(&mut self, target: &str) -> impl Iterator<Item = (&str, &mut V)> {
+        self.0
+            .get_mut(target)
+            .into_iter()
+            .flat_map(|ops| ops.iter_mut().map(|(o, v)| (o.as_str(), v)))
+    }
+}
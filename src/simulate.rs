@@ -0,0 +1,298 @@
+use rand::Rng;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::parse_duration;
+use crate::metrics::Metrics;
+
+/// Longest latency simulated, in seconds, whatever the profile samples.
+const MAX_LATENCY: f64 = 3600.0;
+
+/// Largest `sigma` of a lognormal distribution, at which the p99 is already
+/// 100,000 times the median.
+const MAX_SIGMA: f64 = 5.0;
+
+/// How the simulated latencies are distributed (in seconds).
+#[derive(Clone, Debug)]
+pub enum Distribution {
+    Constant(f64),
+    Uniform { min: f64, max: f64 },
+    Normal { mean: f64, stddev: f64 },
+    LogNormal { median: f64, sigma: f64 },
+    Exponential { mean: f64 },
+}
+
+impl Distribution {
+    fn sample(&self, rng: &mut impl Rng) -> f64 {
+        // Standard normal, with the Box-Muller transform
+        let mut normal = || {
+            let u1: f64 = 1.0 - rng.gen::<f64>();
+            let u2: f64 = rng.gen();
+            (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+        };
+        let value = match *self {
+            Distribution::Constant(value) => value,
+            Distribution::Uniform { min, max } => min + (max - min) * rng.gen::<f64>(),
+            Distribution::Normal { mean, stddev } => mean + stddev * normal(),
+            Distribution::LogNormal { median, sigma } => median * (sigma * normal()).exp(),
+            Distribution::Exponential { mean } => -mean * (1.0 - rng.gen::<f64>()).ln(),
+        };
+        value.clamp(0.0, MAX_LATENCY)
+    }
+}
+
+/// What latencies and errors to simulate, loaded from a profile file.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    distribution: Distribution,
+    /// Probability that an operation gets `spike_latency` added
+    spike_probability: f64,
+    spike_latency: f64,
+    /// Probability that an operation fails
+    error_rate: f64,
+    /// Probability that a burst of `burst_length` errors starts
+    burst_probability: f64,
+    burst_length: u32,
+}
+
+impl Profile {
+    /// Read a profile.
+    ///
+    /// Only a subset of YAML is supported: one `key: value` per line, and
+    /// comments.
+    pub fn load(filename: &Path) -> Result<Profile, String> {
+        let content = std::fs::read_to_string(filename)
+            .map_err(|e| format!("Can't read {:?}: {}", filename, e))?;
+        let mut values = HashMap::new();
+        for (lineno, line) in content.lines().enumerate() {
+            let line = match line.find(" #") {
+                Some(pos) => &line[..pos],
+                None => line,
+            }
+            .trim();
+            if line.is_empty() || line.starts_with('#') || line == "---" {
+                continue;
+            }
+            match line.split_once(':') {
+                Some((key, value)) => {
+                    let value = value.trim().trim_matches(['"', '\'']);
+                    values.insert(key.trim().to_owned(), value.to_owned());
+                }
+                None => return Err(format!("Invalid line {} in {:?}", lineno + 1, filename)),
+            }
+        }
+        Profile::from_values(values).map_err(|e| format!("Invalid profile {:?}: {}", filename, e))
+    }
+
+    fn from_values(mut values: HashMap<String, String>) -> Result<Profile, String> {
+        let mut take_duration = |key: &str| -> Result<Option<f64>, String> {
+            values
+                .remove(key)
+                .map(|v| {
+                    parse_duration(&v)
+                        .map(|d| d.as_secs_f64())
+                        .ok()
+                        .filter(|&d| d <= MAX_LATENCY)
+                        .ok_or_else(|| format!("invalid {}", key))
+                })
+                .transpose()
+        };
+        let latency = take_duration("latency")?;
+        let stddev = take_duration("stddev")?;
+        let min = take_duration("min")?;
+        let max = take_duration("max")?;
+        let spike_latency = take_duration("spike_latency")?.unwrap_or(1.0);
+
+        let mut take_number = |key: &str| -> Result<Option<f64>, String> {
+            values
+                .remove(key)
+                .map(|v| {
+                    v.parse::<f64>()
+                        .ok()
+                        .filter(|n| *n >= 0.0)
+                        .ok_or_else(|| format!("invalid {}", key))
+                })
+                .transpose()
+        };
+        let sigma = match take_number("sigma")? {
+            Some(s) if s > MAX_SIGMA => return Err("invalid sigma".to_owned()),
+            s => s,
+        };
+        let probability = |value: Option<f64>, key: &str| match value {
+            Some(p) if p > 1.0 => Err(format!("invalid {}", key)),
+            p => Ok(p.unwrap_or(0.0)),
+        };
+        let spike_probability =
+            probability(take_number("spike_probability")?, "spike_probability")?;
+        let error_rate = probability(take_number("error_rate")?, "error_rate")?;
+        let burst_probability =
+            probability(take_number("burst_probability")?, "burst_probability")?;
+        let burst_length = match take_number("burst_length")? {
+            Some(n) if n >= 1.0 && n.fract() == 0.0 => n as u32,
+            Some(_) => return Err("invalid burst_length".to_owned()),
+            None => 10,
+        };
+
+        let name = values
+            .remove("distribution")
+            .unwrap_or_else(|| "lognormal".to_owned());
+        let latency = || latency.ok_or_else(|| format!("{} needs a latency", name));
+        let distribution = match name.as_str() {
+            "constant" => Distribution::Constant(latency()?),
+            "uniform" => match (min, max) {
+                (Some(min), Some(max)) if min <= max => Distribution::Uniform { min, max },
+                _ => return Err("uniform needs a min and a max".to_owned()),
+            },
+            "normal" => Distribution::Normal {
+                mean: latency()?,
+                stddev: stddev.ok_or("normal needs a stddev")?,
+            },
+            "lognormal" => Distribution::LogNormal {
+                median: latency()?,
+                sigma: sigma.unwrap_or(0.5),
+            },
+            "exponential" => Distribution::Exponential { mean: latency()? },
+            _ => return Err(format!("unknown distribution {}", name)),
+        };
+
+        if let Some(key) = values.keys().next() {
+            return Err(format!("unknown key {}", key));
+        }
+        Ok(Profile {
+            distribution,
+            spike_probability,
+            spike_latency,
+            error_rate,
+            burst_probability,
+            burst_length,
+        })
+    }
+}
+
+/// Generates samples for a target from a profile, instead of doing I/O.
+pub struct Simulation {
+    profile: Profile,
+    /// How many more errors to generate in the current burst
+    burst_remaining: u32,
+}
+
+impl Simulation {
+    pub fn new(profile: Profile) -> Simulation {
+        Simulation {
+            profile,
+            burst_remaining: 0,
+        }
+    }
+
+    /// Simulate an operation, taking as long as the sampled latency, and
+    /// return that latency.
+    pub fn run(
+        &mut self,
+        operation: &str,
+        rng: &mut impl Rng,
+        metrics: &Metrics,
+        target: &str,
+    ) -> Result<f64, String> {
+        let profile = &self.profile;
+        if self.burst_remaining == 0 && rng.gen_bool(profile.burst_probability) {
+            self.burst_remaining = profile.burst_length;
+        }
+        if self.burst_remaining > 0 || rng.gen_bool(profile.error_rate) {
+            self.burst_remaining = self.burst_remaining.saturating_sub(1);
+            metrics.error(operation, target);
+            return Err(format!("Simulated {} error", operation));
+        }
+
+        let mut duration = profile.distribution.sample(rng);
+        if rng.gen_bool(profile.spike_probability) {
+            duration = (duration + profile.spike_latency).min(MAX_LATENCY);
+        }
+        std::thread::sleep(Duration::try_from_secs_f64(duration).unwrap_or(Duration::ZERO));
+        metrics.observe(operation, target, None, duration);
+        Ok(duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::{Distribution, Profile, MAX_LATENCY, MAX_SIGMA};
+
+    fn profile(values: &[(&str, &str)]) -> Result<Profile, String> {
+        Profile::from_values(
+            values
+                .iter()
+                .map(|&(k, v)| (k.to_owned(), v.to_owned()))
+                .collect::<HashMap<_, _>>(),
+        )
+    }
+
+    #[test]
+    fn profiles() {
+        let normal = profile(&[
+            ("distribution", "normal"),
+            ("latency", "2ms"),
+            ("stddev", "0.0005"),
+            ("error_rate", "0.01"),
+        ])
+        .unwrap();
+        assert!(matches!(
+            normal.distribution,
+            Distribution::Normal { mean, stddev } if mean == 0.002 && stddev == 0.0005
+        ));
+        assert_eq!(normal.error_rate, 0.01);
+        assert_eq!(normal.burst_length, 10);
+
+        let lognormal = profile(&[("latency", "1ms")]).unwrap();
+        assert!(matches!(
+            lognormal.distribution,
+            Distribution::LogNormal { median, sigma } if median == 0.001 && sigma == 0.5
+        ));
+    }
+
+    #[test]
+    fn bounded_samples() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let extreme = [
+            Distribution::LogNormal {
+                median: MAX_LATENCY,
+                sigma: MAX_SIGMA,
+            },
+            Distribution::Normal {
+                mean: MAX_LATENCY,
+                stddev: MAX_LATENCY,
+            },
+            Distribution::Exponential { mean: MAX_LATENCY },
+        ];
+        for distribution in extreme {
+            for _ in 0..1000 {
+                let value = distribution.sample(&mut rng);
+                assert!((0.0..=MAX_LATENCY).contains(&value), "{}", value);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_profiles() {
+        assert!(profile(&[]).is_err());
+        assert!(profile(&[("distribution", "normal"), ("latency", "1ms")]).is_err());
+        assert!(profile(&[("distribution", "uniform"), ("min", "2"), ("max", "1")]).is_err());
+        assert!(profile(&[("latency", "1ms"), ("error_rate", "1.5")]).is_err());
+        assert!(profile(&[("latency", "1ms"), ("burst_length", "0.5")]).is_err());
+        assert!(profile(&[("latency", "1ms"), ("colour", "blue")]).is_err());
+        assert!(profile(&[("distribution", "zipf"), ("latency", "1ms")]).is_err());
+        assert!(profile(&[("latency", "1ms"), ("sigma", "1e300")]).is_err());
+        assert!(profile(&[
+            ("distribution", "normal"),
+            ("latency", "1ms"),
+            ("stddev", "1e15")
+        ])
+        .is_err());
+        assert!(profile(&[("latency", "1ms"), ("spike_latency", "100y")]).is_err());
+    }
+}
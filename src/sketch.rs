@@ -0,0 +1,183 @@
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{GaugeVec, Opts};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::series::Series;
+
+/// The quantiles which are exported.
+const QUANTILES: &[(&str, f64)] = &[("0.5", 0.5), ("0.9", 0.9), ("0.99", 0.99), ("0.999", 0.999)];
+
+/// Relative accuracy of the quantiles.
+const ACCURACY: f64 = 0.01;
+
+/// How many sketches the window is split into, the oldest one being dropped
+/// as a whole.
+const SLOTS: u32 = 10;
+
+/// Sketch of a distribution of positive values, which gives quantiles
+/// within `ACCURACY` of the true value in bounded memory (like DDSketch):
+/// the values are counted in buckets whose bounds grow exponentially.
+#[derive(Default)]
+struct Sketch {
+    buckets: BTreeMap<i32, u64>,
+    /// Values too small for a bucket (below a nanosecond)
+    zeros: u64,
+    count: u64,
+}
+
+fn gamma() -> f64 {
+    (1.0 + ACCURACY) / (1.0 - ACCURACY)
+}
+
+impl Sketch {
+    fn add(&mut self, value: f64) {
+        if value < 1e-9 {
+            self.zeros += 1;
+        } else {
+            let index = (value.ln() / gamma().ln()).ceil() as i32;
+            *self.buckets.entry(index).or_default() += 1;
+        }
+        self.count += 1;
+    }
+
+    fn merge(&mut self, other: &Sketch) {
+        for (&index, &count) in &other.buckets {
+            *self.buckets.entry(index).or_default() += count;
+        }
+        self.zeros += other.zeros;
+        self.count += other.count;
+    }
+
+    fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        // Same rank as status::quantile()
+        let rank = ((self.count as f64 * q).ceil() as u64).clamp(1, self.count);
+        let mut seen = self.zeros;
+        if seen >= rank {
+            return Some(0.0);
+        }
+        for (&index, &count) in &self.buckets {
+            seen += count;
+            if seen >= rank {
+                let gamma = gamma();
+                return Some(2.0 * gamma.powi(index) / (gamma + 1.0));
+            }
+        }
+        None
+    }
+}
+
+/// Sketches of the recent latency by target and operation, one for each
+/// slot of the window with its start time.
+type Sketches = Series<VecDeque<(Instant, Sketch)>>;
+
+/// Latency quantiles over a rolling window, computed in the exporter for the
+/// consumers which can't compute them from histograms (statsd bridges,
+/// textfiles, the JSON API).
+#[derive(Clone)]
+pub struct QuantileCollector {
+    window: Duration,
+    gauge: GaugeVec,
+    sketches: Arc<Mutex<Sketches>>,
+}
+
+impl QuantileCollector {
+    pub fn new(window: Duration, opts: Opts) -> QuantileCollector {
+        QuantileCollector {
+            window,
+            gauge: GaugeVec::new(opts, &["operation", "target", "quantile"]).unwrap(),
+            sketches: Arc::new(Mutex::new(Series::default())),
+        }
+    }
+
+    pub fn observe(&self, operation: &str, target: &str, duration: f64) {
+        let now = Instant::now();
+        let slot = self.window / SLOTS;
+        let mut sketches = self.sketches.lock().unwrap();
+        let slots = sketches.get_or_default(target, operation);
+        match slots.back_mut() {
+            Some((start, sketch)) if now.duration_since(*start) < slot => sketch.add(duration),
+            _ => {
+                let mut sketch = Sketch::default();
+                sketch.add(duration);
+                slots.push_back((now, sketch));
+            }
+        }
+        Self::expire(slots, now, self.window);
+    }
+
+    fn expire(slots: &mut VecDeque<(Instant, Sketch)>, now: Instant, window: Duration) {
+        while let Some((start, _)) = slots.front() {
+            if now.duration_since(*start) <= window {
+                break;
+            }
+            slots.pop_front();
+        }
+    }
+}
+
+impl Collector for QuantileCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.gauge.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let now = Instant::now();
+        let mut sketches = self.sketches.lock().unwrap();
+        self.gauge.reset();
+        for (target, operation, slots) in sketches.iter_mut() {
+            Self::expire(slots, now, self.window);
+            let mut merged = Sketch::default();
+            for (_, sketch) in slots.iter() {
+                merged.merge(sketch);
+            }
+            for &(label, q) in QUANTILES {
+                if let Some(value) = merged.quantile(q) {
+                    self.gauge
+                        .with_label_values(&[operation, target, label])
+                        .set(value);
+                }
+            }
+        }
+        self.gauge.collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Sketch, ACCURACY};
+
+    #[test]
+    fn quantiles() {
+        let mut sketch = Sketch::default();
+        assert_eq!(sketch.quantile(0.5), None);
+        // 1ms to 1s
+        for i in 1..=1000 {
+            sketch.add(i as f64 * 0.001);
+        }
+        for (q, expected) in [(0.0, 0.001), (0.5, 0.5), (0.99, 0.99), (1.0, 1.0)] {
+            let value = sketch.quantile(q).unwrap();
+            assert!(
+                (value - expected).abs() <= expected * ACCURACY,
+                "q{}: {} != {}",
+                q,
+                value,
+                expected
+            );
+        }
+
+        let mut zeros = Sketch::default();
+        zeros.add(0.0);
+        zeros.add(0.0);
+        zeros.add(0.002);
+        assert_eq!(zeros.quantile(0.5), Some(0.0));
+        sketch.merge(&zeros);
+        assert_eq!(sketch.count, 1003);
+        assert_eq!(sketch.quantile(0.0), Some(0.0));
+    }
+}
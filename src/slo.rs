@@ -0,0 +1,193 @@
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{GaugeVec, Opts};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::series::Series;
+
+/// Windows of the multi-window, multi-burn-rate alerts from the SRE
+/// workbook: page on 1h and 5m above 14.4, or 6h and 30m above 6; open a
+/// ticket on 1d and 2h above 3, or 3d and 6h above 1.
+const WINDOWS: &[(&str, u64)] = &[
+    ("5m", 5),
+    ("30m", 30),
+    ("1h", 60),
+    ("2h", 120),
+    ("6h", 360),
+    ("1d", 1440),
+    ("3d", 4320),
+];
+
+/// Number of good and total operations during each minute.
+type Minutes = VecDeque<(u64, u64, u64)>;
+
+#[derive(Default)]
+struct State {
+    /// Threshold (in seconds) and objective of each target which has an SLO
+    targets: HashMap<String, (f64, f64)>,
+    /// Counts of the recent operations, by target and operation
+    counts: Series<Minutes>,
+}
+
+/// Burn rate of the error budget of a latency objective (e.g. 99% of reads
+/// under 50ms), over the windows of the standard alerting policy. A burn
+/// rate of 1 uses up the budget exactly over the SLO period; 14.4 uses up 2%
+/// of a 30-day budget in an hour.
+#[derive(Clone)]
+pub struct SloCollector {
+    burn_rate: GaugeVec,
+    objective: GaugeVec,
+    descs: Vec<Desc>,
+    state: Arc<Mutex<State>>,
+}
+
+fn minute() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        / 60
+}
+
+impl SloCollector {
+    pub fn new(burn_rate_opts: Opts, objective_opts: Opts) -> SloCollector {
+        let burn_rate = GaugeVec::new(burn_rate_opts, &["operation", "target", "window"]).unwrap();
+        let objective = GaugeVec::new(objective_opts, &["target", "threshold"]).unwrap();
+        let descs = burn_rate
+            .desc()
+            .into_iter()
+            .chain(objective.desc())
+            .cloned()
+            .collect();
+        SloCollector {
+            burn_rate,
+            objective,
+            descs,
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    pub fn add(&self, target: &str, threshold: Duration, objective: f64) {
+        let label = humantime::format_duration(threshold)
+            .to_string()
+            .replace(' ', "");
+        self.objective
+            .with_label_values(&[target, &label])
+            .set(objective);
+        self.state
+            .lock()
+            .unwrap()
+            .targets
+            .insert(target.to_owned(), (threshold.as_secs_f64(), objective));
+    }
+
+    /// Count an operation, good if it succeeded within the threshold.
+    fn count(&self, operation: &str, target: &str, good: bool) {
+        let mut state = self.state.lock().unwrap();
+        if !state.targets.contains_key(target) {
+            return;
+        }
+        let now = minute();
+        let minutes = state.counts.get_or_default(target, operation);
+        match minutes.back_mut() {
+            Some((m, good_count, total)) if *m == now => {
+                *good_count += good as u64;
+                *total += 1;
+            }
+            _ => minutes.push_back((now, good as u64, 1)),
+        }
+        let oldest = now.saturating_sub(WINDOWS[WINDOWS.len() - 1].1);
+        while minutes.front().is_some_and(|(m, _, _)| *m <= oldest) {
+            minutes.pop_front();
+        }
+    }
+
+    /// Count a successful operation.
+    pub fn observe(&self, operation: &str, target: &str, duration: f64) {
+        let threshold = match self.state.lock().unwrap().targets.get(target) {
+            Some(&(threshold, _)) => threshold,
+            None => return,
+        };
+        self.count(operation, target, duration <= threshold);
+    }
+
+    /// Count a failed operation.
+    pub fn error(&self, operation: &str, target: &str) {
+        self.count(operation, target, false);
+    }
+}
+
+impl Collector for SloCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let now = minute();
+        let state = self.state.lock().unwrap();
+        self.burn_rate.reset();
+        for (target, operation, minutes) in state.counts.iter() {
+            let budget = 1.0 - state.targets[target].1;
+            for &(window, length) in WINDOWS {
+                let (good, total) = minutes
+                    .iter()
+                    .filter(|(m, _, _)| *m + length > now)
+                    .fold((0, 0), |(g, t), (_, good, total)| (g + good, t + total));
+                if total > 0 {
+                    let bad = (total - good) as f64 / total as f64;
+                    self.burn_rate
+                        .with_label_values(&[operation, target, window])
+                        .set(bad / budget);
+                }
+            }
+        }
+        let mut families = self.burn_rate.collect();
+        families.extend(self.objective.collect());
+        families
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::core::Collector;
+    use prometheus::Opts;
+    use std::time::Duration;
+
+    use super::{SloCollector, WINDOWS};
+
+    #[test]
+    fn burn_rate() {
+        let slo = SloCollector::new(Opts::new("burn_rate", "h"), Opts::new("objective", "h"));
+        slo.add("data", Duration::from_millis(10), 0.99);
+        for _ in 0..90 {
+            slo.observe("read", "data", 0.001);
+        }
+        for _ in 0..5 {
+            slo.observe("read", "data", 0.05);
+            slo.error("read", "data");
+        }
+        // No objective for this one
+        slo.observe("read", "other", 0.05);
+
+        let families = slo.collect();
+        assert_eq!(families[0].get_name(), "burn_rate");
+        let rates = families[0].get_metric();
+        assert_eq!(rates.len(), WINDOWS.len());
+        for rate in rates {
+            // 10% bad with a 1% budget
+            assert!((rate.get_gauge().get_value() - 10.0).abs() < 1e-9);
+            assert!(rate
+                .get_label()
+                .iter()
+                .any(|l| l.get_name() == "target" && l.get_value() == "data"));
+        }
+        assert_eq!(families[1].get_name(), "objective");
+        assert_eq!(families[1].get_metric()[0].get_gauge().get_value(), 0.99);
+        assert_eq!(
+            families[1].get_metric()[0].get_label()[1].get_value(),
+            "10ms"
+        );
+    }
+}
@@ -0,0 +1,305 @@
+use prometheus::proto::{Bucket, Counter, Histogram, LabelPair, Metric, MetricFamily, MetricType};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::metrics::Metrics;
+
+/// The label names and values of a series, sorted.
+type Labels = Vec<(String, String)>;
+
+fn labels(metric: &Metric) -> Labels {
+    let mut labels: Labels = metric
+        .get_label()
+        .iter()
+        .map(|l| (l.get_name().to_owned(), l.get_value().to_owned()))
+        .collect();
+    labels.sort();
+    labels
+}
+
+/// The value of a counter or histogram series when it was saved.
+enum Saved {
+    Counter(f64),
+    Histogram {
+        count: u64,
+        sum: f64,
+        /// Upper bound and cumulative count of each bucket
+        buckets: Vec<(f64, u64)>,
+    },
+}
+
+struct Family {
+    help: String,
+    series: HashMap<Labels, Saved>,
+}
+
+/// Counters and histograms saved by a previous run, which are added to the
+/// current ones so they carry on from where they were instead of starting
+/// over from 0 (which Prometheus would see as a reset).
+///
+/// There are no `_created` samples to carry over: the exporter doesn't
+/// expose them, so Prometheus only relies on the values never decreasing.
+pub struct State {
+    families: HashMap<String, Family>,
+    /// When the state was saved
+    pub saved: SystemTime,
+}
+
+impl State {
+    /// Load the state saved in a file, keeping only the series whose labels
+    /// still make sense: those of a current target, with the current
+    /// constant labels.
+    pub fn load(
+        path: &Path,
+        targets: &[String],
+        const_labels: &HashMap<String, String>,
+    ) -> Result<State, String> {
+        let content =
+            std::fs::read(path).map_err(|e| format!("Can't read state {:?}: {}", path, e))?;
+        let json: Value = serde_json::from_slice(&content)
+            .map_err(|e| format!("Invalid state {:?}: {}", path, e))?;
+        State::from_json(&json, targets, const_labels)
+            .ok_or_else(|| format!("Invalid state {:?}", path))
+    }
+
+    fn from_json(
+        json: &Value,
+        targets: &[String],
+        const_labels: &HashMap<String, String>,
+    ) -> Option<State> {
+        let saved = UNIX_EPOCH + Duration::from_secs_f64(json["saved"].as_f64()?);
+        let mut families = HashMap::new();
+        for family in json["families"].as_array()? {
+            let mut series = HashMap::new();
+            for s in family["series"].as_array()? {
+                let mut labels: Labels = s["labels"]
+                    .as_object()?
+                    .iter()
+                    .map(|(k, v)| Some((k.clone(), v.as_str()?.to_owned())))
+                    .collect::<Option<_>>()?;
+                labels.sort();
+                let labels_match = const_labels
+                    .iter()
+                    .all(|(k, v)| labels.contains(&(k.clone(), v.clone())));
+                let target_exists = labels
+                    .iter()
+                    .all(|(k, v)| k != "target" || targets.contains(v));
+                if !labels_match || !target_exists {
+                    continue;
+                }
+                let value = match family["type"].as_str()? {
+                    "counter" => Saved::Counter(s["value"].as_f64()?),
+                    "histogram" => Saved::Histogram {
+                        count: s["count"].as_u64()?,
+                        sum: s["sum"].as_f64()?,
+                        buckets: s["buckets"]
+                            .as_array()?
+                            .iter()
+                            .map(|b| Some((b[0].as_f64()?, b[1].as_u64()?)))
+                            .collect::<Option<_>>()?,
+                    },
+                    _ => return None,
+                };
+                series.insert(labels, value);
+            }
+            families.insert(
+                family["name"].as_str()?.to_owned(),
+                Family {
+                    help: family["help"].as_str()?.to_owned(),
+                    series,
+                },
+            );
+        }
+        Some(State { families, saved })
+    }
+
+    /// Add the saved values to the families just gathered, and add the
+    /// series which haven't been created again yet.
+    pub fn apply(&self, families: &mut Vec<MetricFamily>) {
+        for (name, saved) in &self.families {
+            let family = match families.iter_mut().find(|f| f.get_name() == name) {
+                Some(f) => f,
+                None => {
+                    let mut family = MetricFamily::default();
+                    family.set_name(name.clone());
+                    family.set_help(saved.help.clone());
+                    family.set_field_type(match saved.series.values().next() {
+                        Some(Saved::Histogram { .. }) => MetricType::HISTOGRAM,
+                        _ => MetricType::COUNTER,
+                    });
+                    families.push(family);
+                    families.last_mut().unwrap()
+                }
+            };
+            let histogram = family.get_field_type() == MetricType::HISTOGRAM;
+            let mut missing: Vec<_> = saved.series.keys().collect();
+            for metric in family.mut_metric().iter_mut() {
+                let labels = labels(metric);
+                missing.retain(|l| **l != labels);
+                match saved.series.get(&labels) {
+                    Some(Saved::Counter(value)) if !histogram => {
+                        let current = metric.get_counter().get_value();
+                        metric.mut_counter().set_value(current + value);
+                    }
+                    Some(Saved::Histogram {
+                        count,
+                        sum,
+                        buckets,
+                    }) if histogram => {
+                        let h = metric.mut_histogram();
+                        // Buckets changed, can't add them up
+                        if h.get_bucket().len() != buckets.len()
+                            || h.get_bucket()
+                                .iter()
+                                .zip(buckets)
+                                .any(|(b, (bound, _))| b.get_upper_bound() != *bound)
+                        {
+                            continue;
+                        }
+                        h.set_sample_count(h.get_sample_count() + count);
+                        h.set_sample_sum(h.get_sample_sum() + sum);
+                        for (b, (_, c)) in h.mut_bucket().iter_mut().zip(buckets) {
+                            b.set_cumulative_count(b.get_cumulative_count() + c);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            for labels in missing {
+                family
+                    .mut_metric()
+                    .push(saved_metric(labels, &saved.series[labels]));
+            }
+        }
+        families.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+    }
+}
+
+fn saved_metric(labels: &Labels, saved: &Saved) -> Metric {
+    let mut metric = Metric::default();
+    for (name, value) in labels {
+        let mut label = LabelPair::default();
+        label.set_name(name.clone());
+        label.set_value(value.clone());
+        metric.mut_label().push(label);
+    }
+    match saved {
+        Saved::Counter(value) => {
+            let mut counter = Counter::default();
+            counter.set_value(*value);
+            metric.set_counter(counter);
+        }
+        Saved::Histogram {
+            count,
+            sum,
+            buckets,
+        } => {
+            let mut histogram = Histogram::default();
+            histogram.set_sample_count(*count);
+            histogram.set_sample_sum(*sum);
+            for (bound, cumulative) in buckets {
+                let mut bucket = Bucket::default();
+                bucket.set_upper_bound(*bound);
+                bucket.set_cumulative_count(*cumulative);
+                histogram.mut_bucket().push(bucket);
+            }
+            metric.set_histogram(histogram);
+        }
+    }
+    metric
+}
+
+/// Write the counters and histograms to a file, replacing it atomically.
+pub fn save(path: &Path, families: &[MetricFamily]) -> Result<(), String> {
+    let families: Vec<Value> = families
+        .iter()
+        .filter_map(|family| {
+            let kind = match family.get_field_type() {
+                MetricType::COUNTER => "counter",
+                MetricType::HISTOGRAM => "histogram",
+                _ => return None,
+            };
+            let series: Vec<Value> = family
+                .get_metric()
+                .iter()
+                .map(|metric| {
+                    let labels: serde_json::Map<_, _> = labels(metric)
+                        .into_iter()
+                        .map(|(k, v)| (k, v.into()))
+                        .collect();
+                    if kind == "counter" {
+                        json!({
+                            "labels": labels,
+                            "value": metric.get_counter().get_value(),
+                        })
+                    } else {
+                        let h = metric.get_histogram();
+                        let buckets: Vec<Value> = h
+                            .get_bucket()
+                            .iter()
+                            .map(|b| json!([b.get_upper_bound(), b.get_cumulative_count()]))
+                            .collect();
+                        json!({
+                            "labels": labels,
+                            "count": h.get_sample_count(),
+                            "sum": h.get_sample_sum(),
+                            "buckets": buckets,
+                        })
+                    }
+                })
+                .collect();
+            Some(json!({
+                "name": family.get_name(),
+                "help": family.get_help(),
+                "type": kind,
+                "series": series,
+            }))
+        })
+        .collect();
+    let saved = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64();
+    let json = json!({ "saved": saved, "families": families });
+
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    std::fs::write(&temp, serde_json::to_vec(&json).unwrap())
+        .and_then(|()| std::fs::rename(&temp, path))
+        .map_err(|e| format!("Can't write state {:?}: {}", path, e))
+}
+
+/// Block SIGINT and SIGTERM, so they are left for `run()` to handle. This
+/// has to be called before starting any other thread, which inherit the mask.
+pub fn block_signals() -> libc::sigset_t {
+    unsafe {
+        let mut set: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGINT);
+        libc::sigaddset(&mut set, libc::SIGTERM);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+        set
+    }
+}
+
+/// Save the state every `interval`, and when asked to stop.
+pub fn run(path: PathBuf, interval: Duration, signals: libc::sigset_t, metrics: &Metrics) -> ! {
+    let timeout = libc::timespec {
+        tv_sec: interval.as_secs() as libc::time_t,
+        tv_nsec: interval.subsec_nanos() as libc::c_long,
+    };
+    loop {
+        let signal = unsafe { libc::sigtimedwait(&signals, std::ptr::null_mut(), &timeout) };
+        if let Err(e) = metrics.save_state(&path) {
+            warn!("{}", e);
+        }
+        if signal > 0 {
+            info!("Saved state to {:?}, exiting", path);
+            exit(0);
+        }
+    }
+}
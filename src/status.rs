@@ -0,0 +1,207 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many errors we keep in memory for the dashboard.
+const KEEP_ERRORS: usize = 20;
+
+pub struct Sample {
+    pub timestamp: f64,
+    pub offset: u64,
+    pub duration: f64,
+}
+
+pub struct ErrorRecord {
+    pub timestamp: f64,
+    pub target: String,
+    pub message: String,
+}
+
+/// Recent measurements, shared between the probe loop and the HTTP server.
+#[derive(Default)]
+pub struct Status {
+    config: Vec<(String, String)>,
+    keep_samples: usize,
+    targets: Vec<(String, VecDeque<Sample>)>,
+    errors: VecDeque<ErrorRecord>,
+    /// How many rounds of probes failed in a row, by target
+    consecutive_failures: HashMap<String, u32>,
+}
+
+pub type SharedStatus = Arc<Mutex<Status>>;
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64()
+}
+
+/// Get the value at the given quantile, from a sorted list.
+pub fn quantile(sorted: &[f64], q: f64) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let idx = ((sorted.len() as f64 * q).ceil() as usize).clamp(1, sorted.len());
+    Some(sorted[idx - 1])
+}
+
+/// Percentiles of the recent samples, as JSON.
+fn percentiles(samples: &VecDeque<Sample>) -> serde_json::Map<String, serde_json::Value> {
+    let mut sorted: Vec<f64> = samples.iter().map(|s| s.duration).collect();
+    sorted.sort_by(f64::total_cmp);
+    [
+        ("p50", 0.5),
+        ("p90", 0.9),
+        ("p99", 0.99),
+        ("p999", 0.999),
+        ("max", 1.0),
+    ]
+    .iter()
+    .map(|&(name, q)| (name.to_owned(), quantile(&sorted, q).into()))
+    .collect()
+}
+
+impl Status {
+    pub fn new(
+        targets: Vec<String>,
+        config: Vec<(String, String)>,
+        keep_samples: usize,
+    ) -> SharedStatus {
+        let targets = targets
+            .into_iter()
+            .map(|t| (t, VecDeque::with_capacity(keep_samples)))
+            .collect();
+        Arc::new(Mutex::new(Status {
+            config,
+            keep_samples,
+            targets,
+            ..Default::default()
+        }))
+    }
+
+    pub fn record_sample(&mut self, target: &str, offset: u64, duration: f64) {
+        if self.keep_samples == 0 {
+            return;
+        }
+        let samples = match self.targets.iter_mut().find(|(t, _)| t == target) {
+            Some((_, s)) => s,
+            None => return,
+        };
+        if samples.len() == self.keep_samples {
+            samples.pop_front();
+        }
+        samples.push_back(Sample {
+            timestamp: now(),
+            offset,
+            duration,
+        });
+    }
+
+    pub fn record_error(&mut self, target: &str, message: String) {
+        if self.errors.len() == KEEP_ERRORS {
+            self.errors.pop_front();
+        }
+        self.errors.push_back(ErrorRecord {
+            timestamp: now(),
+            target: target.to_owned(),
+            message,
+        });
+    }
+
+    /// Record whether a round of probes of a target failed.
+    pub fn record_round(&mut self, target: &str, failed: bool) {
+        match self.consecutive_failures.get_mut(target) {
+            Some(count) => *count = if failed { *count + 1 } else { 0 },
+            None => {
+                self.consecutive_failures
+                    .insert(target.to_owned(), failed as u32);
+            }
+        }
+    }
+
+    /// How many rounds of probes of a target failed in a row.
+    pub fn consecutive_failures(&self, target: &str) -> u32 {
+        self.consecutive_failures.get(target).copied().unwrap_or(0)
+    }
+
+    /// The latency of the samples of a target taken in the last `window`
+    /// seconds, sorted.
+    pub fn recent_latencies(&self, target: &str, window: f64) -> Vec<f64> {
+        let since = now() - window;
+        let mut latencies: Vec<f64> = self
+            .targets
+            .iter()
+            .filter(|(t, _)| t == target)
+            .flat_map(|(_, samples)| samples.iter())
+            .filter(|s| s.timestamp >= since)
+            .map(|s| s.duration)
+            .collect();
+        latencies.sort_by(f64::total_cmp);
+        latencies
+    }
+
+    /// Summary for the dashboard, as JSON.
+    pub fn to_json(&self) -> serde_json::Value {
+        let targets: Vec<serde_json::Value> = self
+            .targets
+            .iter()
+            .map(|(name, samples)| {
+                serde_json::json!({
+                    "name": name,
+                    "last_sample": samples.back().map(|s| s.timestamp),
+                    "percentiles": percentiles(samples),
+                    "latencies": samples.iter().map(|s| s.duration).collect::<Vec<_>>(),
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "config": self.config.iter()
+                .map(|(k, v)| (k.clone(), serde_json::Value::from(v.as_str())))
+                .collect::<serde_json::Map<_, _>>(),
+            "targets": targets,
+            "errors": self.errors.iter()
+                .map(|e| serde_json::json!({
+                    "timestamp": e.timestamp,
+                    "target": e.target,
+                    "message": e.message,
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    /// The recent percentiles of a target, as JSON.
+    pub fn target_json(&self, target: &str) -> serde_json::Value {
+        let samples = self
+            .targets
+            .iter()
+            .find(|(t, _)| t == target)
+            .map(|(_, s)| s);
+        serde_json::json!({
+            "name": target,
+            "last_sample": samples.and_then(|s| s.back()).map(|s| s.timestamp),
+            "percentiles": samples.map(percentiles),
+        })
+    }
+
+    /// The raw recent samples, as JSON.
+    pub fn samples_json(&self) -> serde_json::Value {
+        self.targets
+            .iter()
+            .map(|(name, samples)| {
+                let samples: Vec<serde_json::Value> = samples
+                    .iter()
+                    .map(|s| {
+                        serde_json::json!({
+                            "timestamp": s.timestamp,
+                            "offset": s.offset,
+                            "duration": s.duration,
+                        })
+                    })
+                    .collect();
+                (name.clone(), serde_json::Value::from(samples))
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+}
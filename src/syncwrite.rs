@@ -0,0 +1,97 @@
+use rand::RngCore;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::direct::Resolve;
+use crate::integrity::BLOCK_SIZE;
+use crate::metrics::Metrics;
+use crate::prober::{ProbeResult, Prober};
+use crate::scratch::{self, ScratchFile};
+
+/// The write-through operations, and the flag their scratch file is opened
+/// with.
+pub const SYNC_WRITE_OPERATIONS: &[(&str, libc::c_int)] = &[
+    ("osync", libc::O_SYNC),
+    ("odsync", libc::O_DSYNC),
+    ("syncrange", 0),
+];
+
+/// Writes a block to a scratch file opened with O_SYNC or O_DSYNC, so that
+/// the write only returns once the data (and with O_SYNC, the metadata) is
+/// stable, instead of writing then calling `fsync()`. Storage stacks can
+/// optimize one path and not the other, and applications use both.
+///
+/// For the syncrange operation, the block is written to the page cache,
+/// and only the writeback of that range with `sync_file_range()` is
+/// measured, which unlike `fsync()` doesn't flush the metadata.
+pub struct SyncWriteProbe {
+    /// The scratch file of each operation
+    files: Vec<(&'static str, ScratchFile)>,
+    block: Vec<u8>,
+}
+
+impl SyncWriteProbe {
+    /// Create the scratch files of the `operations` which are write-through
+    /// operations.
+    pub fn new(
+        scratch_dir: &Path,
+        operations: &[&str],
+        resolve: &Resolve,
+    ) -> std::io::Result<SyncWriteProbe> {
+        let mut files = Vec::new();
+        for &(operation, flag) in SYNC_WRITE_OPERATIONS {
+            if operations.contains(&operation) {
+                let file = scratch::create_with_flags(scratch_dir, operation, flag, resolve)?;
+                files.push((operation, file));
+            }
+        }
+        Ok(SyncWriteProbe {
+            files,
+            block: vec![0; BLOCK_SIZE],
+        })
+    }
+}
+
+impl Prober for SyncWriteProbe {
+    fn probe(&mut self, operation: &str, metrics: &Metrics, target: &str) -> ProbeResult {
+        let scratch = match self.files.iter().find(|(o, _)| *o == operation) {
+            Some((_, scratch)) => scratch,
+            None => return Err(format!("No scratch file for {}", operation)),
+        };
+        // New data every time, so it can't be deduplicated
+        rand::thread_rng().fill_bytes(&mut self.block);
+        let start = Instant::now();
+        if let Err(e) = scratch.file.write_all_at(&self.block, 0) {
+            metrics.error(operation, target);
+            return Err(format!(
+                "Error writing to {:?} with {}: {}",
+                scratch.path, operation, e
+            ));
+        }
+        if operation == "syncrange" {
+            let start = Instant::now();
+            let ret = unsafe {
+                libc::sync_file_range(
+                    scratch.file.as_raw_fd(),
+                    0,
+                    self.block.len() as libc::off64_t,
+                    libc::SYNC_FILE_RANGE_WRITE | libc::SYNC_FILE_RANGE_WAIT_AFTER,
+                )
+            };
+            if ret != 0 {
+                metrics.error(operation, target);
+                return Err(format!(
+                    "Error in sync_file_range of {:?}: {}",
+                    scratch.path,
+                    std::io::Error::last_os_error()
+                ));
+            }
+            metrics.observe(operation, target, None, start.elapsed().as_secs_f64());
+            return Ok(());
+        }
+        metrics.observe(operation, target, None, start.elapsed().as_secs_f64());
+        Ok(())
+    }
+}
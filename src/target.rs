@@ -0,0 +1,1507 @@
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::Rng;
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::os::unix::io::AsRawFd;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Barrier};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+use crate::aio::Aio;
+use crate::coldopen::ColdOpenProbe;
+use crate::command::CommandProbe;
+use crate::config::{TargetConfig, METADATA_OPERATIONS, WRITE_OPERATIONS};
+use crate::control::Control;
+use crate::direct::{from_fd_direct, open_direct, set_direct, AlignedBuffer};
+use crate::dirsync::DirSyncProbe;
+use crate::heartbeat::Heartbeat;
+use crate::integrity::{IntegrityProbe, BLOCK_SIZE};
+use crate::metadata::MetadataProbe;
+use crate::metrics::Metrics;
+use crate::mmap::{MmapProbe, REGION_SIZE};
+use crate::pattern;
+use crate::pool::Pool;
+use crate::prefetch::PrefetchProbe;
+use crate::prober::{self, Prober};
+use crate::process::ThreadUsage;
+use crate::readv::ReadvProbe;
+use crate::replay::Replay;
+use crate::scratch;
+use crate::sendfile::SendfileProbe;
+use crate::simulate::Simulation;
+use crate::status::SharedStatus;
+use crate::syncwrite::{SyncWriteProbe, SYNC_WRITE_OPERATIONS};
+use crate::template;
+use crate::trim::TrimProbe;
+
+/// Exit status when a target failed too many times in a row.
+pub const EXIT_TOO_MANY_FAILURES: i32 = 3;
+
+/// How many cold and repeated reads the cache bypass check times.
+const BYPASS_SAMPLES: usize = 8;
+
+/// Repeated reads faster than this, and than a fifth of cold reads, come
+/// from memory rather than the device.
+const BYPASS_CACHED: Duration = Duration::from_micros(20);
+
+/// Reads of at least this size are done in two steps, to also measure
+/// their time to first byte.
+const FIRST_BYTE_MIN_SIZE: usize = 1 << 20;
+
+/// Random reads from the target file.
+struct ReadProbe {
+    file: File,
+    file_size: u64,
+    buffer: AlignedBuffer,
+    recent_offsets: VecDeque<u64>,
+    /// One buffer per thread for concurrent reads
+    buffers: Vec<AlignedBuffer>,
+    /// What offsets and sizes are multiples of, for direct I/O
+    alignment: u64,
+    /// Unallocated regions of a sparse file, which are read from memory
+    holes: Vec<(u64, u64)>,
+    /// The offsets which can be read, from `ranges()`
+    readable: Vec<(u64, u64)>,
+    /// Set to do the reads through Linux native AIO (--io-engine libaio)
+    aio: Option<Aio>,
+    /// Whether the reads bypass the page cache, which some FUSE daemons
+    /// don't support
+    direct: bool,
+    /// Whether `check_bypass()` ran, after the first successful read
+    bypass_checked: bool,
+}
+
+/// Find the holes in a sparse file, with SEEK_DATA and SEEK_HOLE.
+fn find_holes(file: &File, size: u64) -> Vec<(u64, u64)> {
+    let fd = file.as_raw_fd();
+    let mut holes = Vec::new();
+    let mut position = 0;
+    while position < size {
+        let data = unsafe { libc::lseek(fd, position as libc::off_t, libc::SEEK_DATA) };
+        if data < 0 {
+            // ENXIO: no more data until the end, anything else: can't tell
+            if std::io::Error::last_os_error().raw_os_error() == Some(libc::ENXIO) {
+                holes.push((position, size));
+            }
+            break;
+        }
+        let data = data as u64;
+        if data > position {
+            holes.push((position, data));
+        }
+        let hole = unsafe { libc::lseek(fd, data as libc::off_t, libc::SEEK_HOLE) };
+        if hole < 0 {
+            break;
+        }
+        position = hole as u64;
+    }
+    holes
+}
+
+impl ReadProbe {
+    fn open(config: &TargetConfig, path: &Path, regions: u64) -> Result<ReadProbe, String> {
+        if config.verify_pattern {
+            pattern::initialize(path, &config.resolve).map_err(|e| {
+                format!("Can't write the verification pattern to {:?}: {}", path, e)
+            })?;
+        }
+        // Open file (for direct I/O on UNIX)
+        let mut direct = true;
+        let file = match config.fd {
+            Some(fd) => from_fd_direct(fd)
+                .map_err(|e| format!("Can't use file descriptor {}: {}", fd, e))?,
+            None => match open_direct(path, false, &config.resolve) {
+                Err(e) if config.fuse.is_some() && e.raw_os_error() == Some(libc::EINVAL) => {
+                    warn!(
+                        "{} doesn't support direct I/O on {:?}, reading through the page cache",
+                        config.fuse.as_ref().unwrap(),
+                        path
+                    );
+                    direct = false;
+                    config.resolve.open(path, libc::O_RDONLY, 0)
+                }
+                result => result,
+            }
+            .map_err(|e| format!("Can't open {:?}: {}", path, e))?,
+        };
+        let file_size = file
+            .metadata()
+            .map_err(|e| format!("Can't read file length: {}", e))?
+            .len();
+        if file_size < config.read_size as u64 {
+            return Err(format!("File is too small: {} bytes", file_size));
+        }
+        info!("Opened {:?}, size {}", path, file_size);
+        if regions > file_size / 4096 {
+            return Err(format!("File is too small for {} regions", regions));
+        }
+        let allocate = || {
+            AlignedBuffer::allocate(config.read_size, &config.buffers)
+                .map_err(|e| format!("Can't allocate read buffer: {}", e))
+        };
+        let holes = find_holes(&file, file_size);
+        let mut probe = ReadProbe {
+            file,
+            file_size,
+            buffer: allocate()?,
+            recent_offsets: VecDeque::with_capacity(config.avoid_recent),
+            buffers: (0..config.concurrency.iter().copied().max().unwrap_or(0))
+                .map(|_| allocate())
+                .collect::<Result<_, _>>()?,
+            alignment: 4096,
+            holes,
+            readable: Vec::new(),
+            aio: if config.io_engine == "libaio" {
+                Some(Aio::new().map_err(|e| format!("Can't set up AIO: {}", e))?)
+            } else {
+                None
+            },
+            direct,
+            bypass_checked: false,
+        };
+        if !probe.holes.is_empty() {
+            let size: u64 = probe.holes.iter().map(|(start, end)| end - start).sum();
+            info!(
+                "{:?} is sparse, not reading its {} bytes of holes",
+                path, size
+            );
+            if probe.ranges(config).is_empty() {
+                warn!(
+                    "{:?} has no allocated region large enough for the reads, reading holes too",
+                    path
+                );
+                probe.holes.clear();
+            }
+        }
+        probe.readable = probe.ranges(config);
+        if probe.readable.is_empty() {
+            return Err("No offsets left to read outside of exclude-range".to_owned());
+        }
+        Ok(probe)
+    }
+
+    /// The offsets which can be read, as ranges of (first offset, number of
+    /// blocks), leaving out the excluded ranges.
+    fn ranges(&self, config: &TargetConfig) -> Vec<(u64, u64)> {
+        let len = self.buffer.as_slice().len() as u64;
+        let align = self.alignment;
+        let last = (self.file_size - len) / align * align;
+        let mut excluded = config.exclude_ranges.clone();
+        excluded.extend(&self.holes);
+        excluded.sort();
+        let mut ranges = Vec::new();
+        let mut next = 0;
+        for (start, end) in excluded {
+            // The last offset whose read ends before the excluded range
+            if start >= len {
+                let before = ((start - len) / align * align).min(last);
+                if next <= before {
+                    ranges.push((next, (before - next) / align + 1));
+                }
+            }
+            next = next.max(end.div_ceil(align) * align);
+        }
+        if next <= last {
+            ranges.push((next, (last - next) / align + 1));
+        }
+        ranges
+    }
+
+    /// Pick a random offset, uniformly among the ones which can be read.
+    fn random_offset(ranges: &[(u64, u64)], align: u64, rng: &mut impl Rng) -> u64 {
+        let total: u64 = ranges.iter().map(|&(_, n)| n).sum();
+        let mut index = rng.gen_range(0..total);
+        for &(first, count) in ranges {
+            if index < count {
+                return first + index * align;
+            }
+            index -= count;
+        }
+        unreachable!()
+    }
+
+    /// Pick random offset in the file, away from the recent ones so we don't
+    /// hit the device's cache.
+    fn pick_offset(&mut self, config: &TargetConfig, rng: &mut impl Rng) -> u64 {
+        let mut offset = ReadProbe::random_offset(&self.readable, self.alignment, rng);
+        for _ in 0..100 {
+            if !self
+                .recent_offsets
+                .iter()
+                .any(|&o: &u64| o.abs_diff(offset) < config.avoid_distance)
+            {
+                break;
+            }
+            offset = ReadProbe::random_offset(&self.readable, self.alignment, rng);
+        }
+        if config.avoid_recent > 0 {
+            if self.recent_offsets.len() == config.avoid_recent {
+                self.recent_offsets.pop_front();
+            }
+            self.recent_offsets.push_back(offset);
+        }
+        offset
+    }
+
+    /// Check an offset from a replayed trace, aligning it for direct I/O.
+    fn replay_offset(&self, offset: u64) -> Result<u64, String> {
+        let aligned = offset / self.alignment * self.alignment;
+        if aligned + self.buffer.as_slice().len() as u64 > self.file_size {
+            return Err(format!(
+                "Can't replay a read at offset {}, past the end of the file",
+                offset
+            ));
+        }
+        Ok(aligned)
+    }
+
+    /// Read through the page cache from now on, for FUSE daemons which
+    /// accept direct I/O but then fail the reads.
+    fn disable_direct(&mut self) -> Result<(), String> {
+        set_direct(&self.file, false).map_err(|e| format!("Can't turn off direct I/O: {}", e))?;
+        self.direct = false;
+        Ok(())
+    }
+
+    /// Drop a block from the page cache before reading it, when not using
+    /// direct I/O, so the read still goes to the filesystem.
+    fn uncache(&self, offset: u64) {
+        if !self.direct {
+            self.drop_cache(offset);
+        }
+    }
+
+    fn drop_cache(&self, offset: u64) {
+        let len = self.buffer.as_slice().len() as libc::off_t;
+        unsafe {
+            libc::posix_fadvise(
+                self.file.as_raw_fd(),
+                offset as libc::off_t,
+                len,
+                libc::POSIX_FADV_DONTNEED,
+            )
+        };
+    }
+
+    /// Check whether the reads really bypass the page cache, by comparing
+    /// reads of the same offset over and over with reads of new offsets
+    /// (dropped from the cache first): when O_DIRECT is silently ignored
+    /// (e.g. by some FUSE daemons or overlays), the repeated reads come from
+    /// memory. Returns whether the bypass works and the median latencies of
+    /// repeated and cold reads, or None if a read failed.
+    fn check_bypass(&mut self, rng: &mut impl Rng) -> Option<(bool, Duration, Duration)> {
+        let time_read = |probe: &mut ReadProbe, offset| {
+            let start = Instant::now();
+            probe
+                .file
+                .read_exact_at(probe.buffer.as_mut_slice(), offset)
+                .ok()?;
+            Some(start.elapsed())
+        };
+        let mut cold = Vec::with_capacity(BYPASS_SAMPLES);
+        for _ in 0..BYPASS_SAMPLES {
+            let offset = ReadProbe::random_offset(&self.readable, self.alignment, rng);
+            self.drop_cache(offset);
+            cold.push(time_read(self, offset)?);
+        }
+        // Once to bring it into the cache, if it can be
+        let offset = ReadProbe::random_offset(&self.readable, self.alignment, rng);
+        time_read(self, offset)?;
+        let mut repeated = Vec::with_capacity(BYPASS_SAMPLES);
+        for _ in 0..BYPASS_SAMPLES {
+            repeated.push(time_read(self, offset)?);
+        }
+        cold.sort();
+        repeated.sort();
+        let (cold, repeated) = (cold[BYPASS_SAMPLES / 2], repeated[BYPASS_SAMPLES / 2]);
+        let effective = repeated >= BYPASS_CACHED || repeated * 5 >= cold;
+        Some((effective, repeated, cold))
+    }
+
+    /// Switch to the next larger alignment: the block size of the filesystem,
+    /// then powers of 2 up to 1 MiB. The reads get larger if needed.
+    fn realign(&mut self, config: &TargetConfig) -> Result<(), String> {
+        let block_size = self.file.metadata().map(|m| m.blksize()).unwrap_or(0);
+        let alignment = if block_size > self.alignment {
+            block_size
+        } else {
+            self.alignment * 2
+        };
+        if alignment > 1 << 20 {
+            return Err("Reads fail with EINVAL at any alignment".to_owned());
+        }
+        let size = (config.read_size as u64).div_ceil(alignment) * alignment;
+        if size > self.file_size {
+            return Err(format!(
+                "File is too small for reads aligned to {} bytes",
+                alignment
+            ));
+        }
+        let old_alignment = self.alignment;
+        let allocate = || {
+            AlignedBuffer::allocate(size as usize, &config.buffers)
+                .map_err(|e| format!("Can't allocate read buffer: {}", e))
+        };
+        self.buffer = allocate()?;
+        for buffer in &mut self.buffers {
+            *buffer = allocate()?;
+        }
+        self.alignment = alignment;
+        let readable = self.ranges(config);
+        if readable.is_empty() {
+            self.alignment = old_alignment;
+            return Err(format!(
+                "No offsets left to read outside of exclude-range with an alignment of {} bytes",
+                alignment
+            ));
+        }
+        self.readable = readable;
+        Ok(())
+    }
+}
+
+/// The probers of the registered operations, by operation.
+type Plugins = Vec<(String, Box<dyn Prober>)>;
+
+/// What carries over from one round of probes of a target to the next.
+struct Rounds {
+    operations: Vec<String>,
+    mix: Option<WeightedIndex<u32>>,
+    consecutive_failures: u32,
+    gone_since: Option<Instant>,
+    next_resolve: Instant,
+    /// The target's own random number generator, see `TargetConfig::rng()`
+    rng: StdRng,
+    replay: Option<Replay>,
+    /// Don't start a round before then, to stay under
+    /// `--max-probe-time-fraction`
+    throttled_until: Option<Instant>,
+}
+
+/// Run `round` every `interval`, unless paused, or right away when triggered
+/// through the control API (the schedule then restarts from there).
+pub(crate) fn schedule(
+    control: &Control,
+    interval: Duration,
+    metrics: &Metrics,
+    target: &str,
+    mut round: impl FnMut(),
+) -> ! {
+    let mut deadline = Instant::now();
+    metrics.skipped_cycles(target, 0);
+    let mut triggered = false;
+    loop {
+        // Don't run the probes while paused, unless asked to
+        if !triggered && control.is_paused() {
+            deadline = Instant::now() + interval.max(Duration::from_secs(1));
+            triggered = control.wait_until(deadline);
+            if triggered {
+                deadline = Instant::now();
+            }
+            continue;
+        }
+
+        round();
+
+        // Wait until the next measurement is due. If the probes took more
+        // than the interval, skip the measurements we missed (rather than
+        // silently lowering the rate)
+        deadline += interval;
+        let now = Instant::now();
+        if now > deadline && !interval.is_zero() {
+            let missed = ((now - deadline).as_secs_f64() / interval.as_secs_f64()) as u32 + 1;
+            debug!("Probes overran, skipping {} measurements", missed);
+            metrics.skipped_cycles(target, missed);
+            deadline += interval * missed;
+        }
+        triggered = control.wait_until(deadline);
+        if triggered {
+            deadline = Instant::now();
+        }
+    }
+}
+
+/// A target being measured, with the probes it runs.
+pub struct Target {
+    config: TargetConfig,
+    /// The path read, which changes over time for templates
+    path: PathBuf,
+    regions: u64,
+    read: Option<ReadProbe>,
+    integrity: Option<IntegrityProbe>,
+    mmap: Option<MmapProbe>,
+    prefetch: Option<PrefetchProbe>,
+    sendfile: Option<SendfileProbe>,
+    readv: Option<ReadvProbe>,
+    cold_open: Option<ColdOpenProbe>,
+    command: Option<CommandProbe>,
+    /// Probes of the operations registered by downstream crates
+    plugins: Plugins,
+    dirsync: Option<DirSyncProbe>,
+    sync_write: Option<SyncWriteProbe>,
+    trim: Option<TrimProbe>,
+    metadata: Option<MetadataProbe>,
+    simulation: Option<Simulation>,
+    metrics: Arc<Metrics>,
+    status: SharedStatus,
+    heartbeat: Option<Arc<Heartbeat>>,
+    control: Arc<Control>,
+    failed: bool,
+    /// Whether write probes are being skipped for lack of space
+    low_space: bool,
+    /// Whether the metrics are not exported because the target is gone
+    removed: bool,
+    /// Index of the next concurrency level to measure
+    next_level: usize,
+    /// The trace to replay instead of random probes (--replay), until the
+    /// rounds start
+    replay: Option<Replay>,
+}
+
+impl Target {
+    /// Open the files for a target.
+    pub fn open(
+        config: TargetConfig,
+        regions: u64,
+        metrics: Arc<Metrics>,
+        status: SharedStatus,
+        heartbeat: Option<Arc<Heartbeat>>,
+        control: Arc<Control>,
+    ) -> Result<Target, String> {
+        // Templates are resolved again when retrying
+        let path = match &config.template {
+            Some(template) => template::resolve(template),
+            None => config.path.clone(),
+        };
+
+        // Don't open anything when simulating
+        if let Some(profile) = &config.simulate {
+            for (operation, _) in &config.ops {
+                metrics.init_operation(operation, &config.name);
+            }
+            return Ok(Target {
+                simulation: Some(Simulation::new(profile.clone())),
+                config,
+                path,
+                regions,
+                read: None,
+                integrity: None,
+                mmap: None,
+                prefetch: None,
+                sendfile: None,
+                readv: None,
+                cold_open: None,
+                command: None,
+                plugins: Vec::new(),
+                dirsync: None,
+                sync_write: None,
+                trim: None,
+                metadata: None,
+                metrics,
+                status,
+                heartbeat,
+                control,
+                failed: false,
+                low_space: false,
+                removed: false,
+                next_level: 0,
+                replay: None,
+            });
+        }
+        let read = if config.has_op("read") {
+            if config.verify_pattern {
+                if config.fd.is_some() {
+                    return Err(format!(
+                        "Target {} can't write the verification pattern to a file descriptor",
+                        config.name
+                    ));
+                }
+                metrics.init_verification(&config.name);
+            }
+            metrics.init_operation("read", &config.name);
+            Some(ReadProbe::open(&config, &path, regions)?)
+        } else {
+            None
+        };
+
+        // Set up the prefetch probe, which needs to go through the page cache
+        let prefetch = if config.has_op("prefetch") {
+            if config.fd.is_some() {
+                return Err(format!(
+                    "Target {} can't run prefetch probes on a file descriptor",
+                    config.name
+                ));
+            }
+            metrics.init_operation("prefetch", &config.name);
+            Some(PrefetchProbe::new(
+                &path,
+                config.prefetch_size,
+                &config.resolve,
+                config.rng("prefetch"),
+            )?)
+        } else {
+            None
+        };
+
+        // Set up the sendfile probe, which also goes through the page cache
+        let sendfile = if config.has_op("sendfile") {
+            if config.fd.is_some() {
+                return Err(format!(
+                    "Target {} can't run sendfile probes on a file descriptor",
+                    config.name
+                ));
+            }
+            metrics.init_operation("sendfile", &config.name);
+            Some(SendfileProbe::new(
+                &path,
+                config.sendfile_size,
+                &config.resolve,
+                config.rng("sendfile"),
+            )?)
+        } else {
+            None
+        };
+
+        // Set up the vectored read probe
+        let readv = if config.has_op("readv") {
+            if config.fd.is_some() {
+                return Err(format!(
+                    "Target {} can't run readv probes on a file descriptor",
+                    config.name
+                ));
+            }
+            metrics.init_operation("readv", &config.name);
+            Some(ReadvProbe::new(
+                &path,
+                config.readv_segments,
+                config.read_size,
+                &config.buffers,
+                &config.resolve,
+                config.rng("readv"),
+            )?)
+        } else {
+            None
+        };
+
+        let command = if config.has_op("command") {
+            metrics.init_operation("command", &config.name);
+            Some(CommandProbe::new(&config, &path))
+        } else {
+            None
+        };
+
+        let plugins = Self::open_plugins(&config, &path)?;
+        for (operation, _) in &plugins {
+            metrics.init_operation(operation, &config.name);
+        }
+
+        if config.has_op("access") {
+            metrics.init_operation("access", &config.name);
+        }
+
+        // Set up the cold open probe, which opens the file itself every time
+        let cold_open = if config.has_op("open") {
+            if config.fd.is_some() {
+                return Err(format!(
+                    "Target {} can't run open probes on a file descriptor",
+                    config.name
+                ));
+            }
+            metrics.init_operation("open", &config.name);
+            Some(ColdOpenProbe::new(config.read_size, &config.resolve))
+        } else {
+            None
+        };
+
+        // Make sure the write probes have room
+        if let Some(dir) = &config.scratch_dir {
+            let mut needed = 0;
+            if config.has_op("write") || config.has_op("fsync") {
+                needed += BLOCK_SIZE as u64;
+            }
+            for (operation, _) in SYNC_WRITE_OPERATIONS {
+                if config.has_op(operation) {
+                    needed += BLOCK_SIZE as u64;
+                }
+            }
+            if config.has_op("msync") {
+                needed += REGION_SIZE as u64;
+            }
+            if config.has_op("storm") {
+                needed += (config.storm_files * BLOCK_SIZE) as u64;
+            }
+            if needed > 0 {
+                scratch::prepare(dir, needed, config.scratch_budget, config.scratch_min_free)?;
+            }
+        }
+
+        // Set up the write and fsync probes
+        let integrity = match &config.scratch_dir {
+            Some(dir) if config.has_op("write") || config.has_op("fsync") => {
+                let probe = IntegrityProbe::new(dir, &config.resolve)
+                    .map_err(|e| format!("Can't create scratch file in {:?}: {}", dir, e))?;
+                info!(
+                    "Opened scratch file in {:?} ({})",
+                    probe.path(),
+                    probe.mechanism()
+                );
+                metrics.scratch_mechanism(&config.name, probe.mechanism());
+                if config.has_op("write") {
+                    for operation in ["write", "fsync", "verify"] {
+                        metrics.init_operation(operation, &config.name);
+                    }
+                    metrics.init_corruption(&config.name);
+                } else {
+                    metrics.init_operation("fsync", &config.name);
+                }
+                Some(probe)
+            }
+            _ => None,
+        };
+
+        // Set up the directory fsync probe
+        let dirsync = match &config.scratch_dir {
+            Some(dir) if config.has_op("dirsync") => {
+                let probe = DirSyncProbe::new(dir, &config.resolve)
+                    .map_err(|e| format!("Can't open scratch dir {:?}: {}", dir, e))?;
+                metrics.init_operation("dirsync", &config.name);
+                Some(probe)
+            }
+            _ => None,
+        };
+
+        // Set up the O_SYNC, O_DSYNC and sync_file_range write probes
+        let sync_write = match &config.scratch_dir {
+            Some(dir) if SYNC_WRITE_OPERATIONS.iter().any(|(o, _)| config.has_op(o)) => {
+                let operations: Vec<&str> = config.ops.iter().map(|(o, _)| o.as_str()).collect();
+                let probe = SyncWriteProbe::new(dir, &operations, &config.resolve)
+                    .map_err(|e| format!("Can't create scratch file in {:?}: {}", dir, e))?;
+                for (operation, _) in SYNC_WRITE_OPERATIONS {
+                    if config.has_op(operation) {
+                        metrics.init_operation(operation, &config.name);
+                    }
+                }
+                Some(probe)
+            }
+            _ => None,
+        };
+
+        // Set up the trim probe
+        let trim = match &config.scratch_dir {
+            Some(dir) if config.has_op("trim") => {
+                let probe = TrimProbe::new(dir, &config.resolve)
+                    .map_err(|e| format!("Can't open scratch dir {:?}: {}", dir, e))?;
+                metrics.init_operation("trim", &config.name);
+                Some(probe)
+            }
+            _ => None,
+        };
+
+        // Set up the metadata probes
+        let metadata = match &config.scratch_dir {
+            Some(dir) if METADATA_OPERATIONS.iter().any(|o| config.has_op(o)) => {
+                for operation in METADATA_OPERATIONS {
+                    if config.has_op(operation) {
+                        metrics.init_operation(operation, &config.name);
+                    }
+                }
+                if config.has_op("link") {
+                    metrics.init_operation("unlink", &config.name);
+                }
+                if config.has_op("storm") {
+                    metrics.init_operation("storm_create", &config.name);
+                }
+                Some(MetadataProbe::new(dir, config.storm_files, &config.resolve))
+            }
+            _ => None,
+        };
+
+        // Set up the msync probe
+        let mmap = match &config.scratch_dir {
+            Some(dir) if config.has_op("msync") => {
+                let probe = MmapProbe::new(dir, &config.resolve)
+                    .map_err(|e| format!("Can't map scratch file in {:?}: {}", dir, e))?;
+                info!(
+                    "Mapped scratch file in {:?} ({})",
+                    probe.path(),
+                    probe.mechanism()
+                );
+                metrics.scratch_mechanism(&config.name, probe.mechanism());
+                metrics.init_operation("msync", &config.name);
+                Some(probe)
+            }
+            _ => None,
+        };
+
+        // Load the trace to replay, whose operations must have been set up
+        let replay = match &config.replay {
+            Some(filename) => {
+                let replay = Replay::load(filename)?;
+                if let Some(operation) = replay.operations().find(|o| !config.has_op(o)) {
+                    return Err(format!(
+                        "{:?} replays {} probes, which target {} doesn't have in --ops",
+                        filename, operation, config.name
+                    ));
+                }
+                Some(replay)
+            }
+            None => None,
+        };
+
+        Ok(Target {
+            config,
+            path,
+            regions,
+            read,
+            integrity,
+            mmap,
+            prefetch,
+            sendfile,
+            readv,
+            cold_open,
+            command,
+            plugins,
+            dirsync,
+            sync_write,
+            trim,
+            metadata,
+            simulation: None,
+            metrics,
+            status,
+            heartbeat,
+            control,
+            failed: false,
+            low_space: false,
+            removed: false,
+            next_level: 0,
+            replay,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// Open the files for a target, retrying until it works.
+    pub fn open_with_retry(
+        config: TargetConfig,
+        regions: u64,
+        metrics: Arc<Metrics>,
+        status: SharedStatus,
+        heartbeat: Option<Arc<Heartbeat>>,
+        control: Arc<Control>,
+    ) -> Target {
+        let mut delay = Duration::from_secs(1);
+        loop {
+            std::thread::sleep(delay);
+            match Target::open(
+                config.clone(),
+                regions,
+                metrics.clone(),
+                status.clone(),
+                heartbeat.clone(),
+                control.clone(),
+            ) {
+                Ok(t) => {
+                    info!("Target {} is now available", config.name);
+                    return t;
+                }
+                Err(e) => {
+                    delay = (delay * 2).min(Duration::from_secs(60));
+                    warn!("{}, retrying in {}s", e, delay.as_secs());
+                }
+            }
+        }
+    }
+
+    /// Log a problem and show it on the dashboard.
+    fn report(&mut self, message: String) {
+        self.failed = true;
+        error!("{}", message);
+        self.status
+            .lock()
+            .unwrap()
+            .record_error(&self.config.name, message);
+    }
+
+    /// Record a failed operation.
+    fn error(&mut self, operation: &str, message: String) {
+        self.metrics.error(operation, &self.config.name);
+        self.report(message);
+    }
+
+    /// Run the probes forever.
+    pub fn run(mut self) {
+        let mut rounds = self.rounds();
+        let (control, metrics) = (self.control.clone(), self.metrics.clone());
+        let (target, interval) = (self.config.name.clone(), self.config.interval);
+        schedule(&control, interval, &metrics, &target, || {
+            self.guarded_round(&mut rounds)
+        })
+    }
+
+    /// Run the probes forever on the workers of `pool`, which drops the
+    /// rounds that can't be queued.
+    pub fn run_in(mut self, pool: &Pool) {
+        let mut rounds = self.rounds();
+        let (control, metrics) = (self.control.clone(), self.metrics.clone());
+        let (target, interval) = (self.config.name.clone(), self.config.interval);
+        let index = pool.add(&target, Box::new(move || self.guarded_round(&mut rounds)));
+        schedule(&control, interval, &metrics, &target, || {
+            pool.submit(index);
+        })
+    }
+
+    /// Run `count` rounds of probes back to back, without waiting for the
+    /// interval (to measure what they cost).
+    pub fn run_rounds(&mut self, count: u32) {
+        let mut rounds = self.rounds();
+        for _ in 0..count {
+            self.round(&mut rounds);
+        }
+    }
+
+    /// Run one round of probes, catching a panic in them so that it doesn't
+    /// stop the target (or the exporter).
+    fn guarded_round(&mut self, rounds: &mut Rounds) {
+        if catch_unwind(AssertUnwindSafe(|| self.round(rounds))).is_err() {
+            self.metrics.probe_panicked(&self.config.name);
+        }
+    }
+
+    fn rounds(&mut self) -> Rounds {
+        if self.config.max_probe_time_fraction.is_some() {
+            self.metrics.rounds_throttled(&self.config.name, 0);
+        }
+        Rounds {
+            replay: self.replay.take(),
+            operations: self.config.ops.iter().map(|(o, _)| o.clone()).collect(),
+            mix: self
+                .config
+                .weights()
+                .map(|weights| WeightedIndex::new(weights).unwrap()),
+            consecutive_failures: 0,
+            gone_since: None,
+            next_resolve: Instant::now() + self.config.resolve_interval,
+            rng: self.config.rng("probes"),
+            throttled_until: None,
+        }
+    }
+
+    /// Run one round of probes: all the operations, or one from the mix.
+    fn round(&mut self, rounds: &mut Rounds) {
+        let start = Instant::now();
+        if rounds.throttled_until.is_some_and(|until| start < until) {
+            self.metrics.rounds_throttled(&self.config.name, 1);
+            return;
+        }
+        let rng = &mut rounds.rng;
+        if self.config.template.is_some() && Instant::now() >= rounds.next_resolve {
+            self.resolve();
+            rounds.next_resolve = Instant::now() + self.config.resolve_interval;
+        }
+
+        self.failed = false;
+        match (&mut rounds.replay, &rounds.mix) {
+            // Run the next operation of the trace
+            (Some(replay), _) => {
+                let step = replay.next_step();
+                self.probe(&step.operation, step.offset, rng)
+            }
+            // Run one operation from the mix
+            (None, Some(mix)) => self.probe(&rounds.operations[mix.sample(rng)], None, rng),
+            // Run all the operations
+            (None, None) => {
+                for operation in &rounds.operations {
+                    self.probe(operation, None, rng);
+                }
+            }
+        }
+        // Wait long enough after a round for it to only be that fraction of
+        // the time
+        if let Some(fraction) = self.config.max_probe_time_fraction {
+            let busy = start.elapsed();
+            rounds.throttled_until =
+                Some(Instant::now() + busy.mul_f64((1.0 - fraction) / fraction));
+        }
+        self.metrics.set_success(&self.config.name, !self.failed);
+        self.metrics.set_last_probe(&self.config.name);
+        self.status
+            .lock()
+            .unwrap()
+            .record_round(&self.config.name, self.failed);
+        self.check_stale(&mut rounds.gone_since);
+        if self.failed {
+            rounds.consecutive_failures += 1;
+            if Some(rounds.consecutive_failures) == self.config.max_consecutive_failures {
+                error!(
+                    "Target {} failed {} times in a row, exiting",
+                    self.config.name, rounds.consecutive_failures
+                );
+                std::process::exit(EXIT_TOO_MANY_FAILURES);
+            }
+        } else {
+            rounds.consecutive_failures = 0;
+            if let Some(heartbeat) = &self.heartbeat {
+                heartbeat.beat();
+            }
+        }
+    }
+
+    /// Resolve the path template again, and switch to the new file if it
+    /// changed (keeping the old one if the new one can't be opened yet).
+    fn resolve(&mut self) {
+        let path = template::resolve(self.config.template.as_ref().unwrap());
+        if path == self.path {
+            return;
+        }
+        let old_path = self.path.clone();
+        match self.reopen(&path) {
+            Ok(()) => info!(
+                "Target {} now reads {:?} instead of {:?}",
+                self.config.name, path, old_path
+            ),
+            Err(e) => warn!(
+                "Can't switch target {} to {:?}: {}",
+                self.config.name, path, e
+            ),
+        }
+    }
+
+    /// Open the files read by the probes again, from `path`.
+    fn reopen(&mut self, path: &Path) -> Result<(), String> {
+        let read = self.open_reads(path)?;
+        let prefetch = self.open_prefetch(path)?;
+        let sendfile = self.open_sendfile(path)?;
+        let readv = self.open_readv(path)?;
+        let plugins = Self::open_plugins(&self.config, path)?;
+        self.read = read;
+        self.prefetch = prefetch;
+        self.sendfile = sendfile;
+        self.readv = readv;
+        self.plugins = plugins;
+        if self.command.is_some() {
+            self.command = Some(CommandProbe::new(&self.config, path));
+        }
+        self.path = path.to_owned();
+        Ok(())
+    }
+
+    fn open_reads(&self, path: &Path) -> Result<Option<ReadProbe>, String> {
+        if self.read.is_none() {
+            return Ok(None);
+        }
+        ReadProbe::open(&self.config, path, self.regions).map(Some)
+    }
+
+    fn open_prefetch(&self, path: &Path) -> Result<Option<PrefetchProbe>, String> {
+        if self.prefetch.is_none() {
+            return Ok(None);
+        }
+        PrefetchProbe::new(
+            path,
+            self.config.prefetch_size,
+            &self.config.resolve,
+            self.config.rng("prefetch"),
+        )
+        .map(Some)
+    }
+
+    fn open_sendfile(&self, path: &Path) -> Result<Option<SendfileProbe>, String> {
+        if self.sendfile.is_none() {
+            return Ok(None);
+        }
+        SendfileProbe::new(
+            path,
+            self.config.sendfile_size,
+            &self.config.resolve,
+            self.config.rng("sendfile"),
+        )
+        .map(Some)
+    }
+
+    fn open_readv(&self, path: &Path) -> Result<Option<ReadvProbe>, String> {
+        if self.readv.is_none() {
+            return Ok(None);
+        }
+        ReadvProbe::new(
+            path,
+            self.config.readv_segments,
+            self.config.read_size,
+            &self.config.buffers,
+            &self.config.resolve,
+            self.config.rng("readv"),
+        )
+        .map(Some)
+    }
+
+    fn open_plugins(config: &TargetConfig, path: &Path) -> Result<Plugins, String> {
+        let mut plugins = Vec::new();
+        for (operation, _) in &config.ops {
+            if let Some(factory) = prober::registered(operation) {
+                plugins.push((operation.clone(), factory(path, config)?));
+            }
+        }
+        Ok(plugins)
+    }
+
+    /// Run one of the probes, reading at `offset` if given (from a replayed
+    /// trace) rather than at random.
+    fn probe(&mut self, operation: &str, offset: Option<u64>, rng: &mut impl Rng) {
+        if let Some(simulation) = &mut self.simulation {
+            match simulation.run(operation, rng, &self.metrics, &self.config.name) {
+                Ok(duration) => {
+                    if let Some(timeout) = self.config.timeout {
+                        if duration > timeout.as_secs_f64() {
+                            self.error(
+                                operation,
+                                format!(
+                                    "Simulated {} took {:.3}s, more than the timeout",
+                                    operation, duration
+                                ),
+                            );
+                        }
+                    }
+                }
+                Err(e) => self.report(e),
+            }
+            return;
+        }
+        if WRITE_OPERATIONS.contains(&operation) && !self.has_space(operation) {
+            return;
+        }
+        let usage = ThreadUsage::now();
+        let (metrics, target) = (&self.metrics, self.config.name.as_str());
+        let result = match operation {
+            "read" => {
+                self.probe_read(offset, rng);
+                if !self.config.concurrency.is_empty() {
+                    self.probe_concurrent(rng);
+                }
+                Ok(())
+            }
+            "write" | "fsync" => self
+                .integrity
+                .as_mut()
+                .unwrap()
+                .probe(operation, metrics, target),
+            "chmod" | "chown" | "utime" | "link" | "create" | "storm" => self
+                .metadata
+                .as_mut()
+                .unwrap()
+                .probe(operation, metrics, target),
+            "trim" => self
+                .trim
+                .as_mut()
+                .unwrap()
+                .probe(operation, metrics, target),
+            "dirsync" => self
+                .dirsync
+                .as_mut()
+                .unwrap()
+                .probe(operation, metrics, target),
+            "osync" | "odsync" | "syncrange" => self
+                .sync_write
+                .as_mut()
+                .unwrap()
+                .probe(operation, metrics, target),
+            "prefetch" => self
+                .prefetch
+                .as_mut()
+                .unwrap()
+                .probe(operation, metrics, target),
+            "msync" => self
+                .mmap
+                .as_mut()
+                .unwrap()
+                .probe(operation, metrics, target),
+            "sendfile" => self
+                .sendfile
+                .as_mut()
+                .unwrap()
+                .probe(operation, metrics, target),
+            "readv" => self
+                .readv
+                .as_mut()
+                .unwrap()
+                .probe(operation, metrics, target),
+            "access" => self.probe_access(),
+            "command" => self
+                .command
+                .as_mut()
+                .unwrap()
+                .probe(operation, metrics, target),
+            "open" => self
+                .cold_open
+                .as_mut()
+                .unwrap()
+                .run(&self.path, metrics, target),
+            _ => match self.plugins.iter_mut().find(|(o, _)| o == operation) {
+                Some((_, plugin)) => plugin.probe(operation, metrics, target),
+                None => unreachable!(),
+            },
+        };
+        self.metrics
+            .probe_usage(operation, &self.config.name, &usage, &ThreadUsage::now());
+        if let Err(e) = result {
+            self.report(e);
+        }
+    }
+
+    /// Stop exporting the metrics of the target if it has been gone for
+    /// `stale-after`, and export them again once it is back.
+    fn check_stale(&mut self, gone_since: &mut Option<Instant>) {
+        let stale_after = match self.config.stale_after {
+            Some(d) => d,
+            None => return,
+        };
+        if self.path.exists() {
+            if gone_since.take().is_some() {
+                // It might be a different file now
+                let path = self.path.clone();
+                if let Err(e) = self.reopen(&path) {
+                    warn!("Can't reopen target {}: {}", self.config.name, e);
+                }
+                if self.removed {
+                    info!("Target {} is back", self.config.name);
+                    self.metrics.restore_target(&self.config.name);
+                    self.removed = false;
+                }
+            }
+            return;
+        }
+        let since = *gone_since.get_or_insert_with(Instant::now);
+        if !self.removed && since.elapsed() >= stale_after {
+            warn!(
+                "Target {} is gone, not exporting its metrics until it comes back",
+                self.config.name
+            );
+            self.metrics.remove_target(&self.config.name);
+            self.removed = true;
+        }
+    }
+
+    /// Check that the target can be read with `access()`, which doesn't open
+    /// it but goes to the server for the permissions (e.g. NFS ACCESS).
+    fn probe_access(&mut self) -> Result<(), String> {
+        let path = CString::new(self.path.as_os_str().as_bytes())
+            .map_err(|_| format!("Invalid path {:?}", self.path))?;
+        let start = Instant::now();
+        let ret = unsafe { libc::access(path.as_ptr(), libc::R_OK) };
+        let duration = start.elapsed().as_secs_f64();
+        if ret != 0 {
+            self.metrics.error("access", &self.config.name);
+            return Err(format!(
+                "Error checking access to {:?}: {}",
+                self.path,
+                std::io::Error::last_os_error()
+            ));
+        }
+        self.metrics
+            .observe("access", &self.config.name, None, duration);
+        Ok(())
+    }
+
+    /// Read from several threads at once, using the next concurrency level.
+    fn probe_concurrent(&mut self, rng: &mut impl Rng) {
+        let level = self.config.concurrency[self.next_level];
+        self.next_level = (self.next_level + 1) % self.config.concurrency.len();
+        let read = self.read.as_mut().unwrap();
+        let offsets: Vec<u64> = (0..level)
+            .map(|_| ReadProbe::random_offset(&read.readable, read.alignment, rng))
+            .collect();
+        for &offset in &offsets {
+            read.uncache(offset);
+        }
+
+        let file = &read.file;
+        let barrier = Barrier::new(level);
+        let results: Vec<Result<f64, String>> = std::thread::scope(|scope| {
+            let threads: Vec<_> = read.buffers[..level]
+                .iter_mut()
+                .zip(&offsets)
+                .map(|(buffer, &offset)| {
+                    let barrier = &barrier;
+                    scope.spawn(move || {
+                        barrier.wait();
+                        let start = Instant::now();
+                        file.read_exact_at(buffer.as_mut_slice(), offset)
+                            .map(|()| start.elapsed().as_secs_f64())
+                            .map_err(|e| format!("Error reading at offset {}: {}", offset, e))
+                    })
+                })
+                .collect();
+            threads.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+
+        for (i, result) in results.into_iter().enumerate() {
+            match result {
+                Ok(duration) => {
+                    self.metrics
+                        .observe_concurrent(&self.config.name, level, duration);
+                    if self.config.verify_pattern {
+                        self.verify(offsets[i], Some(i));
+                    }
+                }
+                Err(e) => self.error("read", e),
+            }
+        }
+    }
+
+    /// Check the data read at `offset` against the pattern of the file, in
+    /// the buffer of a concurrent read or the main one.
+    fn verify(&mut self, offset: u64, concurrent: Option<usize>) {
+        let read = self.read.as_ref().unwrap();
+        let buffer = match concurrent {
+            Some(i) => &read.buffers[i],
+            None => &read.buffer,
+        };
+        if let Some(wrong) = pattern::check(buffer.as_slice(), offset) {
+            self.metrics.verification_failed(&self.config.name);
+            self.error(
+                "read",
+                format!(
+                    "Read at offset {} returned wrong data, starting at offset {}",
+                    offset, wrong
+                ),
+            );
+        }
+    }
+
+    /// Check that a write probe won't bring the scratch filesystem below
+    /// scratch-min-free, otherwise record it as skipped.
+    fn has_space(&mut self, operation: &str) -> bool {
+        let dir = self.config.scratch_dir.as_ref().unwrap();
+        let needed = if operation == "msync" {
+            REGION_SIZE
+        } else {
+            BLOCK_SIZE
+        } as u64;
+        match scratch::free_space(dir) {
+            Ok(free) if free < self.config.scratch_min_free + needed => {
+                if !self.low_space {
+                    warn!(
+                        "Only {} bytes free in {:?}, skipping write probes",
+                        free, dir
+                    );
+                    self.low_space = true;
+                }
+                self.metrics
+                    .probe_skipped(operation, &self.config.name, "low_space");
+                false
+            }
+            Ok(_) => {
+                if self.low_space {
+                    info!("Space available in {:?} again", dir);
+                    self.low_space = false;
+                }
+                true
+            }
+            // If statvfs fails, let the probe run and report the problem
+            Err(_) => true,
+        }
+    }
+
+    fn probe_read(&mut self, replayed: Option<u64>, rng: &mut impl Rng) {
+        let read = self.read.as_mut().unwrap();
+        let mut phases = None;
+        let mut first_byte = None;
+        let (offset, result, start) = loop {
+            let offset = match replayed {
+                Some(offset) => match read.replay_offset(offset) {
+                    Ok(offset) => offset,
+                    Err(e) => break (offset, Err(e), Instant::now()),
+                },
+                None => read.pick_offset(&self.config, rng),
+            };
+            read.uncache(offset);
+            let start = Instant::now();
+
+            // Read
+            let result = match &read.aio {
+                Some(aio) => aio
+                    .read(read.file.as_raw_fd(), read.buffer.as_mut_slice(), offset)
+                    .map(|(queue, service)| {
+                        phases = Some([("queue", queue), ("service", service)]);
+                    }),
+                None => read.file.seek(SeekFrom::Start(offset)).and_then(|_| {
+                    let seek = start.elapsed().as_secs_f64();
+                    let transfer = Instant::now();
+                    let buffer = read.buffer.as_mut_slice();
+                    if buffer.len() >= FIRST_BYTE_MIN_SIZE {
+                        // The first block, then the rest
+                        let (first, rest) = buffer.split_at_mut(read.alignment as usize);
+                        read.file.read_exact(first)?;
+                        first_byte = Some(start.elapsed().as_secs_f64());
+                        read.file.read_exact(rest)?;
+                    } else {
+                        read.file.read_exact(buffer)?;
+                    }
+                    phases = Some([("seek", seek), ("read", transfer.elapsed().as_secs_f64())]);
+                    Ok(())
+                }),
+            };
+
+            // The filesystem might need larger alignment for direct I/O,
+            // try to find what it accepts rather than failing forever
+            match result {
+                Err(e)
+                    if e.raw_os_error() == Some(libc::EINVAL)
+                        && read.direct
+                        && self.config.fuse.is_some() =>
+                {
+                    if let Err(e) = read.disable_direct() {
+                        break (offset, Err(e), start);
+                    }
+                    warn!(
+                        "Reads from {} failed with EINVAL, {} doesn't support direct I/O, now reading through the page cache",
+                        self.config.name,
+                        self.config.fuse.as_ref().unwrap()
+                    );
+                }
+                Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+                    if let Err(e) = read.realign(&self.config) {
+                        break (offset, Err(e), start);
+                    }
+                    warn!(
+                        "Reads from {} failed with EINVAL, now using an alignment of {} bytes",
+                        self.config.name, read.alignment
+                    );
+                    self.metrics
+                        .read_alignment(&self.config.name, read.alignment);
+                }
+                result => {
+                    break (
+                        offset,
+                        result.map_err(|e| format!("Error reading at offset {}: {}", offset, e)),
+                        start,
+                    )
+                }
+            }
+        };
+        let file_size = read.file_size;
+        match result {
+            Ok(()) => {
+                let duration = start.elapsed().as_secs_f64();
+                debug!("Latency: {}", duration);
+                self.status
+                    .lock()
+                    .unwrap()
+                    .record_sample(&self.config.name, offset, duration);
+                let region = if self.regions > 0 {
+                    Some(offset * self.regions / file_size)
+                } else {
+                    None
+                };
+                self.metrics
+                    .observe("read", &self.config.name, region, duration);
+                for (phase, duration) in phases.into_iter().flatten() {
+                    self.metrics
+                        .observe_phase("read", &self.config.name, phase, duration);
+                }
+                if let Some(first_byte) = first_byte {
+                    let read = self.read.as_ref().unwrap();
+                    let rest = read.buffer.as_slice().len() as u64 - read.alignment;
+                    self.metrics
+                        .observe_first_byte("read", &self.config.name, first_byte);
+                    if rest > 0 && duration > first_byte {
+                        self.metrics.throughput(
+                            "read",
+                            &self.config.name,
+                            rest,
+                            duration - first_byte,
+                        );
+                    }
+                }
+                if self.config.verify_pattern {
+                    self.verify(offset, None);
+                }
+                let read = self.read.as_mut().unwrap();
+                if !read.bypass_checked {
+                    read.bypass_checked = true;
+                    match read.check_bypass(rng) {
+                        Some((effective, repeated, cold)) => {
+                            if !effective {
+                                warn!(
+                                    "Reads from {} seem to be served from the page cache, even with direct I/O (repeated reads take {:?}, new ones {:?}), the latencies are not those of the storage",
+                                    self.config.name, repeated, cold
+                                );
+                            }
+                            self.metrics.cache_bypass(&self.config.name, effective);
+                        }
+                        None => warn!(
+                            "Reads from {} failed, can't check whether they bypass the page cache",
+                            self.config.name
+                        ),
+                    }
+                }
+                if let Some(timeout) = self.config.timeout {
+                    if duration > timeout.as_secs_f64() {
+                        self.error(
+                            "read",
+                            format!(
+                                "Read at offset {} took {:.3}s, more than the timeout",
+                                offset, duration
+                            ),
+                        );
+                    }
+                }
+            }
+            Err(e) => self.error("read", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::ffi::OsString;
+    use std::fs::OpenOptions;
+    use std::os::unix::fs::FileExt;
+
+    use super::ReadProbe;
+    use crate::config::Config;
+
+    #[test]
+    fn read_ranges() {
+        // 4MiB, with data only in the second MiB
+        let path = std::env::temp_dir().join(format!("fs-latency-ranges-{}", std::process::id()));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        file.write_all_at(&vec![1; 1 << 20], 1 << 20).unwrap();
+        file.set_len(4 << 20).unwrap();
+        file.sync_all().unwrap();
+
+        let config = Config::parse([
+            OsString::from("--exclude-range"),
+            OsString::from("1032KiB-1040KiB"),
+            path.clone().into(),
+        ]);
+        let probe = ReadProbe::open(&config.targets[0], &path, 1);
+        std::fs::remove_file(&path).unwrap();
+        let probe = probe.unwrap();
+        assert_eq!(probe.holes, [(0, 1 << 20), (2 << 20, 4 << 20)]);
+        assert_eq!(probe.readable, [(1 << 20, 2), (1040 << 10, 252)]);
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let offset = ReadProbe::random_offset(&probe.readable, 4096, &mut rng);
+            assert_eq!(offset % 4096, 0);
+            assert!((1 << 20..=(2 << 20) - 4096).contains(&offset));
+            assert!(!(1032 << 10..1040 << 10).contains(&offset));
+        }
+    }
+}
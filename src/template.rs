@@ -0,0 +1,101 @@
+use std::path::PathBuf;
+
+use crate::config::hostname;
+
+/// Whether a path has substitutions, and should be resolved again over time.
+pub fn is_template(path: &str) -> bool {
+    path.contains('%') || path.contains('$')
+}
+
+/// Resolve a path template, replacing `%Y`, `%m`, `%d`, `%H` and `%M` by the
+/// current local date and time, `%%` by `%`, and `$VAR` or `${VAR}` by the
+/// value of the environment variable (`$HOSTNAME` defaults to the hostname).
+pub fn resolve(template: &str) -> PathBuf {
+    let tm = local_time();
+    let mut result = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '%' => match chars.next() {
+                Some('Y') => result.push_str(&format!("{:04}", tm.tm_year + 1900)),
+                Some('m') => result.push_str(&format!("{:02}", tm.tm_mon + 1)),
+                Some('d') => result.push_str(&format!("{:02}", tm.tm_mday)),
+                Some('H') => result.push_str(&format!("{:02}", tm.tm_hour)),
+                Some('M') => result.push_str(&format!("{:02}", tm.tm_min)),
+                Some('%') => result.push('%'),
+                // Leave unknown sequences alone
+                Some(other) => {
+                    result.push('%');
+                    result.push(other);
+                }
+                None => result.push('%'),
+            },
+            '$' => {
+                let mut name = String::new();
+                if chars.peek() == Some(&'{') {
+                    chars.next();
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            break;
+                        }
+                        name.push(c);
+                    }
+                } else {
+                    while let Some(&c) = chars.peek() {
+                        if !(c.is_ascii_alphanumeric() || c == '_') {
+                            break;
+                        }
+                        name.push(c);
+                        chars.next();
+                    }
+                }
+                match std::env::var(&name) {
+                    Ok(value) => result.push_str(&value),
+                    Err(_) if name == "HOSTNAME" => result.push_str(&hostname()),
+                    Err(_) => {}
+                }
+            }
+            c => result.push(c),
+        }
+    }
+    result.into()
+}
+
+fn local_time() -> libc::tm {
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&now, &mut tm) };
+    tm
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{is_template, local_time, resolve};
+
+    #[test]
+    fn substitutions() {
+        assert!(is_template("/data/%Y/file"));
+        assert!(is_template("/data/$HOST/file"));
+        assert!(!is_template("/data/file"));
+
+        std::env::set_var("FS_LATENCY_TEST_VOLUME", "vol1");
+        std::env::remove_var("FS_LATENCY_TEST_UNSET");
+        assert_eq!(
+            resolve(
+                "/mnt/$FS_LATENCY_TEST_VOLUME/${FS_LATENCY_TEST_VOLUME}x/$FS_LATENCY_TEST_UNSET"
+            ),
+            PathBuf::from("/mnt/vol1/vol1x/")
+        );
+        assert_eq!(
+            resolve("/data/100%%/%q/%"),
+            PathBuf::from("/data/100%/%q/%")
+        );
+        let year = local_time().tm_year + 1900;
+        assert_eq!(
+            resolve("/logs/%Y"),
+            PathBuf::from(format!("/logs/{:04}", year))
+        );
+    }
+}
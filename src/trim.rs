@@ -0,0 +1,74 @@
+use rand::Rng;
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::direct::Resolve;
+use crate::metrics::Metrics;
+use crate::prober::{ProbeResult, Prober};
+
+/// `_IOWR('X', 121, struct fstrim_range)`
+const FITRIM: u64 = 0xc0185879;
+
+/// How much of the filesystem is trimmed at a time.
+const TRIM_RANGE: u64 = 16 << 20;
+
+#[repr(C)]
+struct FstrimRange {
+    start: u64,
+    len: u64,
+    minlen: u64,
+}
+
+/// Discards the free blocks in a small range of the scratch directory's
+/// filesystem with `FITRIM`, since discards can stall some SSD-backed volumes
+/// for seconds. Needs CAP_SYS_ADMIN.
+pub struct TrimProbe {
+    dir: File,
+    path: PathBuf,
+}
+
+impl TrimProbe {
+    pub fn new(scratch_dir: &Path, resolve: &Resolve) -> std::io::Result<TrimProbe> {
+        let dir = resolve.open(scratch_dir, libc::O_RDONLY | libc::O_DIRECTORY, 0)?;
+        Ok(TrimProbe {
+            dir,
+            path: scratch_dir.to_owned(),
+        })
+    }
+}
+
+impl Prober for TrimProbe {
+    fn probe(&mut self, _operation: &str, metrics: &Metrics, target: &str) -> ProbeResult {
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstatvfs(self.dir.as_raw_fd(), &mut stat) } != 0 {
+            return Err(format!(
+                "Can't get size of filesystem of {:?}: {}",
+                self.path,
+                std::io::Error::last_os_error()
+            ));
+        }
+        let size = stat.f_blocks * stat.f_frsize;
+        let start = rand::thread_rng().gen_range(0..size.saturating_sub(TRIM_RANGE) + 1);
+        let mut range = FstrimRange {
+            start,
+            len: TRIM_RANGE,
+            minlen: 0,
+        };
+
+        let time = Instant::now();
+        let ret = unsafe { libc::ioctl(self.dir.as_raw_fd(), FITRIM as _, &mut range) };
+        let duration = time.elapsed().as_secs_f64();
+        if ret != 0 {
+            metrics.error("trim", target);
+            return Err(format!(
+                "Error trimming filesystem of {:?}: {}",
+                self.path,
+                std::io::Error::last_os_error()
+            ));
+        }
+        metrics.observe("trim", target, None, duration);
+        Ok(())
+    }
+}
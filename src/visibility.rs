@@ -0,0 +1,87 @@
+use prometheus::{Histogram, HistogramVec};
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, error};
+
+use crate::metrics::Metrics;
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64()
+}
+
+/// Periodically writes a marker with a sequence number and timestamp, for
+/// another instance to read.
+pub fn run_writer(path: PathBuf, interval: Duration, metrics: &Metrics) {
+    let target = path.display().to_string();
+    metrics.init_operation("visibility_write", &target);
+    let mut sequence: u64 = 0;
+    loop {
+        sequence += 1;
+        let result = File::create(&path).and_then(|mut file| {
+            writeln!(file, "{} {}", sequence, now())?;
+            file.sync_all()
+        });
+        if let Err(e) = result {
+            error!("Error writing visibility marker {:?}: {}", path, e);
+            metrics.error("visibility_write", &target);
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+/// Polls a marker written by another instance, and measures how long it
+/// takes for new content to become visible here.
+pub fn run_reader(path: PathBuf, poll: Duration, metrics: &Metrics) {
+    let target = path.display().to_string();
+    let lag = HistogramVec::new(
+        metrics.histogram_opts(
+            "visibility_lag_seconds",
+            "Time between a marker being written by the peer and it being visible here",
+        ),
+        &["target"],
+    )
+    .unwrap();
+    metrics.register(&lag);
+    let lag: Histogram = lag.with_label_values(&[&target]);
+    metrics.init_operation("visibility_read", &target);
+
+    let mut last_sequence = None;
+    loop {
+        match std::fs::read_to_string(&path) {
+            Ok(content) => {
+                let marker = content
+                    .trim()
+                    .split_once(' ')
+                    .and_then(|(s, t)| Some((s.parse::<u64>().ok()?, t.parse::<f64>().ok()?)));
+                match marker {
+                    // First read tells us nothing about when it was written
+                    Some((sequence, _)) if last_sequence.is_none() => {
+                        last_sequence = Some(sequence);
+                    }
+                    Some((sequence, timestamp)) if Some(sequence) != last_sequence => {
+                        last_sequence = Some(sequence);
+                        let duration = (now() - timestamp).max(0.0);
+                        debug!("Visibility lag: {}", duration);
+                        lag.observe(duration);
+                    }
+                    Some(_) => {}
+                    // The peer might be in the middle of writing it
+                    None => debug!("Incomplete visibility marker"),
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && last_sequence.is_none() => {
+                debug!("Waiting for peer to write visibility marker");
+            }
+            Err(e) => {
+                error!("Error reading visibility marker {:?}: {}", path, e);
+                metrics.error("visibility_read", &target);
+            }
+        }
+        std::thread::sleep(poll);
+    }
+}
@@ -0,0 +1,94 @@
+use prometheus::{GaugeVec, HistogramVec};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{debug, error};
+
+use crate::metrics::Metrics;
+
+/// Repeatedly walks a directory tree, like `find` or `du`, stat-ing every
+/// entry, and measures how long it takes.
+pub fn run(
+    path: PathBuf,
+    interval: Duration,
+    max_depth: usize,
+    max_entries: usize,
+    metrics: &Metrics,
+) {
+    let target = path.display().to_string();
+    let latency = HistogramVec::new(
+        metrics.histogram_opts("walk_seconds", "Time taken to walk the directory tree"),
+        &["target"],
+    )
+    .unwrap();
+    metrics.register(&latency);
+    let entries = GaugeVec::new(
+        metrics.opts("walk_entries", "Number of entries found by the last walk"),
+        &["target"],
+    )
+    .unwrap();
+    metrics.register(&entries);
+    let rate = GaugeVec::new(
+        metrics.opts(
+            "walk_entries_per_second",
+            "Number of entries stat-ed per second during the last walk",
+        ),
+        &["target"],
+    )
+    .unwrap();
+    metrics.register(&rate);
+    metrics.init_operation("walk", &target);
+
+    loop {
+        let start = Instant::now();
+        match walk(&path, max_depth, max_entries) {
+            Ok(count) => {
+                let duration = start.elapsed().as_secs_f64();
+                debug!("Walked {} entries in {}", count, duration);
+                latency.with_label_values(&[&target]).observe(duration);
+                entries.with_label_values(&[&target]).set(count as f64);
+                rate.with_label_values(&[&target])
+                    .set(count as f64 / duration);
+            }
+            Err(e) => {
+                error!("Error walking {:?}: {}", path, e);
+                metrics.error("walk", &target);
+            }
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+/// Walk the tree down to `max_depth`, stopping after `max_entries` entries.
+/// Returns the number of entries.
+fn walk(root: &Path, max_depth: usize, max_entries: usize) -> std::io::Result<usize> {
+    let mut count = 0;
+    let mut stack = vec![(root.to_owned(), 0)];
+    while let Some((dir, depth)) = stack.pop() {
+        let list = match std::fs::read_dir(&dir) {
+            Ok(l) => l,
+            // Only fail if the root can't be read
+            Err(e) if depth == 0 => return Err(e),
+            Err(e) => {
+                debug!("Can't read {:?}: {}", dir, e);
+                continue;
+            }
+        };
+        for entry in list {
+            let entry = entry?;
+            let metadata = match entry.metadata() {
+                Ok(m) => m,
+                // Removed during the walk
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            count += 1;
+            if count >= max_entries {
+                return Ok(count);
+            }
+            if metadata.is_dir() && depth < max_depth {
+                stack.push((entry.path(), depth + 1));
+            }
+        }
+    }
+    Ok(count)
+}